@@ -0,0 +1,380 @@
+//! Manual adjustments and overrides read from `data/ref/adjustments.toml`.
+//!
+//! Events inject transfers no export records: opening balances, off-chain
+//! purchases, lost funds or corrections. Overrides replace what the pipeline
+//! derived for one `transfer_id`: the USD value of its transfers, its category,
+//! or the lots its disposals consume. Events and USD values are applied to the
+//! transfers after ingestion, before they are grouped and classified.
+//! Categories are configured for classification like the hashes of
+//! `data/ref/categories.toml`, taking precedence over them, and exempt the
+//! transaction from the spam filter. Lot assignments are read by the lot
+//! engine. Each adjustment applied is returned as an [`AdjustmentRecord`] for
+//! the outputs.
+
+use crate::category::{classify, configured_category, CategoryConfig, CategoryMapping, CATEGORY_NAMES};
+use crate::ingest::exchange::exchange_token;
+use crate::ingest::DATETIME_FORMAT;
+use crate::{Chain, Token, Transaction, Transfer, TransferDirection};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Optional file of adjustments.
+pub const ADJUSTMENT_FILE: &str = "data/ref/adjustments.toml";
+
+/// Counterparty of injected transfers when none is given.
+const DEFAULT_COUNTERPARTY: &str = "adjustment";
+
+#[derive(Debug, Deserialize, Clone)]
+/// Transfer injected by hand.
+pub struct Event {
+  /// Identifier of the transaction; events sharing it are grouped together.
+  pub transfer_id: String,
+  /// UTC timestamp, `YYYY-MM-DD HH:MM:SS`.
+  pub datetime: String,
+  /// Exchange or other off-chain account; the analyzed address on `chain` when absent.
+  pub account: Option<String>,
+  /// Chain of an on-chain event; Arbitrum when absent.
+  #[serde(default)]
+  pub chain: Chain,
+  /// Token contract of an on-chain event; native ETH when absent.
+  pub token_address: Option<String>,
+  /// Asset held in an off-chain `account`, e.g. `BTC`.
+  pub symbol: Option<String>,
+  pub direction: TransferDirection,
+  pub value: Decimal,
+  pub usd_value: Option<Decimal>,
+  /// Other side of the transfer, `adjustment` when absent.
+  pub counterparty: Option<String>,
+  /// Category of the event's transaction, as named in `data/ref/categories.toml`.
+  pub category: Option<String>,
+  pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Replacement of fields derived for a transaction.
+pub struct Override {
+  pub transfer_id: String,
+  /// Limits `usd_value` to the transfers of the token with this symbol.
+  pub symbol: Option<String>,
+  /// USD value of each matching transfer.
+  pub usd_value: Option<Decimal>,
+  /// Category of the transaction, as named in `data/ref/categories.toml`.
+  pub category: Option<String>,
+  /// `transfer_id`s of the acquisitions whose lots the transaction's disposals
+  /// consume first, before falling back to the oldest.
+  #[serde(default)]
+  pub lots: Vec<String>,
+  pub note: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+/// Contents of `data/ref/adjustments.toml`.
+pub struct Adjustments {
+  #[serde(default, rename = "event")]
+  pub events: Vec<Event>,
+  #[serde(default, rename = "override")]
+  pub overrides: Vec<Override>,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Adjustment applied, as listed in `adjustments.csv`.
+pub struct AdjustmentRecord {
+  pub transfer_id: String,
+  /// `event`, `usd_value`, `category` or `lots`.
+  pub kind: String,
+  /// Value derived by the pipeline, empty for events.
+  pub original: String,
+  pub adjusted: String,
+  pub note: String,
+}
+
+/// Loads `data/ref/adjustments.toml`, or no adjustments when it is absent.
+pub fn read_adjustments() -> Result<Adjustments, Box<dyn Error>> {
+    let path = Path::new(ADJUSTMENT_FILE);
+    if !path.exists() {
+      return Ok(Adjustments::default());
+    }
+    let toml_str = fs::read_to_string(path).map_err(|e| format!("{}: {}", ADJUSTMENT_FILE, e))?;
+    Ok(toml::de::from_str(&toml_str).map_err(|e| format!("{}: {}", ADJUSTMENT_FILE, e))?)
+}
+
+/// Checks that a category can be configured.
+fn check_category(transfer_id: &str, category: &str) -> Result<(), Box<dyn Error>> {
+    match CATEGORY_NAMES.contains(&category) {
+      true => Ok(()),
+      false => Err(format!("{}: unknown category {} in {}", transfer_id, category, ADJUSTMENT_FILE).into()),
+    }
+}
+
+/// Converts an event and the analyzed address into a [`Transfer`].
+impl TryFrom<(&str, &Event)> for Transfer {
+    type Error = Box<dyn Error>;
+
+    fn try_from((address, event): (&str, &Event)) -> Result<Self, Self::Error> {
+      NaiveDateTime::parse_from_str(&event.datetime, DATETIME_FORMAT)
+        .map_err(|_| format!("{}: invalid datetime {}", event.transfer_id, event.datetime))?;
+
+      let (token, account, chain) = match &event.account {
+        Some(account) => {
+          let symbol = event.symbol.as_ref().ok_or(format!("{}: off-chain event without a symbol", event.transfer_id))?;
+          (exchange_token(account, symbol), account.clone(), Chain::Offchain)
+        },
+        None => {
+          let token = match &event.token_address {
+            Some(contract) => Token::from((event.chain, contract)),
+            None => Token::eth(event.chain),
+          };
+          (token, address.to_lowercase(), event.chain)
+        },
+      };
+
+      Ok(Transfer {
+        transfer_id: event.transfer_id.clone(),
+        datetime: event.datetime.clone(),
+        usd_value: token.stable_usd_value.map(|x| x * event.value).or(event.usd_value),
+        token,
+        value: event.value,
+        direction: event.direction.clone(),
+        counterparty: vec![event.counterparty.clone().unwrap_or_else(|| String::from(DEFAULT_COUNTERPARTY))],
+        account,
+        chain,
+      })
+    }
+}
+
+impl Adjustments {
+  /// Acquisitions whose lots each transaction consumes first, by `transfer_id`.
+  pub fn lot_assignments(&self) -> HashMap<String, Vec<String>> {
+    self
+      .overrides
+      .iter()
+      .filter(|x| !x.lots.is_empty())
+      .map(|x| (x.transfer_id.clone(), x.lots.clone()))
+      .collect()
+  }
+
+  /// Adds the events to the transfers of `address` and overrides the USD value
+  /// of the matching transfers.
+  pub fn apply_to_transfers(&self, address: &str, transfers: &mut Vec<Transfer>) -> Result<Vec<AdjustmentRecord>, Box<dyn Error>> {
+    let mut records = vec![];
+    for event in &self.events {
+      let transfer = Transfer::try_from((address, event))?;
+      let sign = if transfer.direction == TransferDirection::Incoming { "+" } else { "-" };
+      records.push(AdjustmentRecord {
+        transfer_id: event.transfer_id.clone(),
+        kind: String::from("event"),
+        original: String::new(),
+        adjusted: format!("{}{} {} on {}", sign, transfer.value.normalize(), transfer.token.symbol, transfer.account),
+        note: event.note.clone().unwrap_or_default(),
+      });
+      transfers.push(transfer);
+    }
+
+    for adjustment in self.overrides.iter() {
+      let Some(usd_value) = adjustment.usd_value else { continue };
+      let matching = transfers.iter_mut().filter(|x| {
+        x.transfer_id == adjustment.transfer_id && adjustment.symbol.as_ref().is_none_or(|symbol| &x.token.symbol == symbol)
+      });
+      for transfer in matching {
+        records.push(AdjustmentRecord {
+          transfer_id: adjustment.transfer_id.clone(),
+          kind: String::from("usd_value"),
+          original: format!("{}: {}", transfer.token.symbol, transfer.usd_value.map(|x| x.to_string()).unwrap_or_default()),
+          adjusted: format!("{}: {}", transfer.token.symbol, usd_value),
+          note: adjustment.note.clone().unwrap_or_default(),
+        });
+        transfer.usd_value = Some(usd_value);
+      }
+    }
+    Ok(records)
+  }
+
+  /// Categories of the events and overrides, keyed by `transfer_id` like the
+  /// hashes of `data/ref/categories.toml`.
+  pub fn category_config(&self) -> Result<CategoryConfig, Box<dyn Error>> {
+    let categories = self
+      .events
+      .iter()
+      .filter_map(|x| Some((&x.transfer_id, x.category.as_ref()?)))
+      .chain(self.overrides.iter().filter_map(|x| Some((&x.transfer_id, x.category.as_ref()?))));
+    let mut config = CategoryConfig::new();
+    for (transfer_id, category) in categories {
+      check_category(transfer_id, category)?;
+      config.insert(transfer_id.clone(), CategoryMapping { category: category.clone(), description: None });
+    }
+    Ok(config)
+  }
+
+  /// Lists the category and lot adjustments of the classified transactions.
+  /// The original category is the one `config` (`data/ref/categories.toml`
+  /// without the adjustments) and the heuristics give.
+  pub fn transaction_records(&self, transactions: &[Transaction], config: &CategoryConfig) -> Vec<AdjustmentRecord> {
+    let note = |note: &Option<String>| note.clone().unwrap_or_default();
+    let mut category_notes: HashMap<&String, &Option<String>> = HashMap::new();
+    for event in self.events.iter().filter(|x| x.category.is_some()) {
+      category_notes.insert(&event.transfer_id, &event.note);
+    }
+    for adjustment in self.overrides.iter().filter(|x| x.category.is_some()) {
+      category_notes.insert(&adjustment.transfer_id, &adjustment.note);
+    }
+    let lots = self.lot_assignments();
+
+    let mut records = vec![];
+    for transaction in transactions {
+      if let Some(category_note) = category_notes.get(&transaction.transfer_id) {
+        let transfers = &transaction.net_transfers;
+        let original = classify(configured_category(transfers, config).as_deref(), transfers);
+        records.push(AdjustmentRecord {
+          transfer_id: transaction.transfer_id.clone(),
+          kind: String::from("category"),
+          original: original.name().to_string(),
+          adjusted: transaction.category.name().to_string(),
+          note: note(category_note),
+        });
+      }
+      if let Some(assigned) = lots.get(&transaction.transfer_id) {
+        let adjustment = self.overrides.iter().find(|x| x.transfer_id == transaction.transfer_id && !x.lots.is_empty());
+        records.push(AdjustmentRecord {
+          transfer_id: transaction.transfer_id.clone(),
+          kind: String::from("lots"),
+          original: String::from("FIFO"),
+          adjusted: assigned.join(" "),
+          note: adjustment.map(|x| note(&x.note)).unwrap_or_default(),
+        });
+      }
+    }
+    records
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::transaction::{classify_transactions, group_transfers};
+  use crate::TransactionCategory;
+
+  fn event(transfer_id: &str, account: Option<&str>, symbol: Option<&str>) -> Event {
+    Event {
+      transfer_id: transfer_id.to_string(),
+      datetime: String::from("2024-01-01 00:00:00"),
+      account: account.map(String::from),
+      chain: Chain::Arbitrum,
+      token_address: None,
+      symbol: symbol.map(String::from),
+      direction: TransferDirection::Incoming,
+      value: Decimal::from(2),
+      usd_value: Some(Decimal::from(5000)),
+      counterparty: None,
+      category: None,
+      note: Some(String::from("opening balance")),
+    }
+  }
+
+  fn adjustment(transfer_id: &str) -> Override {
+    Override {
+      transfer_id: transfer_id.to_string(),
+      symbol: None,
+      usd_value: None,
+      category: None,
+      lots: vec![],
+      note: None,
+    }
+  }
+
+  fn transfer(transfer_id: &str, symbol: &str, direction: TransferDirection) -> Transfer {
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: String::from("2024-01-02 00:00:00"),
+      token: Token { asset: symbol.to_string(), symbol: symbol.to_string(), address: format!("0x{}", symbol), ..Default::default() },
+      value: Decimal::ONE,
+      usd_value: Some(Decimal::from(100)),
+      direction,
+      counterparty: vec![String::from("0xrouter")],
+      account: String::from("0xwallet"),
+      chain: Chain::Arbitrum,
+    }
+  }
+
+  #[test]
+  fn events_are_injected_as_transfers() {
+    let adjustments = Adjustments {
+      events: vec![event("opening", None, None), event("btc", Some("kraken"), Some("BTC"))],
+      overrides: vec![],
+    };
+    let mut transfers = vec![];
+    let records = adjustments.apply_to_transfers("0xWallet", &mut transfers).unwrap();
+
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(transfers[0].token.symbol, "ETH");
+    assert_eq!(transfers[0].account, "0xwallet");
+    assert_eq!(transfers[0].counterparty, vec![String::from(DEFAULT_COUNTERPARTY)]);
+    assert_eq!(transfers[1].token.address, "kraken:BTC");
+    assert_eq!(transfers[1].chain, Chain::Offchain);
+    assert_eq!(records[0].kind, "event");
+    assert_eq!(records[0].adjusted, "+2 ETH on 0xwallet");
+    assert_eq!(records[1].note, "opening balance");
+  }
+
+  #[test]
+  fn invalid_events_are_errors() {
+    let adjustments = Adjustments { events: vec![event("btc", Some("kraken"), None)], overrides: vec![] };
+    assert!(adjustments.apply_to_transfers("0xwallet", &mut vec![]).is_err());
+
+    let undated = Event { datetime: String::from("2024-01-01"), ..event("opening", None, None) };
+    let adjustments = Adjustments { events: vec![undated], overrides: vec![] };
+    assert!(adjustments.apply_to_transfers("0xwallet", &mut vec![]).is_err());
+  }
+
+  #[test]
+  fn usd_values_are_overridden_for_the_symbol() {
+    let adjustments = Adjustments {
+      events: vec![],
+      overrides: vec![Override { symbol: Some(String::from("ARB")), usd_value: Some(Decimal::from(80)), ..adjustment("swap") }],
+    };
+    let mut transfers = vec![
+      transfer("swap", "ARB", TransferDirection::Outgoing),
+      transfer("swap", "USDC", TransferDirection::Incoming),
+      transfer("other", "ARB", TransferDirection::Incoming),
+    ];
+    let records = adjustments.apply_to_transfers("0xwallet", &mut transfers).unwrap();
+
+    let usd_values: Vec<Option<Decimal>> = transfers.iter().map(|x| x.usd_value).collect();
+    assert_eq!(usd_values, [Some(Decimal::from(80)), Some(Decimal::from(100)), Some(Decimal::from(100))]);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].original, "ARB: 100");
+    assert_eq!(records[0].adjusted, "ARB: 80");
+  }
+
+  #[test]
+  fn unknown_categories_are_errors() {
+    let adjustments = Adjustments {
+      events: vec![],
+      overrides: vec![Override { category: Some(String::from("Gift")), ..adjustment("swap") }],
+    };
+    let error = adjustments.category_config().unwrap_err().to_string();
+    assert!(error.contains("unknown category Gift"));
+  }
+
+  #[test]
+  fn categories_are_used_by_classification() {
+    let adjustments = Adjustments {
+      events: vec![],
+      overrides: vec![Override { category: Some(String::from("Income")), note: Some(String::from("bonus")), ..adjustment("swap") }],
+    };
+    let transfers = vec![transfer("swap", "ARB", TransferDirection::Incoming), transfer("other", "ARB", TransferDirection::Incoming)];
+    let transactions = classify_transactions(group_transfers(transfers), &adjustments.category_config().unwrap());
+
+    let categories: Vec<(&str, &TransactionCategory)> = transactions.iter().map(|x| (x.transfer_id.as_str(), &x.category)).collect();
+    assert_eq!(categories, [("other", &TransactionCategory::Unknown), ("swap", &TransactionCategory::Income)]);
+
+    let records = adjustments.transaction_records(&transactions, &CategoryConfig::new());
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].original.as_str(), records[0].adjusted.as_str()), ("Unknown", "Income"));
+    assert_eq!(records[0].note, "bonus");
+  }
+}
@@ -1,40 +1,506 @@
 //! Command line interface for converting raw CSV exports into normalized transactions.
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
+use log::info;
+use serde::Serialize;
 use std::error::Error;
+use std::collections::HashSet;
+use std::path::Path;
+use rust_decimal::Decimal;
 use arb_portfolio::{
-  read_tokens, write_csv, Transaction, Transfer,
-  //read_transactions,
-  read_internals,
+  read_etherscan, read_etherscan_dir, read_custom, read_sources, read_exchange_dir, link_transfers,
+  read_api_dir, read_api_fees_dir, read_csv, read_fees_dir, read_fees, read_rpc_dir, write_csv, write_json, write_ndjson, Chain, Fee, Transaction, Transfer,
+};
+use arb_portfolio::bridge::match_bridges;
+use arb_portfolio::category::{bridge_counterparties, read_category_config};
+use arb_portfolio::spam::{filter_spam, SpamReason, SpamTransfer};
+use arb_portfolio::review::{merge_review, review_rows, ReviewRow};
+use arb_portfolio::adjustment::read_adjustments;
+use arb_portfolio::dedup::{dedup, Sources};
+use arb_portfolio::store::{stamp, Store};
+use arb_portfolio::transaction::{classify_transactions, group_transfers};
+use arb_portfolio::portfolio::Portfolio;
+use arb_portfolio::price::PriceStore;
+use arb_portfolio::nav::{nav, write_nav_csv};
+use arb_portfolio::performance::{report, Performance, Period};
+use arb_portfolio::gas::{gas_report, GasSummary, GmxSummary};
+use arb_portfolio::income::{income_report, IncomeSummary};
+use arb_portfolio::ingest::gmx::read_gmx_trades;
+use arb_portfolio::slippage::{router_executions, swap_executions, RouterExecution, SwapExecution, DEFAULT_THRESHOLD_BPS};
+use arb_portfolio::sqlite::write_sqlite;
+use arb_portfolio::export::plaintext::{write_plaintext, PlainText};
+use arb_portfolio::export::form8949::{form_8949, schedule_d};
+use arb_portfolio::export::html::write_html;
+use arb_portfolio::export::tax_tools::{entries, CoinLedgerRow, CoinTrackerRow, KoinlyRow};
+use arb_portfolio::schema::{
+  PortfolioRecord, PortfolioRow, TransactionRecord, TransactionRow, TransferRecord, TransferRow,
 };
-use arb_portfolio::transaction::ToTransaction;
 
 /// Command line arguments for the backend tool
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
-    /// Arbitrum address
+    /// Wallet address to analyze, on every chain; not needed by `review import`
+    #[arg(long)]
+    address: Option<String>,
+    /// Format of the output files
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Also export the full ledger into this SQLite database, replacing it
+    #[arg(long)]
+    sqlite: Option<String>,
+    /// Also write a self-contained HTML report
+    #[arg(long)]
+    html: Option<String>,
+    /// Also export the classified transactions as a Beancount journal
+    #[arg(long)]
+    beancount: Option<String>,
+    /// Also export the classified transactions as a ledger-cli journal
+    #[arg(long)]
+    ledger: Option<String>,
+    /// Also export realised disposals as IRS Form 8949 lines (CSV)
+    #[arg(long)]
+    form8949: Option<String>,
+    /// Also export the Schedule D totals of those lines (CSV)
+    #[arg(long)]
+    schedule_d: Option<String>,
+    /// Also export the transactions as a Koinly universal CSV
     #[arg(long)]
-    address: String,
+    koinly: Option<String>,
+    /// Also export the transactions as a CoinTracker CSV
+    #[arg(long)]
+    cointracker: Option<String>,
+    /// Also export the transactions as a CoinLedger universal CSV
+    #[arg(long)]
+    coinledger: Option<String>,
+    /// Persistent store remembering ingested transfers and classifications
+    #[arg(long)]
+    store: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Reports printed instead of writing the outputs.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Time- and money-weighted returns, drawdown and volatility of the
+    /// portfolio and of each asset
+    Performance {
+      /// Periods ending on the last day of the NAV series: all, ytd, <n>d, <n>m or <n>y
+      #[arg(long = "period", default_values_t = [Period::All])]
+      periods: Vec<Period>,
+      /// Annual risk-free rate for the Sharpe ratio, e.g. 0.04
+      #[arg(long, default_value_t = 0.0)]
+      risk_free: f64,
+    },
+    /// Gas spent by month, category and protocol, and GMX execution fees and
+    /// price impact by month and order size
+    Fees,
+    /// Airdrops and other income at fair market value, by month and token
+    Income,
+    /// Tokens ignored as spam and not yet confirmed, as entries to review and
+    /// paste into data/ref/tokens.toml
+    Spam,
+    /// Review of the Unknown and SwapUnknown transactions
+    Review {
+      #[command(subcommand)]
+      action: ReviewAction,
+    },
+    /// Slippage of two-asset swaps against the day's reference prices, per
+    /// swap and per router
+    Slippage {
+      /// Slippage in basis points above which swaps are flagged
+      #[arg(long, default_value_t = DEFAULT_THRESHOLD_BPS)]
+      threshold_bps: u32,
+      /// Only print the flagged swaps
+      #[arg(long)]
+      outliers: bool,
+    },
+}
+
+/// Steps of the review of unclassified transactions.
+#[derive(Subcommand, Debug)]
+enum ReviewAction {
+    /// Writes the transactions to review, with a suggested category, as a CSV
+    Export {
+      file: String,
+    },
+    /// Appends the categories filled in a review CSV to data/ref/categories.toml
+    Import {
+      file: String,
+    },
+}
+
+/// Output file formats.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// Headed CSV with flat token columns
+    Csv,
+    /// Pretty-printed JSON arrays including net transfers and token metadata
+    Json,
+    /// Newline-delimited JSON, one record per line
+    Ndjson,
+}
+
+/// Writes `records` to `<name>.<format>` in the repository root.
+fn write_records<T: Serialize + Default>(records: &[T], name: &str, format: Format) -> Result<(), Box<dyn Error>> {
+    match format {
+      Format::Csv => write_csv(records, &format!("{}.csv", name)),
+      Format::Json => write_json(records, &format!("{}.json", name)),
+      Format::Ndjson => write_ndjson(records, &format!("{}.ndjson", name)),
+    }
+}
+
+/// Prints a performance report as an aligned table.
+fn print_performance(report: &[Performance]) {
+    let percent = |x: Option<f64>| x.map(|x| format!("{:.2}%", x * 100.0)).unwrap_or_else(|| String::from("-"));
+    let ratio = |x: Option<f64>| x.map(|x| format!("{:.2}", x)).unwrap_or_else(|| String::from("-"));
+    println!(
+      "{:<12} {:<6} {:<10} {:<10} {:>14} {:>14} {:>14} {:>9} {:>9} {:>9} {:>9} {:>9} {:>7}",
+      "scope", "period", "from", "to", "start", "end", "net flows", "TWR", "TWR/yr", "XIRR", "max DD", "vol", "sharpe",
+    );
+    for x in report {
+      println!(
+        "{:<12} {:<6} {:<10} {:<10} {:>14.2} {:>14.2} {:>14.2} {:>9} {:>9} {:>9} {:>9} {:>9} {:>7}",
+        x.scope, x.period, x.from, x.to, x.start_value, x.end_value, x.net_flows,
+        percent(x.twr), percent(x.twr_annualized), percent(x.xirr), percent(x.max_drawdown), percent(x.volatility), ratio(x.sharpe),
+      );
+    }
+}
+
+/// Prints gas totals as an aligned table.
+fn print_gas(title: &str, rows: &[GasSummary]) {
+    println!("{:<24} {:>6} {:>14} {:>12}", title, "txs", "gas ETH", "gas USD");
+    for x in rows {
+      println!("{:<24} {:>6} {:>14.6} {:>12.2}", x.key, x.transactions, x.gas_eth, x.gas_usd);
+    }
+    println!();
+}
+
+/// Prints GMX trading costs as an aligned table.
+fn print_gmx(title: &str, rows: &[GmxSummary]) {
+    println!(
+      "{:<12} {:>6} {:>14} {:>12} {:>12} {:>10} {:>12} {:>8}",
+      title, "trades", "volume", "impact", "exec fee", "gas", "pnl", "cost %",
+    );
+    for x in rows {
+      let share = x.cost_share().map(|x| format!("{:.3}%", x * Decimal::ONE_HUNDRED)).unwrap_or_else(|| String::from("-"));
+      println!(
+        "{:<12} {:>6} {:>14.2} {:>12.2} {:>12.2} {:>10.2} {:>12.2} {:>8}",
+        x.key, x.trades, x.volume_usd, x.price_impact_usd, x.execution_fee_usd, x.gas_usd, x.pnl_usd, share,
+      );
+    }
+    println!();
+}
+
+/// Prints income totals as an aligned table.
+fn print_income(title: &str, rows: &[IncomeSummary]) {
+    println!("{:<24} {:>8} {:>18} {:>14} {:>14} {:>8}", title, "receipts", "amount", "fair value", "cost basis", "unpriced");
+    for x in rows {
+      println!(
+        "{:<24} {:>8} {:>18} {:>14.2} {:>14.2} {:>8}",
+        x.key, x.receipts, x.amount.map(|x| format!("{:.6}", x)).unwrap_or_else(|| String::from("-")), x.fair_value_usd, x.cost_basis_usd, x.unpriced,
+      );
+    }
+    println!();
+}
+
+/// Prints the spam tokens awaiting review as `data/ref/tokens.toml` entries
+/// marked `spam = true`; change it to `false` to keep a token.
+fn print_spam(spam: &[SpamTransfer]) {
+    let pending = spam.iter().filter(|x| x.reason != SpamReason::Confirmed).into_group_map_by(|x| (x.chain, &x.address));
+    for ((chain, address), transfers) in pending.into_iter().sorted_by_key(|x| (x.1[0].datetime.clone(), x.0)) {
+      let first = transfers[0];
+      println!("# {}: {} transfer(s) since {}", first.reason, transfers.len(), first.datetime);
+      println!("[\"{}:{}\"]", chain, address);
+      println!("asset = \"Spam\"");
+      println!("symbol = {:?}", first.symbol);
+      println!("is_debt = false");
+      println!("spam = true");
+      println!();
+    }
+}
+
+/// Prints swap executions as an aligned table, flagged swaps marked with `*`.
+fn print_swaps(rows: &[SwapExecution]) {
+    println!(
+      "{:<20} {:<10} {:<10} {:>16} {:>16} {:>12} {:>9}  {:<42} protocol",
+      "datetime", "sold", "bought", "effective rate", "reference rate", "volume", "bps", "router",
+    );
+    for x in rows {
+      println!(
+        "{:<20} {:<10} {:<10} {:>16.6} {:>16.6} {:>12.2} {:>9.1}{} {:<42} {}",
+        x.datetime, x.asset_sold, x.asset_purchased, x.effective_rate, x.reference_rate, x.volume_usd, x.slippage_bps,
+        if x.outlier { "*" } else { " " }, x.router, x.protocol.as_deref().unwrap_or("-"),
+      );
+    }
+    println!();
 }
 
-const ADDRESS: &str = "0x0A8Dd68E974C371A6a6Efe95cfA22a200eb7AfCc";
+/// Prints slippage per router as an aligned table.
+fn print_routers(rows: &[RouterExecution]) {
+    println!(
+      "{:<42} {:<12} {:>6} {:>14} {:>9} {:>9} {:>8} {:>10}",
+      "router", "protocol", "swaps", "volume", "bps", "max bps", "flagged", "cost",
+    );
+    for x in rows {
+      println!(
+        "{:<42} {:<12} {:>6} {:>14.2} {:>9.1} {:>9.1} {:>8} {:>10.2}",
+        x.router, x.protocol.as_deref().unwrap_or("-"), x.swaps, x.volume_usd, x.slippage_bps, x.max_slippage_bps, x.outliers, x.cost_usd,
+      );
+    }
+    println!();
+}
+
+/// Label of a source (the file or directory it reads) and the reader of its
+/// transfers, only called when the source has to be parsed.
+type Reader<'a> = (String, Box<dyn Fn() -> Result<Vec<Transfer>, Box<dyn Error>> + 'a>);
+
+/// Reads the fees paid by the transactions `address` sent from the normal
+/// transaction exports; the same transaction may appear in several of them.
+fn read_all_fees(address: &str) -> Result<Vec<Fee>, Box<dyn Error>> {
+    let mut fees = vec![];
+    if Path::new("data/ingest/transactions.csv").exists() {
+      fees.extend(read_fees("data/ingest/transactions.csv", address, Chain::Arbitrum)?);
+    }
+    fees.extend(read_api_fees_dir("data/ingest/api", address, Chain::Arbitrum)?);
+    for chain in Chain::ONCHAIN {
+      let dir = format!("data/ingest/{}", chain);
+      fees.extend(read_fees_dir(&dir, address, chain)?);
+      fees.extend(read_api_fees_dir(&format!("{}/api", dir), address, chain)?);
+    }
+    let mut seen = HashSet::new();
+    fees.retain(|x| seen.insert((x.chain, x.transfer_id.to_lowercase())));
+    Ok(fees)
+}
 
 /// Runs the importer CLI.
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
     // initialize logging from log4rs config file
     log4rs::init_file("log4rs.yml", Default::default()).expect("failed to init logger");
-  
-    let mut transfers: Vec<Transfer> = read_tokens("data/ingest/tokens.csv", ADDRESS)?;
-    //transfers.extend(read_transactions("data/ingest/transactions.csv", ADDRESS)?);
-    transfers.extend(read_internals("data/ingest/internal.csv", ADDRESS)?);
 
-   let transactions: Vec<Transaction> = transfers.clone().to_transaction();
+    // Importing a review only edits data/ref/categories.toml
+    if let Some(Command::Review { action: ReviewAction::Import { file } }) = &args.command {
+      let merge = merge_review(&read_csv::<ReviewRow>(file)?)?;
+      for (key, category) in &merge.added {
+        println!("added {} = {}", key, category);
+      }
+      for (key, reason) in &merge.skipped {
+        println!("skipped {}: {}", key, reason);
+      }
+      return Ok(());
+    }
+
+    let address = args.address.as_deref().ok_or("--address is required")?;
+
+    // Readers of each source, labelled with the file or directory they read
+    let mappings = read_sources()?;
+    let mut readers: Vec<Reader> = vec![];
+    // Arbitrum exports directly under data/ingest/, read when present
+    for file_path in ["data/ingest/tokens.csv", "data/ingest/internal.csv", "data/ingest/nfts.csv"] {
+      if Path::new(file_path).exists() {
+        readers.push((String::from(file_path), Box::new(move || read_etherscan(file_path, address, Chain::Arbitrum))));
+      }
+    }
+
+    readers.push((String::from("data/ingest/api"), Box::new(|| read_api_dir("data/ingest/api", address, Chain::Arbitrum))));
+    readers.push((String::from("data/ingest/rpc"), Box::new(|| read_rpc_dir("data/ingest/rpc", &[address], Chain::Arbitrum))));
+
+    // Every chain, Arbitrum included, keeps any number of explorer exports,
+    // API responses and RPC dumps under data/ingest/<chain>/
+    for chain in Chain::ONCHAIN {
+      let dir = format!("data/ingest/{}", chain);
+      let (api, rpc) = (format!("{}/api", dir), format!("{}/rpc", dir));
+      readers.push((dir.clone(), Box::new(move || read_etherscan_dir(&dir, address, chain))));
+      readers.push((api.clone(), Box::new(move || read_api_dir(&api, address, chain))));
+      readers.push((rpc.clone(), Box::new(move || read_rpc_dir(&rpc, &[address], chain))));
+    }
+
+    for mapping in mappings.values() {
+      readers.push((mapping.file.clone(), Box::new(move || read_custom(mapping, address))));
+    }
+
+    readers.push((String::from("data/ingest/exchange"), Box::new(|| read_exchange_dir("data/ingest/exchange"))));
+
+    // With a store, only sources whose files changed since they were last
+    // ingested are parsed again
+    let mut store = args.store.as_deref().map(Store::open).transpose()?;
+    let mut sources: Sources = vec![];
+    for (source, read) in readers {
+      match store.as_mut() {
+        Some(store) => {
+          let stamp = stamp(&source)?;
+          match store.is_current(&source, &stamp)? {
+            true => info!("{}: unchanged", source),
+            false => {
+              let added = store.ingest(&source, &stamp, read()?)?;
+              info!("{}: {} new transfers", source, added);
+            },
+          }
+        },
+        None => sources.push((source, read()?)),
+      }
+    }
+    if let Some(store) = &store {
+      sources = store.sources()?;
+    }
+
+    let (all_transfers, duplicates) = dedup(sources);
+    if !duplicates.is_empty() {
+      info!("dropped {} transfers repeated across sources", duplicates.len());
+    }
+
+    let (mut exchange_transfers, mut transfers): (Vec<Transfer>, Vec<Transfer>) =
+      all_transfers.into_iter().partition(|x| x.chain == Chain::Offchain);
+
+    let category_config = read_category_config();
+    match_bridges(&mut transfers, &bridge_counterparties(&category_config));
+
+    link_transfers(&mut exchange_transfers, &transfers);
+    transfers.extend(exchange_transfers);
+
+    let adjustments = read_adjustments()?;
+    let mut adjusted = adjustments.apply_to_transfers(address, &mut transfers)?;
+    let overridden = adjustments.category_config()?;
+    let mut config = category_config.clone();
+    config.extend(overridden.clone());
+
+   let mut transactions: Vec<Transaction> = match store.as_mut() {
+     Some(store) => {
+       let (transactions, reclassified) = store.classify(group_transfers(transfers), &config)?;
+       info!("{} of {} transactions classified", reclassified, transactions.len());
+       transactions
+     },
+     None => classify_transactions(group_transfers(transfers), &config),
+   };
+
+   let spam = filter_spam(&mut transactions, &overridden.into_keys().collect());
+   if !spam.is_empty() {
+     info!("ignored {} spam transfers", spam.len());
+   }
+
+   adjusted.extend(adjustments.transaction_records(&transactions, &category_config));
+   if !adjusted.is_empty() {
+     info!("applied {} adjustments", adjusted.len());
+   }
 
    let net_transfers: Vec<Transfer> = transactions.iter().flat_map(|x| x.net_transfers.clone()).collect();
 
-    write_csv(&transactions, "transactions.csv")?;
-    write_csv(&net_transfers, "transfers.csv")?;
+   let prices = PriceStore::load(&transactions)?;
+   let fees = read_all_fees(address)?;
+   let lot_assignments = adjustments.lot_assignments();
+   let ledger = Portfolio::from((&transactions, fees.as_slice(), &prices, &lot_assignments));
+   let portfolio = ledger.holdings();
+   let nav_records = nav(&transactions, &fees, &prices, &lot_assignments);
+
+    if let Some(Command::Performance { periods, risk_free }) = &args.command {
+      print_performance(&report(&transactions, &nav_records, &prices, periods, *risk_free));
+      return Ok(());
+    }
+
+    if let Some(Command::Fees) = &args.command {
+      let trades = match Path::new("data/ingest/trades.csv").exists() {
+        true => read_gmx_trades("data/ingest/trades.csv")?,
+        false => vec![],
+      };
+
+      let report = gas_report(&fees, &transactions, &trades, &prices);
+      print_gas("month", &report.by_month);
+      print_gas("category", &report.by_category);
+      print_gas("protocol", &report.by_protocol);
+      print_gmx("GMX month", &report.gmx_by_month);
+      print_gmx("GMX size", &report.gmx_by_size);
+      return Ok(());
+    }
+
+    if let Some(Command::Income) = &args.command {
+      let report = income_report(&ledger.income);
+      print_income("month", &report.by_month);
+      print_income("token", &report.by_token);
+      return Ok(());
+    }
+
+    if let Some(Command::Spam) = &args.command {
+      print_spam(&spam);
+      return Ok(());
+    }
+
+    if let Some(Command::Review { action: ReviewAction::Export { file } }) = &args.command {
+      let rows = review_rows(&transactions);
+      write_csv(&rows, file)?;
+      println!("{} transactions to review written to {}", rows.len(), file);
+      return Ok(());
+    }
+
+    if let Some(Command::Slippage { threshold_bps, outliers }) = &args.command {
+      let executions = swap_executions(&transactions, &PriceStore::read_file()?, Decimal::from(*threshold_bps));
+      let swaps: Vec<SwapExecution> = executions.iter().filter(|x| x.outlier || !outliers).cloned().collect();
+      print_swaps(&swaps);
+      print_routers(&router_executions(&executions));
+      return Ok(());
+    }
+
+    match args.format {
+      Format::Csv => {
+        let transaction_rows: Vec<TransactionRow> = transactions.iter().map(TransactionRow::from).collect();
+        let transfer_rows: Vec<TransferRow> = net_transfers.iter().map(TransferRow::from).collect();
+        let portfolio_rows: Vec<PortfolioRow> = portfolio.iter().map(PortfolioRow::from).collect();
+
+        write_records(&transaction_rows, "transactions", args.format)?;
+        write_records(&transfer_rows, "transfers", args.format)?;
+        write_records(&portfolio_rows, "portfolio", args.format)?;
+        write_nav_csv(&nav_records, "nav.csv")?;
+      },
+      Format::Json | Format::Ndjson => {
+        let transaction_records: Vec<TransactionRecord> = transactions.iter().map(TransactionRecord::from).collect();
+        let transfer_records: Vec<TransferRecord> = net_transfers.iter().map(TransferRecord::from).collect();
+        let portfolio_records: Vec<PortfolioRecord> = portfolio.iter().map(PortfolioRecord::from).collect();
+
+        write_records(&transaction_records, "transactions", args.format)?;
+        write_records(&transfer_records, "transfers", args.format)?;
+        write_records(&portfolio_records, "portfolio", args.format)?;
+        write_records(&nav_records, "nav", args.format)?;
+      },
+    }
+    if !adjusted.is_empty() {
+      write_records(&adjusted, "adjustments", args.format)?;
+    }
+
+    if let Some(file_path) = &args.sqlite {
+      write_sqlite(&transactions, &ledger, file_path)?;
+    }
+    if let Some(file_path) = &args.html {
+      write_html(&transactions, &ledger, &prices, file_path)?;
+    }
+    if let Some(file_path) = &args.beancount {
+      write_plaintext(&transactions, &ledger, &fees, PlainText::Beancount, file_path)?;
+    }
+    if let Some(file_path) = &args.ledger {
+      write_plaintext(&transactions, &ledger, &fees, PlainText::Ledger, file_path)?;
+    }
+    if args.form8949.is_some() || args.schedule_d.is_some() {
+      let form = form_8949(&ledger);
+      if let Some(file_path) = &args.form8949 {
+        write_csv(&form, file_path)?;
+      }
+      if let Some(file_path) = &args.schedule_d {
+        write_csv(&schedule_d(&form), file_path)?;
+      }
+    }
+
+    let entries = entries(&transactions);
+    if let Some(file_path) = &args.koinly {
+      write_csv(&entries.iter().map(KoinlyRow::from).collect::<Vec<_>>(), file_path)?;
+    }
+    if let Some(file_path) = &args.cointracker {
+      write_csv(&entries.iter().map(CoinTrackerRow::from).collect::<Vec<_>>(), file_path)?;
+    }
+    if let Some(file_path) = &args.coinledger {
+      write_csv(&entries.iter().map(CoinLedgerRow::from).collect::<Vec<_>>(), file_path)?;
+    }
 
     Ok(())
 }
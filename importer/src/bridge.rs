@@ -0,0 +1,152 @@
+//! Matching of the two legs of a bridge transfer between chains.
+//!
+//! Bridging shows up as an outgoing transfer on the source chain and an
+//! incoming transfer on the destination chain, each in its own transaction.
+//! [`match_bridges`] pairs them so they group into one [`crate::Transaction`]
+//! classified as [`crate::TransactionCategory::Bridge`], and the lot engine
+//! carries the lots across instead of realising a gain. Only legs sent to or
+//! received from a bridge configured in `data/ref/categories.toml` are paired.
+
+use crate::ingest::DATETIME_FORMAT;
+use crate::{Chain, Transfer, TransferDirection};
+use chrono::{Duration, NaiveDateTime};
+use itertools::Itertools;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// Maximum time between the two legs; covers the seven day challenge period of
+/// optimistic rollup withdrawals.
+const BRIDGE_WINDOW_DAYS: i64 = 8;
+
+/// Maximum fraction of the amount lost to bridge fees.
+const BRIDGE_TOLERANCE: &str = "0.01";
+
+/// Rewrites the `transfer_id` of each incoming bridge leg to the hash of the
+/// outgoing leg it pairs with.
+///
+/// Only transfers of a registered asset that are alone in their transaction are
+/// considered. Legs pair when they carry the same underlying asset on different
+/// chains, either of them has one of the `bridges` as counterparty, the incoming
+/// leg arrives within [`BRIDGE_WINDOW_DAYS`] after the outgoing one, and at most
+/// [`BRIDGE_TOLERANCE`] of the amount was lost to fees. Each outgoing leg takes
+/// the earliest matching incoming leg.
+pub fn match_bridges(transfers: &mut [Transfer], bridges: &HashSet<String>) {
+    let tolerance = Decimal::from_str(BRIDGE_TOLERANCE).unwrap();
+    let parse = |x: &str| NaiveDateTime::parse_from_str(x, DATETIME_FORMAT).ok();
+
+    let mut legs: HashMap<&str, usize> = HashMap::new();
+    for transfer in transfers.iter() {
+      *legs.entry(&transfer.transfer_id).or_default() += 1;
+    }
+
+    let candidates: Vec<(usize, NaiveDateTime)> = transfers
+      .iter()
+      .enumerate()
+      .filter(|(_, x)| x.chain != Chain::Offchain && x.token.is_known() && legs[x.transfer_id.as_str()] == 1)
+      .filter_map(|(idx, x)| Some((idx, parse(&x.datetime)?)))
+      .sorted_by_key(|(_, time)| *time)
+      .collect();
+
+    let mut used = vec![false; transfers.len()];
+    let mut pairs = vec![];
+    let via_bridge = |x: &Transfer| x.counterparty.iter().any(|x| bridges.contains(&x.to_lowercase()));
+    for &(out, sent_at) in candidates.iter().filter(|(idx, _)| transfers[*idx].direction == TransferDirection::Outgoing) {
+      let sent = &transfers[out];
+      let matched = candidates
+        .iter()
+        .filter(|(idx, received_at)| {
+          let received = &transfers[*idx];
+          let delay = *received_at - sent_at;
+          !used[*idx]
+            && received.direction == TransferDirection::Incoming
+            && received.chain != sent.chain
+            && received.token.asset == sent.token.asset
+            && (via_bridge(sent) || via_bridge(received))
+            && delay >= Duration::zero()
+            && delay <= Duration::days(BRIDGE_WINDOW_DAYS)
+            && received.value <= sent.value
+            && received.value >= sent.value * (Decimal::ONE - tolerance)
+        })
+        .map(|(idx, _)| *idx)
+        .next();
+
+      if let Some(incoming) = matched {
+        used[incoming] = true;
+        pairs.push((out, incoming));
+      }
+    }
+
+    for (out, incoming) in pairs {
+      transfers[incoming].transfer_id = transfers[out].transfer_id.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Token;
+
+  const GATEWAY: &str = "0xgateway";
+
+  fn leg(transfer_id: &str, datetime: &str, chain: Chain, value: &str, direction: TransferDirection, counterparty: &str) -> Transfer {
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      token: Token::eth(chain),
+      value: Decimal::from_str(value).unwrap(),
+      usd_value: None,
+      direction,
+      counterparty: vec![counterparty.to_string()],
+      account: String::from("0xwallet"),
+      chain,
+    }
+  }
+
+  fn bridges() -> HashSet<String> {
+    HashSet::from([String::from(GATEWAY)])
+  }
+
+  #[test]
+  fn pairs_legs_through_a_configured_bridge() {
+    let mut transfers = vec![
+      leg("0xa", "2024-01-01 00:00:00", Chain::Ethereum, "1", TransferDirection::Outgoing, GATEWAY),
+      leg("0xb", "2024-01-01 00:10:00", Chain::Arbitrum, "0.995", TransferDirection::Incoming, "0xrelayer"),
+    ];
+    match_bridges(&mut transfers, &bridges());
+    assert_eq!(transfers[1].transfer_id, "0xa");
+  }
+
+  #[test]
+  fn ignores_legs_without_a_bridge_counterparty() {
+    let mut transfers = vec![
+      leg("0xa", "2024-01-01 00:00:00", Chain::Ethereum, "1", TransferDirection::Outgoing, "0xfriend"),
+      leg("0xb", "2024-01-01 00:10:00", Chain::Arbitrum, "1", TransferDirection::Incoming, "0xemployer"),
+    ];
+    match_bridges(&mut transfers, &bridges());
+    assert_eq!(transfers[1].transfer_id, "0xb");
+  }
+
+  #[test]
+  fn ignores_unknown_assets() {
+    let mut transfers = vec![
+      leg("0xa", "2024-01-01 00:00:00", Chain::Ethereum, "1", TransferDirection::Outgoing, GATEWAY),
+      leg("0xb", "2024-01-01 00:10:00", Chain::Arbitrum, "1", TransferDirection::Incoming, GATEWAY),
+    ];
+    transfers.iter_mut().for_each(|x| x.token = Token { chain: x.chain, ..Default::default() });
+    match_bridges(&mut transfers, &bridges());
+    assert_eq!(transfers[1].transfer_id, "0xb");
+  }
+
+  #[test]
+  fn ignores_late_or_short_legs() {
+    let mut transfers = vec![
+      leg("0xa", "2024-01-01 00:00:00", Chain::Ethereum, "1", TransferDirection::Outgoing, GATEWAY),
+      leg("0xb", "2024-01-10 00:00:00", Chain::Arbitrum, "1", TransferDirection::Incoming, GATEWAY),
+      leg("0xc", "2024-01-01 00:10:00", Chain::Arbitrum, "0.9", TransferDirection::Incoming, GATEWAY),
+    ];
+    match_bridges(&mut transfers, &bridges());
+    assert_eq!(transfers[1].transfer_id, "0xb");
+    assert_eq!(transfers[2].transfer_id, "0xc");
+  }
+}
@@ -10,12 +10,12 @@ impl TryFrom<&Vec<Transfer>> for DebtSwap {
     type Error = &'static str;
     fn try_from(transfers: &Vec<Transfer>) -> Result<DebtSwap, &'static str> {
       if transfers.len() != 2 { Err("Nope")? }
-      if transfers.iter().map(|x| x.direction.clone()).all_equal() == false {
+      if !transfers.iter().map(|x| x.direction.clone()).all_equal() {
         Err("Nope")?
       }
 
-      let debt = transfers.iter().find(|x| x.token.is_debt == true).ok_or("Nope")?;
-      let token = transfers.iter().find(|x| x.token.is_debt == false).ok_or("Nope")?;
+      let debt = transfers.iter().find(|x| x.token.is_debt).ok_or("Nope")?;
+      let token = transfers.iter().find(|x| !x.token.is_debt).ok_or("Nope")?;
       let debt_value = debt.value;
       let value = token.value;
 
@@ -40,7 +40,7 @@ impl TryFrom<&Vec<Transfer>> for TwoAssetSwap {
       let sold = transfers.iter().find(|x| x.direction == TransferDirection::Outgoing).ok_or("Nope")?;
       let purchased = transfers.iter().find(|x| x.direction == TransferDirection::Incoming).ok_or("Nope")?;
       let value_purchased = purchased.value;
-      let value_sold = purchased.value;
+      let value_sold = sold.value;
 
       match (sold.usd_value, purchased.usd_value) {
         (Some(value_sold_usd), Some(value_purchased_usd)) => Ok(TwoAssetSwap {
@@ -1,68 +1,175 @@
 //! Utilities for classifying transfers into [`TransactionCategory`] values.
 
-use crate::{TransactionCategory, Transfer, SwapSubCategory};
-use std::collections::HashMap;
+use crate::{Chain, TransactionCategory, Transfer, TransferDirection, SwapSubCategory};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use serde::{Serialize, Serializer};
+use itertools::Itertools;
 
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 /// Mapping of identifiers to known transaction categories.
-struct CategoryMapping {
+pub struct CategoryMapping {
     pub category: String,
-    //pub description: String,
+    /// Protocol or purpose of the address or transaction, e.g. `AAVE` or `GMX`.
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 /// Convenience alias for the category configuration file.
-type CategoryConfig = HashMap<String, CategoryMapping>;
+pub type CategoryConfig = HashMap<String, CategoryMapping>;
+
+/// Path of the category configuration file.
+pub const CATEGORY_FILE: &str = "data/ref/categories.toml";
+
+/// Category names accepted in `data/ref/categories.toml`.
+pub const CATEGORY_NAMES: [&str; 7] = ["Swap", "Trade", "Transfer", "Bridge", "Airdrop", "Income", "Ignore"];
+
+/// Loads `data/ref/categories.toml`.
+pub fn read_category_config() -> CategoryConfig {
+    let path = Path::new(CATEGORY_FILE);
+    let toml_str = fs::read_to_string(path).unwrap();
+    toml::de::from_str(&toml_str).unwrap()
+}
+
+/// Counterparties configured as `Bridge`, the only ones whose transfers
+/// [`crate::bridge::match_bridges`] pairs across chains.
+pub fn bridge_counterparties(config: &CategoryConfig) -> HashSet<String> {
+    config
+      .iter()
+      .filter(|(_, x)| x.category == "Bridge")
+      .map(|(key, _)| key.to_lowercase())
+      .collect()
+}
+
+/// Keys under which a group of transfers can be configured: the transaction
+/// hash and every counterparty, in lookup order.
+pub fn category_keys(transfers: &[Transfer]) -> Vec<&String> {
+    transfers
+      .iter()
+      .flat_map(|x| [vec![&x.transfer_id], x.counterparty.iter().collect()].concat())
+      .collect()
+}
+
+/// Name of the category configured for a group of transfers, if any.
+pub fn configured_category(transfers: &[Transfer], config: &CategoryConfig) -> Option<String> {
+    category_keys(transfers)
+      .into_iter()
+      .find_map(|key| config.get(key))
+      .map(|x| x.category.clone())
+}
+
+/// Description configured for a group of transfers, if any, looked up like its category.
+pub fn configured_description(transfers: &[Transfer], config: &CategoryConfig) -> Option<String> {
+    category_keys(transfers)
+      .into_iter()
+      .find_map(|key| config.get(key))
+      .and_then(|x| x.description.clone())
+}
+
+/// Builds the [`TransactionCategory`] of a group of transfers from its
+/// configured category name, falling back to heuristics when there is none.
+pub fn classify(name: Option<&str>, transfers: &Vec<Transfer>) -> TransactionCategory {
+    match name {
+        //Some("Swap") if is_simple_swap(transfers) => TransactionCategory::Swap(SwapSubCategory::Simple),
+        //Some("Swap") => TransactionCategory::Swap(Default::default()),
+        Some("Swap") => TransactionCategory::Swap(transfers.into()),
+        Some("Trade") => TransactionCategory::Trade,
+        Some("Transfer") => TransactionCategory::Transfer,
+        Some("Bridge") => TransactionCategory::Bridge,
+        Some("Airdrop") => TransactionCategory::Airdrop,
+        Some("Income") => TransactionCategory::Income,
+        Some("Ignore") => TransactionCategory::Ignore,
+        None if is_bridge(transfers) => TransactionCategory::Bridge,
+        None if is_own_move(transfers) => TransactionCategory::Transfer,
+        None => TransactionCategory::Unknown,
+        _ => panic!(),
+    }
+}
 
 /// Derives a [`TransactionCategory`] for a group of transfers by consulting
 /// `data/ref/categories.toml` and falling back to heuristics when needed.
 impl From<&Vec<Transfer>> for TransactionCategory {
     fn from(transfers: &Vec<Transfer>) -> Self {
-      // Load categories from the TOML file
-      let path = Path::new("data/ref/categories.toml");
-      let toml_str = fs::read_to_string(path).unwrap();
-      let config: CategoryConfig = toml::de::from_str(&toml_str).unwrap();
-
-      let category: Option<&CategoryMapping> = transfers
-        .iter()
-        .flat_map(|x| vec![vec![&x.transfer_id], x.counterparty.iter().collect()].concat())
-        .find_map(|key| config.get(key));
-
-      match category.map(|x| x.category.as_str()) {
-          //Some("Swap") if is_simple_swap(transfers) => TransactionCategory::Swap(SwapSubCategory::Simple),
-          //Some("Swap") => TransactionCategory::Swap(Default::default()),
-          Some("Swap") => TransactionCategory::Swap(transfers.into()),
-          Some("Trade") => TransactionCategory::Trade,
-          Some("Transfer") => TransactionCategory::Transfer,
-          Some("Airdrop") => TransactionCategory::Airdrop,
-          Some("Ignore") => TransactionCategory::Ignore,
-          None => TransactionCategory::Unknown,
-          _ => panic!(),
+      let config = read_category_config();
+      classify(configured_category(transfers, &config).as_deref(), transfers)
+    }
+}
+
+impl TransactionCategory {
+    /// Whether tokens received in this category are income.
+    pub fn is_income(&self) -> bool {
+      matches!(self, TransactionCategory::Airdrop | TransactionCategory::Income)
+    }
+
+    /// Rebuilds the category named `name` (see [`Self::name`]) for the same
+    /// transfers without consulting the configuration or the heuristics. Swap
+    /// details are derived from the transfers; `None` when they no longer fit.
+    pub fn from_name(name: &str, transfers: &Vec<Transfer>) -> Option<Self> {
+      Some(match name {
+        "SwapTwoAsset" => TransactionCategory::Swap(SwapSubCategory::TwoAsset(transfers.try_into().ok()?)),
+        "SwapDebt" => TransactionCategory::Swap(SwapSubCategory::Debt(transfers.try_into().ok()?)),
+        "SwapUnknown" => TransactionCategory::Swap(SwapSubCategory::UnknownSwap),
+        "Trade" => TransactionCategory::Trade,
+        "Transfer" => TransactionCategory::Transfer,
+        "Bridge" => TransactionCategory::Bridge,
+        "Airdrop" => TransactionCategory::Airdrop,
+        "Income" => TransactionCategory::Income,
+        "Ignore" => TransactionCategory::Ignore,
+        "Unknown" => TransactionCategory::Unknown,
+        _ => None?,
+      })
+    }
+
+    /// Name of the category in the outputs, e.g. `SwapTwoAsset`.
+    pub fn name(&self) -> &'static str {
+      match self {
+        TransactionCategory::Swap(sub) => match sub {
+          SwapSubCategory::TwoAsset(_) => "SwapTwoAsset",
+          SwapSubCategory::UnknownSwap => "SwapUnknown",
+          SwapSubCategory::Debt(_) => "SwapDebt",
+        },
+        TransactionCategory::Trade => "Trade",
+        TransactionCategory::Transfer => "Transfer",
+        TransactionCategory::Bridge => "Bridge",
+        TransactionCategory::Airdrop => "Airdrop",
+        TransactionCategory::Income => "Income",
+        TransactionCategory::Ignore => "Ignore",
+        TransactionCategory::Unknown => "Unknown",
       }
     }
 }
 
+/// Whether the transfers move a single asset between two of our own accounts,
+/// e.g. an exchange withdrawal linked to its on-chain deposit.
+fn is_own_move(transfers: &[Transfer]) -> bool {
+    transfers.iter().map(|x| &x.token.asset).all_equal()
+      && transfers.iter().map(|x| &x.account).unique().count() > 1
+      && transfers.iter().any(|x| x.direction == TransferDirection::Incoming)
+      && transfers.iter().any(|x| x.direction == TransferDirection::Outgoing)
+}
+
+/// Whether the transfers are the two legs of a bridge paired by
+/// [`crate::bridge::match_bridges`]: one asset leaving one chain and arriving on another.
+fn is_bridge(transfers: &[Transfer]) -> bool {
+    match transfers {
+      [sent, received] | [received, sent] if sent.direction == TransferDirection::Outgoing
+        && received.direction == TransferDirection::Incoming =>
+        sent.token.asset == received.token.asset
+          && sent.chain != received.chain
+          && sent.chain != Chain::Offchain
+          && received.chain != Chain::Offchain,
+      _ => false,
+    }
+}
+
 impl Serialize for TransactionCategory {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let name = match self {
-            TransactionCategory::Swap(sub) => match sub {
-              SwapSubCategory::TwoAsset(_) => "SwapTwoAsset",
-              SwapSubCategory::UnknownSwap => "SwapUnknown",
-              SwapSubCategory::Debt(_) => "SwapDebt",
-            },
-            TransactionCategory::Trade => "Trade",
-            TransactionCategory::Transfer => "Transfer",
-            TransactionCategory::Airdrop => "Airdrop",
-            TransactionCategory::Ignore => "Ignore",
-            TransactionCategory::Unknown => "Unknown",
-        };
-        serializer.serialize_str(name)
+        serializer.serialize_str(self.name())
     }
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+//! Naming of the chains a portfolio can span.
+
+use crate::Chain;
+use std::fmt;
+use std::str::FromStr;
+
+impl Chain {
+    /// Chains whose explorers and nodes can be ingested, i.e. all but [`Chain::Offchain`].
+    pub const ONCHAIN: [Chain; 4] = [Chain::Arbitrum, Chain::Ethereum, Chain::Optimism, Chain::Base];
+
+    /// Lowercase name used in `data/ref/tokens.toml` keys and ingest directories.
+    pub fn name(&self) -> &'static str {
+      match self {
+        Chain::Arbitrum => "arbitrum",
+        Chain::Ethereum => "ethereum",
+        Chain::Optimism => "optimism",
+        Chain::Base => "base",
+        Chain::Offchain => "offchain",
+      }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      f.write_str(self.name())
+    }
+}
+
+impl FromStr for Chain {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+      match name.trim().to_lowercase().as_str() {
+        "arbitrum" => Ok(Chain::Arbitrum),
+        "ethereum" | "mainnet" => Ok(Chain::Ethereum),
+        "optimism" => Ok(Chain::Optimism),
+        "base" => Ok(Chain::Base),
+        "offchain" => Ok(Chain::Offchain),
+        _ => Err(format!("unknown chain {}", name)),
+      }
+    }
+}
@@ -0,0 +1,130 @@
+//! Removal of transfers repeated across overlapping exports.
+//!
+//! Downloading overlapping date ranges yields the same rows in several files.
+//! A transfer is identified by its hash, both ends, token, value and time (the
+//! hash fixes the block); within one file identical transfers are genuine (e.g.
+//! the same amount sent twice in one transaction), so each is kept as many
+//! times as the file holding it most often contains it.
+
+use crate::{Chain, Transfer, TransferDirection};
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+/// Transfer dropped because another source already contained it.
+pub struct Duplicate {
+  /// Source (file or directory) the dropped copy was read from.
+  pub source: String,
+  pub transfer: Transfer,
+}
+
+/// Transfers grouped by the source (file or directory) they were read from.
+pub type Sources = Vec<(String, Vec<Transfer>)>;
+
+/// Fields identifying the same transfer across exports.
+type TransferKey = (String, Chain, String, bool, Vec<String>, String, Option<String>, Decimal, String);
+
+fn key(transfer: &Transfer) -> TransferKey {
+  (
+    transfer.transfer_id.to_lowercase(),
+    transfer.chain,
+    transfer.account.to_lowercase(),
+    transfer.direction == TransferDirection::Incoming,
+    transfer.counterparty.iter().map(|x| x.to_lowercase()).collect(),
+    transfer.token.address.to_lowercase(),
+    transfer.token.token_id.clone(),
+    transfer.value,
+    transfer.datetime.clone(),
+  )
+}
+
+/// Merges the transfers read from several sources, dropping the copies of
+/// transfers already read from an earlier source. Each dropped copy is logged
+/// and returned.
+pub fn dedup(sources: Sources) -> (Vec<Transfer>, Vec<Duplicate>) {
+    let mut most: HashMap<TransferKey, usize> = HashMap::new();
+    for (_, transfers) in &sources {
+      let mut counts: HashMap<TransferKey, usize> = HashMap::new();
+      for transfer in transfers {
+        *counts.entry(key(transfer)).or_default() += 1;
+      }
+      for (key, count) in counts {
+        let entry = most.entry(key).or_default();
+        *entry = (*entry).max(count);
+      }
+    }
+
+    let mut kept: HashMap<TransferKey, usize> = HashMap::new();
+    let mut merged = vec![];
+    let mut duplicates = vec![];
+    for (source, transfers) in sources {
+      for transfer in transfers {
+        let key = key(&transfer);
+        let count = kept.entry(key.clone()).or_default();
+        if *count < most[&key] {
+          *count += 1;
+          merged.push(transfer);
+        } else {
+          warn!(
+            "{}: dropping duplicate {} {} {} of {}",
+            source, transfer.value, transfer.token.symbol, transfer.transfer_id, transfer.account
+          );
+          duplicates.push(Duplicate { source: source.clone(), transfer });
+        }
+      }
+    }
+    (merged, duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Token;
+
+  fn transfer(transfer_id: &str, value: i64) -> Transfer {
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: String::from("2024-01-01 00:00:00"),
+      token: Token::eth(Chain::Arbitrum),
+      value: Decimal::from(value),
+      usd_value: None,
+      direction: TransferDirection::Incoming,
+      counterparty: vec![String::from("0xsender")],
+      account: String::from("0xwallet"),
+      chain: Chain::Arbitrum,
+    }
+  }
+
+  #[test]
+  fn drops_copies_from_later_sources() {
+    let sources = vec![
+      (String::from("a.csv"), vec![transfer("0x1", 1), transfer("0x2", 2)]),
+      (String::from("b.csv"), vec![transfer("0x2", 2), transfer("0x3", 3)]),
+    ];
+    let (merged, duplicates) = dedup(sources);
+    assert_eq!(merged.iter().map(|x| x.transfer_id.as_str()).collect::<Vec<_>>(), ["0x1", "0x2", "0x3"]);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].source, "b.csv");
+  }
+
+  #[test]
+  fn keeps_repeats_within_one_file() {
+    let sources = vec![
+      (String::from("a.csv"), vec![transfer("0x1", 1)]),
+      (String::from("b.csv"), vec![transfer("0x1", 1), transfer("0x1", 1)]),
+    ];
+    let (merged, duplicates) = dedup(sources);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(duplicates.len(), 1);
+  }
+
+  #[test]
+  fn hashes_match_case_insensitively() {
+    let sources = vec![
+      (String::from("a.csv"), vec![transfer("0xABC", 1)]),
+      (String::from("api"), vec![transfer("0xabc", 1)]),
+    ];
+    assert_eq!(dedup(sources).0.len(), 1);
+  }
+}
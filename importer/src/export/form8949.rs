@@ -0,0 +1,350 @@
+//! IRS Form 8949 and Schedule D export of realised disposals.
+//!
+//! Every disposal is split by holding period: the lot slices it consumed that
+//! were held for more than one year are reported in Part II (long-term), the
+//! others in Part I (short-term), with proceeds split pro rata by amount.
+//! Digital assets are not reported on a Form 1099-B, so rows use box C and F
+//! and their totals go to Schedule D lines 3 and 10. Amounts are rounded to
+//! cents per row and totals are summed from the rounded rows, so they match
+//! the form. Lots consumed without a known acquisition (more sold than was
+//! ever received) have a zero basis and are treated as short-term.
+
+use crate::portfolio::Portfolio;
+use crate::price::parse_date;
+use crate::{CostBasisTransfer, Lot};
+use chrono::{Datelike, Months, NaiveDate};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Date format used on the form.
+const FORM_DATE_FORMAT: &str = "%m/%d/%Y";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+/// Holding period of a disposal.
+pub enum Term {
+  /// Held one year or less (Part I).
+  #[default]
+  Short,
+  /// Held more than one year (Part II).
+  Long,
+}
+
+impl Term {
+  /// Form 8949 checkbox for transactions not reported on a Form 1099-B.
+  pub fn form_box(&self) -> &'static str {
+    match self {
+      Term::Short => "C",
+      Term::Long => "F",
+    }
+  }
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// One line of Form 8949.
+pub struct Form8949Row {
+  pub tax_year: i32,
+  pub term: Term,
+  #[serde(rename = "box")]
+  pub form_box: &'static str,
+  /// (a) Description of property, e.g. `0.5 ETH`.
+  pub description: String,
+  /// (b) Date acquired, or `Various` when lots of several dates were consumed.
+  pub date_acquired: String,
+  /// (c) Date sold or disposed of.
+  pub date_sold: String,
+  /// (d) Proceeds.
+  pub proceeds: Decimal,
+  /// (e) Cost or other basis.
+  pub cost_basis: Decimal,
+  /// (h) Gain or (loss).
+  pub gain_or_loss: Decimal,
+  pub transfer_id: String,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// One line of Schedule D for a tax year.
+pub struct ScheduleDRow {
+  pub tax_year: i32,
+  pub line: &'static str,
+  pub description: &'static str,
+  /// (d) Proceeds, on lines 3 and 10.
+  pub proceeds: Option<Decimal>,
+  /// (e) Cost or other basis, on lines 3 and 10.
+  pub cost_basis: Option<Decimal>,
+  /// (h) Gain or (loss).
+  pub gain_or_loss: Decimal,
+}
+
+/// Holding period of a lot sold on `sold`: long-term when sold after the
+/// anniversary of its acquisition.
+fn term(lot: &Lot, sold: NaiveDate) -> Term {
+  match parse_date(&lot.datetime).and_then(|x| x.checked_add_months(Months::new(12))) {
+    Some(anniversary) if sold > anniversary => Term::Long,
+    _ => Term::Short,
+  }
+}
+
+/// Rounds an amount to cents.
+fn cents(amount: Decimal) -> Decimal {
+  amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// Form 8949 rows of one disposal, one per holding period. Proceeds are split
+/// by the amount of each period's lots; when there is no amount to split by,
+/// the disposal is reported on a single short-term row.
+fn disposal_rows(disposal: &CostBasisTransfer) -> Vec<Form8949Row> {
+  let Some(sold) = parse_date(&disposal.datetime) else { return vec![] };
+
+  let total = match disposal.value.is_zero() {
+    true => disposal.lots.iter().map(|x| x.amount).sum(),
+    false => disposal.value,
+  };
+
+  let mut terms: BTreeMap<Term, Vec<&Lot>> = BTreeMap::new();
+  for lot in &disposal.lots {
+    let term = match total.is_zero() {
+      true => Term::Short,
+      false => term(lot, sold),
+    };
+    terms.entry(term).or_default().push(lot);
+  }
+
+  terms
+    .into_iter()
+    .map(|(term, lots)| {
+      let amount: Decimal = lots.iter().map(|x| x.amount).sum();
+      let proceeds = match total.is_zero() {
+        true => disposal.sale_price,
+        false => disposal.sale_price * amount / total,
+      };
+      let proceeds = cents(proceeds);
+      let cost_basis = cents(lots.iter().map(|x| x.cost_basis).sum());
+
+      let mut dates: Vec<NaiveDate> = lots.iter().filter_map(|x| parse_date(&x.datetime)).collect();
+      dates.dedup();
+      let date_acquired = match dates.as_slice() {
+        [date] => date.format(FORM_DATE_FORMAT).to_string(),
+        [] => String::new(),
+        _ => String::from("Various"),
+      };
+
+      let description = match &disposal.token.token_id {
+        Some(token_id) => format!("{} #{}", disposal.token.asset, token_id),
+        None => format!("{} {}", amount.normalize(), disposal.token.symbol),
+      };
+
+      Form8949Row {
+        tax_year: sold.year(),
+        term,
+        form_box: term.form_box(),
+        description,
+        date_acquired,
+        date_sold: sold.format(FORM_DATE_FORMAT).to_string(),
+        proceeds,
+        cost_basis,
+        gain_or_loss: proceeds - cost_basis,
+        transfer_id: disposal.transfer_id.clone(),
+      }
+    })
+    .collect()
+}
+
+/// Form 8949 rows for every realised disposal, short-term rows of each tax
+/// year first. Repaid debt is not a capital asset and is left out.
+pub fn form_8949(portfolio: &Portfolio) -> Vec<Form8949Row> {
+  let mut rows: Vec<Form8949Row> = portfolio
+    .disposals
+    .iter()
+    .filter(|x| !x.token.is_debt)
+    .flat_map(disposal_rows)
+    .collect();
+  rows.sort_by_key(|x| (x.tax_year, x.term));
+  rows
+}
+
+/// Schedule D lines 3, 7, 10, 15 and 16 of every tax year, totalled from the
+/// Form 8949 rows.
+pub fn schedule_d(rows: &[Form8949Row]) -> Vec<ScheduleDRow> {
+  let mut totals: BTreeMap<(i32, Term), (Decimal, Decimal, Decimal)> = BTreeMap::new();
+  for row in rows {
+    let total = totals.entry((row.tax_year, row.term)).or_default();
+    total.0 += row.proceeds;
+    total.1 += row.cost_basis;
+    total.2 += row.gain_or_loss;
+  }
+
+  let years: BTreeSet<i32> = totals.keys().map(|x| x.0).collect();
+  let mut lines = vec![];
+  for tax_year in years {
+    let (short, long) = (totals.get(&(tax_year, Term::Short)), totals.get(&(tax_year, Term::Long)));
+    let total = |x: Option<&(Decimal, Decimal, Decimal)>| x.copied().unwrap_or_default();
+    let (short, long) = (total(short), total(long));
+
+    lines.extend([
+      ScheduleDRow {
+        tax_year,
+        line: "3",
+        description: "Short-term totals from Form 8949 with box C checked",
+        proceeds: Some(short.0),
+        cost_basis: Some(short.1),
+        gain_or_loss: short.2,
+      },
+      ScheduleDRow {
+        tax_year,
+        line: "7",
+        description: "Net short-term capital gain or (loss)",
+        proceeds: None,
+        cost_basis: None,
+        gain_or_loss: short.2,
+      },
+      ScheduleDRow {
+        tax_year,
+        line: "10",
+        description: "Long-term totals from Form 8949 with box F checked",
+        proceeds: Some(long.0),
+        cost_basis: Some(long.1),
+        gain_or_loss: long.2,
+      },
+      ScheduleDRow {
+        tax_year,
+        line: "15",
+        description: "Net long-term capital gain or (loss)",
+        proceeds: None,
+        cost_basis: None,
+        gain_or_loss: long.2,
+      },
+      ScheduleDRow {
+        tax_year,
+        line: "16",
+        description: "Combine lines 7 and 15",
+        proceeds: None,
+        cost_basis: None,
+        gain_or_loss: short.2 + long.2,
+      },
+    ]);
+  }
+  lines
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Token;
+  use std::str::FromStr;
+
+  fn lot(datetime: &str, amount: &str, cost_basis: &str) -> Lot {
+    Lot {
+      transfer_id: format!("0x{}", datetime),
+      datetime: datetime.to_string(),
+      token: Token::default(),
+      amount: Decimal::from_str(amount).unwrap(),
+      cost_basis: Decimal::from_str(cost_basis).unwrap(),
+    }
+  }
+
+  fn disposal(value: &str, sale_price: &str, lots: Vec<Lot>) -> CostBasisTransfer {
+    let token = Token { symbol: String::from("ETH"), ..Token::default() };
+    let cost_basis = lots.iter().map(|x| x.cost_basis).sum();
+    let sale_price = Decimal::from_str(sale_price).unwrap();
+    CostBasisTransfer {
+      transfer_id: String::from("0xsale"),
+      datetime: String::from("2024-06-01T00:00:00Z"),
+      token,
+      value: Decimal::from_str(value).unwrap(),
+      sale_price,
+      cost_basis,
+      pnl: sale_price - cost_basis,
+      lots,
+    }
+  }
+
+  #[test]
+  fn splits_proceeds_by_holding_period() {
+    let rows = disposal_rows(&disposal(
+      "3",
+      "3000",
+      vec![lot("2022-01-01T00:00:00Z", "1", "500"), lot("2024-01-01T00:00:00Z", "2", "1600")],
+    ));
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!((rows[0].term, rows[0].form_box), (Term::Short, "C"));
+    assert_eq!(rows[0].description, "2 ETH");
+    assert_eq!(rows[0].proceeds, Decimal::from(2000));
+    assert_eq!(rows[0].gain_or_loss, Decimal::from(400));
+    assert_eq!((rows[1].term, rows[1].form_box), (Term::Long, "F"));
+    assert_eq!(rows[1].proceeds, Decimal::from(1000));
+    assert_eq!(rows[1].gain_or_loss, Decimal::from(500));
+  }
+
+  #[test]
+  fn anniversary_sale_is_short_term() {
+    let rows = disposal_rows(&disposal("1", "100", vec![lot("2023-06-01T00:00:00Z", "1", "50")]));
+    assert_eq!(rows[0].term, Term::Short);
+  }
+
+  #[test]
+  fn zero_value_splits_by_lot_amount() {
+    let rows = disposal_rows(&disposal(
+      "0",
+      "300",
+      vec![lot("2022-01-01T00:00:00Z", "1", "0"), lot("2024-01-01T00:00:00Z", "2", "0")],
+    ));
+
+    let proceeds: Vec<Decimal> = rows.iter().map(|x| x.proceeds).collect();
+    assert_eq!(proceeds, vec![Decimal::from(200), Decimal::from(100)]);
+  }
+
+  #[test]
+  fn zero_amounts_fall_back_to_one_row() {
+    let rows = disposal_rows(&disposal(
+      "0",
+      "300",
+      vec![lot("2022-01-01T00:00:00Z", "0", "10"), lot("2024-01-01T00:00:00Z", "0", "20")],
+    ));
+
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].term, Term::Short);
+    assert_eq!(rows[0].proceeds, Decimal::from(300));
+    assert_eq!(rows[0].cost_basis, Decimal::from(30));
+  }
+
+  #[test]
+  fn various_acquisition_dates() {
+    let rows = disposal_rows(&disposal(
+      "2",
+      "200",
+      vec![lot("2024-01-01T00:00:00Z", "1", "50"), lot("2024-01-01T12:00:00Z", "1", "50")],
+    ));
+    assert_eq!(rows[0].date_acquired, "01/01/2024");
+    assert_eq!(rows[0].date_sold, "06/01/2024");
+
+    let rows = disposal_rows(&disposal(
+      "2",
+      "200",
+      vec![lot("2024-01-01T00:00:00Z", "1", "50"), lot("2024-02-01T00:00:00Z", "1", "50")],
+    ));
+    assert_eq!(rows[0].date_acquired, "Various");
+  }
+
+  #[test]
+  fn schedule_d_totals() {
+    let mut rows = disposal_rows(&disposal(
+      "3",
+      "3000",
+      vec![lot("2022-01-01T00:00:00Z", "1", "500"), lot("2024-01-01T00:00:00Z", "2", "1600")],
+    ));
+    rows.extend(disposal_rows(&disposal("1", "100", vec![lot("2024-01-01T00:00:00Z", "1", "250")])));
+
+    let lines = schedule_d(&rows);
+    let line = |number: &str| lines.iter().find(|x| x.tax_year == 2024 && x.line == number).unwrap();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(line("3").proceeds, Some(Decimal::from(2100)));
+    assert_eq!(line("3").cost_basis, Some(Decimal::from(1850)));
+    assert_eq!(line("7").gain_or_loss, Decimal::from(250));
+    assert_eq!(line("10").proceeds, Some(Decimal::from(1000)));
+    assert_eq!(line("15").gain_or_loss, Decimal::from(500));
+    assert_eq!(line("16").gain_or_loss, Decimal::from(750));
+  }
+}
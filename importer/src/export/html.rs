@@ -0,0 +1,238 @@
+//! Self-contained static HTML report of the portfolio.
+//!
+//! The report embeds its styles and uses no scripts or external assets, so it
+//! can be opened from disk or mailed as a single file. Holdings are marked at
+//! the latest price of the [`PriceStore`], which is also what the unrealized
+//! PnL is measured against.
+
+use crate::category::{configured_description, read_category_config};
+use crate::portfolio::Portfolio;
+use crate::price::PriceStore;
+use crate::{Transaction, TransactionCategory};
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em auto;max-width:70em;color:#222}\
+h1,h2{font-weight:600}table{border-collapse:collapse;width:100%;margin-bottom:2em}\
+th,td{padding:.3em .6em;border-bottom:1px solid #ddd;text-align:left}\
+td.n,th.n{text-align:right;font-variant-numeric:tabular-nums}\
+.neg{color:#b00}.pos{color:#070}.bar{background:#4a7bd0;height:.8em}\
+.cards{display:flex;gap:1em;margin-bottom:2em}.card{border:1px solid #ddd;padding:.8em 1.2em;border-radius:4px}\
+.card b{display:block;font-size:1.3em}code{font-size:.85em}";
+
+/// Escapes text for use in HTML content and attributes.
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Formats a USD amount with two decimals.
+fn usd(amount: Decimal) -> String {
+  format!("{:.2}", amount)
+}
+
+/// Formats a token amount with at most six decimals.
+fn amount(amount: Decimal) -> String {
+  amount.round_dp(6).normalize().to_string()
+}
+
+/// Table cell of a signed USD amount, coloured by its sign.
+fn pnl_cell(pnl: Decimal) -> String {
+  let class = if pnl.is_sign_negative() && !pnl.is_zero() { "n neg" } else { "n pos" };
+  format!("<td class=\"{}\">{}</td>", class, usd(pnl))
+}
+
+/// Renders the report of the classified transactions and the lots `portfolio`
+/// holds after replaying them.
+pub fn to_html(transactions: &[Transaction], portfolio: &Portfolio, prices: &PriceStore) -> String {
+    let holdings = portfolio.holdings();
+    let mut html = String::new();
+
+    // Holdings marked at the last price seen; debt lots carry a negative cost
+    // basis and count against the total
+    let mut rows = vec![];
+    for item in &holdings {
+      let underlying = item.token.asset.trim_start_matches("Debt ");
+      let price = if item.token.is_nft() { None } else { prices.latest(underlying) };
+      let sign = if item.token.is_debt { -Decimal::ONE } else { Decimal::ONE };
+      let value = price.map(|x| sign * x * item.amount);
+      rows.push((item, item.cost_basis, price, value));
+    }
+    let cost_basis: Decimal = rows.iter().map(|x| x.1).sum();
+    let market_value: Decimal = rows.iter().filter_map(|x| x.3).sum();
+    let marked_cost: Decimal = rows.iter().filter(|x| x.3.is_some()).map(|x| x.1).sum();
+    let realized: Decimal = portfolio.disposals.iter().filter(|x| !x.token.is_debt).map(|x| x.pnl).sum();
+    let unknown: Vec<&Transaction> = transactions.iter().filter(|x| x.category == TransactionCategory::Unknown).collect();
+
+    writeln!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">").unwrap();
+    writeln!(html, "<title>Portfolio report</title>\n<style>{}</style>\n</head>\n<body>", STYLE).unwrap();
+    writeln!(html, "<h1>Portfolio report</h1>").unwrap();
+    if let (Some(first), Some(last)) = (transactions.iter().map(|x| &x.datetime).min(), transactions.iter().map(|x| &x.datetime).max()) {
+      writeln!(html, "<p>{} transactions from {} to {} (UTC).</p>", transactions.len(), escape(first), escape(last)).unwrap();
+    }
+    writeln!(html, "<div class=\"cards\">").unwrap();
+    for (label, value) in [
+      ("Market value", usd(market_value)),
+      ("Cost basis", usd(cost_basis)),
+      ("Unrealized PnL", usd(market_value - marked_cost)),
+      ("Realized PnL", usd(realized)),
+      ("Unknown transactions", unknown.len().to_string()),
+    ] {
+      writeln!(html, "<div class=\"card\">{}<b>{}</b></div>", label, value).unwrap();
+    }
+    writeln!(html, "</div>").unwrap();
+
+    writeln!(html, "<h2>Holdings</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><th>Asset</th><th>Symbol</th><th class=\"n\">Amount</th><th class=\"n\">Cost basis</th><th class=\"n\">Price</th><th class=\"n\">Market value</th><th class=\"n\">Unrealized PnL</th></tr>").unwrap();
+    for (item, cost_basis, price, value) in &rows {
+      let asset = match &item.token.token_id {
+        Some(token_id) => format!("{} #{}", item.token.asset, token_id),
+        None => item.token.asset.clone(),
+      };
+      write!(
+        html,
+        "<tr><td>{}</td><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>",
+        escape(&asset),
+        escape(&item.token.symbol),
+        amount(item.amount),
+        usd(*cost_basis),
+        price.map(usd).unwrap_or_default(),
+        value.map(usd).unwrap_or_default(),
+      ).unwrap();
+      match value {
+        Some(value) => writeln!(html, "{}</tr>", pnl_cell(value - cost_basis)).unwrap(),
+        None => writeln!(html, "<td></td></tr>").unwrap(),
+      }
+    }
+    writeln!(html, "</table>").unwrap();
+
+    // Allocation of the long positions by underlying asset
+    let mut allocation: BTreeMap<String, Decimal> = BTreeMap::new();
+    for (item, _, _, value) in &rows {
+      if let Some(value) = value.filter(|x| x.is_sign_positive()) {
+        *allocation.entry(item.token.asset.clone()).or_default() += value;
+      }
+    }
+    let gross: Decimal = allocation.values().sum();
+    let mut allocation: Vec<(String, Decimal)> = allocation.into_iter().collect();
+    allocation.sort_by_key(|x| Reverse(x.1));
+    writeln!(html, "<h2>Allocation by underlying asset</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><th>Asset</th><th class=\"n\">Market value</th><th class=\"n\">Share</th><th style=\"width:40%\"></th></tr>").unwrap();
+    for (asset, value) in allocation {
+      let share = if gross.is_zero() { Decimal::ZERO } else { value / gross * Decimal::ONE_HUNDRED };
+      writeln!(
+        html,
+        "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{:.1}%</td><td><div class=\"bar\" style=\"width:{:.1}%\"></div></td></tr>",
+        escape(&asset), usd(value), share, share,
+      ).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    // Realized PnL per asset
+    let mut realized: BTreeMap<&str, (usize, Decimal, Decimal, Decimal)> = BTreeMap::new();
+    for disposal in portfolio.disposals.iter().filter(|x| !x.token.is_debt) {
+      let entry = realized.entry(&disposal.token.asset).or_default();
+      entry.0 += 1;
+      entry.1 += disposal.sale_price;
+      entry.2 += disposal.cost_basis;
+      entry.3 += disposal.pnl;
+    }
+    writeln!(html, "<h2>Realized PnL</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><th>Asset</th><th class=\"n\">Disposals</th><th class=\"n\">Proceeds</th><th class=\"n\">Cost basis</th><th class=\"n\">PnL</th></tr>").unwrap();
+    for (asset, (count, proceeds, cost_basis, pnl)) in realized {
+      writeln!(
+        html,
+        "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>{}</tr>",
+        escape(asset), count, usd(proceeds), usd(cost_basis), pnl_cell(pnl),
+      ).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    // Transactions and USD volume per category
+    let mut categories: BTreeMap<String, (usize, Decimal)> = BTreeMap::new();
+    for transaction in transactions {
+      let entry = categories.entry(transaction.category.name().to_string()).or_default();
+      entry.0 += 1;
+      entry.1 += transaction.net_transfers.iter().filter_map(|x| x.usd_value).sum::<Decimal>();
+    }
+    writeln!(html, "<h2>Categories</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><th>Category</th><th class=\"n\">Transactions</th><th class=\"n\">USD volume</th></tr>").unwrap();
+    for (category, (count, volume)) in categories {
+      writeln!(html, "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td></tr>", escape(&category), count, usd(volume)).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    // Activity per protocol, from the descriptions in categories.toml
+    let config = read_category_config();
+    let mut protocols: BTreeMap<String, (usize, Decimal, String, String, BTreeSet<String>)> = BTreeMap::new();
+    for transaction in transactions {
+      let protocol = configured_description(&transaction.net_transfers, &config).unwrap_or_else(|| String::from("(not configured)"));
+      let entry = protocols.entry(protocol).or_default();
+      entry.0 += 1;
+      entry.1 += transaction.net_transfers.iter().filter_map(|x| x.usd_value).sum::<Decimal>();
+      if entry.2.is_empty() || transaction.datetime < entry.2 {
+        entry.2 = transaction.datetime.clone();
+      }
+      if transaction.datetime > entry.3 {
+        entry.3 = transaction.datetime.clone();
+      }
+      entry.4.insert(transaction.category.name().to_string());
+    }
+    writeln!(html, "<h2>Protocol activity</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><th>Protocol</th><th class=\"n\">Transactions</th><th class=\"n\">USD volume</th><th>First</th><th>Last</th><th>Categories</th></tr>").unwrap();
+    for (protocol, (count, volume, first, last, categories)) in protocols {
+      writeln!(
+        html,
+        "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        escape(&protocol), count, usd(volume), escape(&first), escape(&last), escape(&categories.into_iter().collect::<Vec<_>>().join(", ")),
+      ).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    writeln!(html, "<h2>Unknown transactions</h2>").unwrap();
+    if unknown.is_empty() {
+      writeln!(html, "<p>None.</p>").unwrap();
+    } else {
+      writeln!(html, "<table>\n<tr><th>Date</th><th>Transaction</th><th>Assets</th><th>Legs</th><th>Counterparties</th></tr>").unwrap();
+      let mut unknown = unknown;
+      unknown.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+      for transaction in unknown {
+        let legs: Vec<String> = transaction
+          .net_transfers
+          .iter()
+          .map(|x| format!("{:?} {} {}", x.direction, amount(x.value), x.token.symbol))
+          .collect();
+        let counterparties: BTreeSet<&String> = transaction.net_transfers.iter().flat_map(|x| &x.counterparty).collect();
+        writeln!(
+          html,
+          "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+          escape(&transaction.datetime),
+          escape(&transaction.transfer_id),
+          escape(&transaction.assets),
+          escape(&legs.join("; ")),
+          escape(&counterparties.into_iter().cloned().collect::<Vec<_>>().join(" ")),
+        ).unwrap();
+      }
+      writeln!(html, "</table>").unwrap();
+    }
+
+    writeln!(html, "</body>\n</html>").unwrap();
+    html
+}
+
+/// Writes the report rendered by [`to_html`] to `file_path`.
+pub fn write_html(
+    transactions: &[Transaction],
+    portfolio: &Portfolio,
+    prices: &PriceStore,
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(file_path, to_html(transactions, portfolio, prices))?)
+}
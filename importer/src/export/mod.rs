@@ -0,0 +1,6 @@
+//! Exports of the classified ledger into formats consumed by other tools.
+
+pub mod form8949;
+pub mod html;
+pub mod plaintext;
+pub mod tax_tools;
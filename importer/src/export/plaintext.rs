@@ -0,0 +1,359 @@
+//! Plain-text accounting export for Beancount and ledger-cli.
+//!
+//! Each classified transaction becomes one entry. Its legs post to an account
+//! derived from where the token is held (`Assets:Arbitrum:USDC`,
+//! `Assets:Coinbase:ETH`) or, for debt tokens, to `Liabilities:Aave:<asset>`.
+//! Commodities are the underlying assets the lot engine pools by. Legs that
+//! open or consume lots carry the lot's per-unit USD cost, and the remainder
+//! of the entry is left to an account derived from the category, e.g. the
+//! realised gain of a swap to `Income:CapitalGains`. Since the lot engine
+//! already decided which lots each disposal consumed, Beancount output turns
+//! lot matching off (`booking_method` `NONE`). Network fees are booked to
+//! `Expenses:Gas` at their USD value.
+
+use crate::portfolio::{LotMovement, Portfolio};
+use crate::token::read_token_config;
+use crate::{Chain, Fee, Token, Transaction, TransactionCategory, Transfer, TransferDirection};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+
+/// Plain-text accounting syntaxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlainText {
+  Beancount,
+  Ledger,
+}
+
+/// Commodity name valid in Beancount: uppercase, at most 24 characters,
+/// starting with a letter and ending with a letter or digit. ledger-cli needs
+/// the names with digits, `-`, `.` or `_` quoted, see [`PlainText::quote`].
+pub fn commodity(name: &str) -> String {
+  let mut commodity: String = name
+    .to_uppercase()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '-' })
+    .collect();
+  if !commodity.starts_with(|c: char| c.is_ascii_uppercase()) {
+    commodity.insert(0, 'X');
+  }
+  commodity.truncate(24);
+  while commodity.ends_with(|c: char| !c.is_ascii_alphanumeric()) {
+    commodity.pop();
+  }
+  commodity
+}
+
+/// Beancount string literal, with `"` and `\` escaped so that symbols of
+/// unregistered (often spam) tokens cannot end it early.
+pub fn string(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl PlainText {
+  /// Commodity as written in the syntax: ledger-cli only accepts unquoted
+  /// commodities made of letters.
+  pub fn quote(&self, commodity: &str) -> String {
+    match self {
+      PlainText::Ledger if !commodity.chars().all(|c| c.is_ascii_alphabetic()) => format!("\"{}\"", commodity),
+      _ => commodity.to_string(),
+    }
+  }
+}
+
+/// Account name component: letters, digits and dashes, starting with an
+/// uppercase letter or digit.
+fn component(name: &str) -> String {
+  let mut component: String = name
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+    .collect();
+  match component.chars().next() {
+    Some(c) if c.is_ascii_lowercase() => component.replace_range(..1, &c.to_ascii_uppercase().to_string()),
+    Some(c) if c.is_ascii_alphanumeric() => {},
+    _ => component.insert(0, 'X'),
+  }
+  component
+}
+
+/// Commodity of a token: its underlying asset, without the `Debt` prefix of
+/// debt tokens.
+fn token_commodity(token: &Token) -> String {
+  match token.is_debt {
+    true => commodity(token.asset.trim_start_matches("Debt ")),
+    false => commodity(&token.asset),
+  }
+}
+
+/// Account a transfer posts to.
+fn account(transfer: &Transfer) -> String {
+  if transfer.token.is_debt {
+    return format!("Liabilities:Aave:{}", token_commodity(&transfer.token));
+  }
+  let location = match transfer.chain {
+    Chain::Offchain => component(&transfer.account),
+    chain => component(chain.name()),
+  };
+  format!("Assets:{}:{}", location, component(&transfer.token.symbol))
+}
+
+/// Account receiving the remainder of an entry: realised gains, income,
+/// fees of own moves, or unexplained differences.
+fn remainder_account(transaction: &Transaction) -> &'static str {
+  let has = |direction: TransferDirection| transaction.net_transfers.iter().any(|x| x.direction == direction);
+  match transaction.category {
+    TransactionCategory::Swap(_) | TransactionCategory::Trade => "Income:CapitalGains",
+    TransactionCategory::Airdrop => "Income:Airdrop",
+    TransactionCategory::Income => "Income:Rewards",
+    TransactionCategory::Transfer | TransactionCategory::Bridge
+      if has(TransferDirection::Incoming) && has(TransferDirection::Outgoing) => "Expenses:Fees",
+    TransactionCategory::Transfer | TransactionCategory::Bridge => "Equity:Transfers",
+    TransactionCategory::Ignore | TransactionCategory::Unknown => "Equity:Unclassified",
+  }
+}
+
+/// Entry date in the syntax's format.
+fn date(datetime: &str, syntax: PlainText) -> String {
+  let date = datetime.get(..10).unwrap_or(datetime);
+  match syntax {
+    PlainText::Beancount => date.to_string(),
+    PlainText::Ledger => date.replace('-', "/"),
+  }
+}
+
+/// One posting line.
+struct Posting {
+  account: String,
+  amount: Decimal,
+  commodity: String,
+  /// Per-unit USD cost and acquisition date of the lot.
+  cost: Option<(Decimal, String)>,
+}
+
+impl Posting {
+  fn render(&self, syntax: PlainText) -> String {
+    let amount = format!("{} {}", self.amount.normalize(), syntax.quote(&self.commodity));
+    let cost = match (&self.cost, syntax) {
+      (None, _) => String::new(),
+      (Some((unit, acquired)), PlainText::Beancount) if !acquired.is_empty() => {
+        format!(" {{{} USD, {}}}", unit.normalize(), date(acquired, syntax))
+      },
+      (Some((unit, acquired)), PlainText::Ledger) if !acquired.is_empty() => {
+        format!(" {{{} USD}} [{}]", unit.normalize(), date(acquired, syntax))
+      },
+      (Some((unit, _)), _) => format!(" {{{} USD}}", unit.normalize()),
+    };
+    format!("  {}  {}{}", self.account, amount, cost)
+  }
+}
+
+/// Per-unit cost of a lot, always positive so debt lots are expressed as
+/// negative amounts at a positive cost.
+fn unit_cost(movement: &LotMovement) -> Decimal {
+  match movement.lot.amount.is_zero() {
+    true => Decimal::ZERO,
+    false => (movement.lot.cost_basis / movement.lot.amount).abs(),
+  }
+}
+
+/// Postings of one transfer: at cost from the lots it opened or consumed, or
+/// at face amount for own moves and legs that touched no lot.
+fn postings(transfer: &Transfer, movements: &[&LotMovement], at_cost: bool) -> Vec<Posting> {
+  let account = account(transfer);
+  let commodity = token_commodity(&transfer.token);
+  let sign = match (&transfer.direction, transfer.token.is_debt) {
+    (TransferDirection::Incoming, false) | (TransferDirection::Outgoing, true) => Decimal::ONE,
+    (TransferDirection::Incoming, true) | (TransferDirection::Outgoing, false) => -Decimal::ONE,
+  };
+
+  let lots: Vec<&&LotMovement> = movements
+    .iter()
+    .filter(|x| x.token == transfer.token && x.direction == transfer.direction)
+    .collect();
+  if !at_cost || lots.is_empty() {
+    return vec![Posting { account, amount: sign * transfer.value, commodity, cost: None }];
+  }
+
+  lots
+    .into_iter()
+    .map(|movement| Posting {
+      account: account.clone(),
+      amount: sign * movement.lot.amount,
+      commodity: commodity.clone(),
+      cost: Some((unit_cost(movement), movement.lot.datetime.clone())),
+    })
+    .collect()
+}
+
+/// Renders the classified transactions, with the lots `portfolio` opened and
+/// consumed while replaying them, as a Beancount or ledger-cli journal. Each
+/// network fee paid becomes an entry of its own booked to `Expenses:Gas`.
+pub fn to_plaintext(transactions: &[Transaction], portfolio: &Portfolio, fees: &[Fee], syntax: PlainText) -> String {
+    let mut movements: HashMap<&str, Vec<&LotMovement>> = HashMap::new();
+    for movement in &portfolio.journal {
+      movements.entry(&movement.transfer_id).or_default().push(movement);
+    }
+
+    let mut ordered: Vec<&Transaction> = transactions
+      .iter()
+      .filter(|x| x.category != TransactionCategory::Ignore && !x.net_transfers.is_empty())
+      .collect();
+    ordered.sort_by(|a, b| (&a.datetime, &a.transfer_id).cmp(&(&b.datetime, &b.transfer_id)));
+
+    let mut opened: BTreeMap<String, String> = BTreeMap::new();
+    let mut commodities: BTreeMap<String, String> = BTreeMap::new();
+    // Entries keyed by datetime and hash, fees after the transaction they paid for
+    let mut entries: Vec<((String, String, bool), String)> = vec![];
+
+    for transaction in ordered {
+      let mut entry = String::new();
+      let at_cost = !matches!(transaction.category, TransactionCategory::Transfer | TransactionCategory::Bridge);
+      let lots = movements.get(transaction.transfer_id.as_str()).map(|x| x.as_slice()).unwrap_or_default();
+      let postings: Vec<Posting> = transaction
+        .net_transfers
+        .iter()
+        .flat_map(|transfer| postings(transfer, lots, at_cost))
+        .collect();
+      let remainder = remainder_account(transaction);
+
+      for transfer in &transaction.net_transfers {
+        commodities.entry(token_commodity(&transfer.token)).or_insert_with(|| transfer.token.asset.trim_start_matches("Debt ").to_string());
+      }
+      for account in postings.iter().map(|x| x.account.as_str()).chain([remainder]) {
+        opened.entry(account.to_string()).or_insert_with(|| transaction.datetime.clone());
+      }
+
+      let date = date(&transaction.datetime, syntax);
+      let category = transaction.category.name();
+      match syntax {
+        PlainText::Beancount => {
+          writeln!(entry, "{} * \"{}\" {}", date, category, string(&transaction.assets)).unwrap();
+          writeln!(entry, "  transfer_id: {}", string(&transaction.transfer_id)).unwrap();
+        },
+        PlainText::Ledger => {
+          writeln!(entry, "{} * {} {}", date, category, transaction.assets).unwrap();
+          writeln!(entry, "  ; transfer_id: {}", transaction.transfer_id).unwrap();
+        },
+      }
+      for posting in &postings {
+        writeln!(entry, "{}", posting.render(syntax)).unwrap();
+      }
+      writeln!(entry, "  {}", remainder).unwrap();
+      writeln!(entry).unwrap();
+      entries.push(((transaction.datetime.clone(), transaction.transfer_id.clone(), false), entry));
+    }
+
+    for fee in fees.iter().filter(|x| !x.value.is_zero()) {
+      let account = format!("Assets:{}:ETH", component(fee.chain.name()));
+      let commodity = token_commodity(&fee.token);
+      commodities.entry(commodity.clone()).or_insert_with(|| fee.token.asset.clone());
+      let commodity = syntax.quote(&commodity);
+      for account in [account.as_str(), "Expenses:Gas"] {
+        opened.entry(account.to_string()).or_insert_with(|| fee.datetime.clone());
+      }
+
+      let mut entry = String::new();
+      let date = date(&fee.datetime, syntax);
+      match syntax {
+        PlainText::Beancount => {
+          writeln!(entry, "{} * \"Gas\" {}", date, string(&fee.counterparty)).unwrap();
+          writeln!(entry, "  transfer_id: {}", string(&fee.transfer_id)).unwrap();
+        },
+        PlainText::Ledger => {
+          writeln!(entry, "{} * Gas {}", date, fee.counterparty).unwrap();
+          writeln!(entry, "  ; transfer_id: {}", fee.transfer_id).unwrap();
+        },
+      }
+      match fee.usd_value {
+        Some(usd_value) => {
+          writeln!(entry, "  Expenses:Gas  {} USD", usd_value.normalize()).unwrap();
+          writeln!(entry, "  {}  -{} {} @ {} USD", account, fee.value.normalize(), commodity, (usd_value / fee.value).normalize()).unwrap();
+        },
+        None => {
+          writeln!(entry, "  Expenses:Gas  {} {}", fee.value.normalize(), commodity).unwrap();
+          writeln!(entry, "  {}  -{} {}", account, fee.value.normalize(), commodity).unwrap();
+        },
+      }
+      writeln!(entry).unwrap();
+      entries.push(((fee.datetime.clone(), fee.transfer_id.clone(), true), entry));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let entries: String = entries.into_iter().map(|x| x.1).collect();
+
+    // Commodities of the token registry not seen in any entry are declared
+    // too, described by their asset
+    for meta in read_token_config().values() {
+      let asset = meta.asset.trim_start_matches("Debt ");
+      commodities.entry(commodity(asset)).or_insert_with(|| asset.to_string());
+    }
+
+    let first_date = opened.values().min().cloned().unwrap_or_default();
+    let mut header = String::new();
+    match syntax {
+      PlainText::Beancount => {
+        writeln!(header, "option \"operating_currency\" \"USD\"").unwrap();
+        writeln!(header, "option \"booking_method\" \"NONE\"").unwrap();
+        writeln!(header).unwrap();
+        writeln!(header, "{} commodity USD", date(&first_date, syntax)).unwrap();
+        for (commodity, name) in &commodities {
+          writeln!(header, "{} commodity {}\n  name: {}", date(&first_date, syntax), commodity, string(name)).unwrap();
+        }
+        writeln!(header).unwrap();
+        for (account, datetime) in &opened {
+          writeln!(header, "{} open {}", date(datetime, syntax), account).unwrap();
+        }
+      },
+      PlainText::Ledger => {
+        writeln!(header, "commodity USD").unwrap();
+        for (commodity, name) in &commodities {
+          writeln!(header, "commodity {}\n  note {}", syntax.quote(commodity), name).unwrap();
+        }
+        writeln!(header).unwrap();
+        for account in opened.keys() {
+          writeln!(header, "account {}", account).unwrap();
+        }
+      },
+    }
+    writeln!(header).unwrap();
+
+    header + &entries
+}
+
+/// Writes the journal rendered by [`to_plaintext`] to `file_path`.
+pub fn write_plaintext(
+    transactions: &[Transaction],
+    portfolio: &Portfolio,
+    fees: &[Fee],
+    syntax: PlainText,
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(file_path, to_plaintext(transactions, portfolio, fees, syntax))?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn commodities_are_valid_beancount_names() {
+    assert_eq!(commodity("USDC.e"), "USDC.E");
+    assert_eq!(commodity("1INCH"), "X1INCH");
+    assert_eq!(commodity("Uniswap V3 Position"), "UNISWAP-V3-POSITION");
+  }
+
+  #[test]
+  fn beancount_strings_are_escaped() {
+    assert_eq!(string("WETH|USDC"), "\"WETH|USDC\"");
+    assert_eq!(string("Visit \"claim.xyz\" \\o/"), "\"Visit \\\"claim.xyz\\\" \\\\o/\"");
+  }
+
+  #[test]
+  fn ledger_quotes_commodities_beyond_letters() {
+    assert_eq!(PlainText::Ledger.quote("ETH"), "ETH");
+    assert_eq!(PlainText::Ledger.quote("USDC-E"), "\"USDC-E\"");
+    assert_eq!(PlainText::Ledger.quote("X1INCH"), "\"X1INCH\"");
+    assert_eq!(PlainText::Beancount.quote("USDC.E"), "USDC.E");
+  }
+}
@@ -0,0 +1,323 @@
+//! Universal CSV imports of Koinly, CoinTracker and CoinLedger.
+//!
+//! These tools expect one row per exchange of a sent and a received amount, so
+//! the outgoing and incoming legs of trades are paired up in order and unpaired
+//! legs become deposit or withdrawal rows. Legs of any other transaction, such
+//! as a bridge or a move between our own accounts, are never exchanged for one
+//! another and each get a row of their own. Aave debt tokens are
+//! unknown to them and are left out; the borrowed or repaid asset carries the
+//! loan label instead. Ignored transactions are skipped.
+
+use crate::ingest::DATETIME_FORMAT;
+use crate::{
+  Chain, DebtDirection, SwapSubCategory, Transaction, TransactionCategory, Transfer, TransferDirection,
+};
+use chrono::NaiveDateTime;
+use itertools::{EitherOrBoth, Itertools};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Tax tool label of a transaction, derived from its category.
+pub enum Kind {
+  /// Swaps and trades.
+  Trade,
+  /// Aave borrows.
+  Borrow,
+  /// Aave repayments.
+  Repay,
+  Airdrop,
+  /// Rewards, interest and other income.
+  Income,
+  /// Moves between our own accounts or chains, and anything unclassified.
+  Transfer,
+}
+
+impl From<&TransactionCategory> for Kind {
+    fn from(category: &TransactionCategory) -> Self {
+      match category {
+        TransactionCategory::Swap(SwapSubCategory::Debt(debt)) => match debt.direction {
+          DebtDirection::Borrow => Kind::Borrow,
+          DebtDirection::Repayment => Kind::Repay,
+          DebtDirection::Unknown => Kind::Trade,
+        },
+        TransactionCategory::Swap(_) | TransactionCategory::Trade => Kind::Trade,
+        TransactionCategory::Airdrop => Kind::Airdrop,
+        TransactionCategory::Income => Kind::Income,
+        _ => Kind::Transfer,
+      }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Amount sent and/or received within a transaction.
+pub struct Entry {
+  pub transfer_id: String,
+  pub datetime: NaiveDateTime,
+  pub kind: Kind,
+  /// Chain or exchange account the amounts moved on.
+  pub platform: String,
+  pub sent: Option<(Decimal, String)>,
+  pub received: Option<(Decimal, String)>,
+  /// USD value of the received amount, or of the sent amount when nothing was received.
+  pub usd_value: Option<Decimal>,
+}
+
+/// Currency code of a leg: the token symbol, with the token ID for NFTs.
+fn currency(transfer: &Transfer) -> String {
+  match &transfer.token.token_id {
+    Some(token_id) => format!("{}#{}", transfer.token.symbol, token_id),
+    None => transfer.token.symbol.clone(),
+  }
+}
+
+/// Entries of every transaction, in chronological order.
+pub fn entries(transactions: &[Transaction]) -> Vec<Entry> {
+  let mut entries = vec![];
+  let ordered = transactions.iter().sorted_by(|a, b| (&a.datetime, &a.transfer_id).cmp(&(&b.datetime, &b.transfer_id)));
+  for transaction in ordered {
+    if transaction.category == TransactionCategory::Ignore {
+      continue;
+    }
+    let Ok(datetime) = NaiveDateTime::parse_from_str(&transaction.datetime, DATETIME_FORMAT) else { continue };
+
+    let kind = Kind::from(&transaction.category);
+    let legs = transaction.net_transfers.iter().filter(|x| !x.token.is_debt);
+    let (outgoing, incoming): (Vec<&Transfer>, Vec<&Transfer>) = legs.partition(|x| x.direction == TransferDirection::Outgoing);
+
+    let pairs: Vec<(Option<&Transfer>, Option<&Transfer>)> = match kind {
+      Kind::Trade => outgoing
+        .iter()
+        .zip_longest(incoming.iter())
+        .map(|pair| match pair {
+          EitherOrBoth::Both(sent, received) => (Some(*sent), Some(*received)),
+          EitherOrBoth::Left(sent) => (Some(*sent), None),
+          EitherOrBoth::Right(received) => (None, Some(*received)),
+        })
+        .collect(),
+      _ => outgoing.iter().map(|x| (Some(*x), None)).chain(incoming.iter().map(|x| (None, Some(*x)))).collect(),
+    };
+
+    for (sent, received) in pairs {
+      let leg = received.or(sent).unwrap();
+      entries.push(Entry {
+        transfer_id: transaction.transfer_id.clone(),
+        datetime,
+        kind,
+        platform: match leg.chain {
+          Chain::Offchain => leg.account.clone(),
+          chain => chain.name().to_string(),
+        },
+        sent: sent.map(|x| (x.value, currency(x))),
+        received: received.map(|x| (x.value, currency(x))),
+        usd_value: leg.usd_value,
+      });
+    }
+  }
+  entries
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of the Koinly universal CSV.
+pub struct KoinlyRow {
+  #[serde(rename = "Date")]
+  pub date: String,
+  #[serde(rename = "Sent Amount")]
+  pub sent_amount: Option<Decimal>,
+  #[serde(rename = "Sent Currency")]
+  pub sent_currency: Option<String>,
+  #[serde(rename = "Received Amount")]
+  pub received_amount: Option<Decimal>,
+  #[serde(rename = "Received Currency")]
+  pub received_currency: Option<String>,
+  #[serde(rename = "Fee Amount")]
+  pub fee_amount: Option<Decimal>,
+  #[serde(rename = "Fee Currency")]
+  pub fee_currency: Option<String>,
+  #[serde(rename = "Net Worth Amount")]
+  pub net_worth_amount: Option<Decimal>,
+  #[serde(rename = "Net Worth Currency")]
+  pub net_worth_currency: Option<String>,
+  #[serde(rename = "Label")]
+  pub label: String,
+  #[serde(rename = "Description")]
+  pub description: String,
+  #[serde(rename = "TxHash")]
+  pub tx_hash: String,
+}
+
+impl From<&Entry> for KoinlyRow {
+    fn from(entry: &Entry) -> Self {
+      let label = match entry.kind {
+        Kind::Borrow => "loan",
+        Kind::Repay => "loan repayment",
+        Kind::Airdrop => "airdrop",
+        Kind::Income => "reward",
+        Kind::Trade | Kind::Transfer => "",
+      };
+      KoinlyRow {
+        date: entry.datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        sent_amount: entry.sent.as_ref().map(|x| x.0),
+        sent_currency: entry.sent.as_ref().map(|x| x.1.clone()),
+        received_amount: entry.received.as_ref().map(|x| x.0),
+        received_currency: entry.received.as_ref().map(|x| x.1.clone()),
+        fee_amount: None,
+        fee_currency: None,
+        net_worth_amount: entry.usd_value,
+        net_worth_currency: entry.usd_value.map(|_| String::from("USD")),
+        label: label.to_string(),
+        description: entry.platform.clone(),
+        tx_hash: entry.transfer_id.clone(),
+      }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of the CoinTracker CSV import.
+pub struct CoinTrackerRow {
+  #[serde(rename = "Date")]
+  pub date: String,
+  #[serde(rename = "Received Quantity")]
+  pub received_quantity: Option<Decimal>,
+  #[serde(rename = "Received Currency")]
+  pub received_currency: Option<String>,
+  #[serde(rename = "Sent Quantity")]
+  pub sent_quantity: Option<Decimal>,
+  #[serde(rename = "Sent Currency")]
+  pub sent_currency: Option<String>,
+  #[serde(rename = "Fee Amount")]
+  pub fee_amount: Option<Decimal>,
+  #[serde(rename = "Fee Currency")]
+  pub fee_currency: Option<String>,
+  #[serde(rename = "Tag")]
+  pub tag: String,
+}
+
+/// CoinTracker has no loan tags; untagged receipts and sends are treated as
+/// non-taxable deposits and withdrawals, which is how borrows and repayments
+/// are reported. Other income uses the `staked` tag, its income tag for rewards.
+impl From<&Entry> for CoinTrackerRow {
+    fn from(entry: &Entry) -> Self {
+      CoinTrackerRow {
+        date: entry.datetime.format("%m/%d/%Y %H:%M:%S").to_string(),
+        received_quantity: entry.received.as_ref().map(|x| x.0),
+        received_currency: entry.received.as_ref().map(|x| x.1.clone()),
+        sent_quantity: entry.sent.as_ref().map(|x| x.0),
+        sent_currency: entry.sent.as_ref().map(|x| x.1.clone()),
+        fee_amount: None,
+        fee_currency: None,
+        tag: match entry.kind {
+          Kind::Airdrop => String::from("airdrop"),
+          Kind::Income => String::from("staked"),
+          _ => String::new(),
+        },
+      }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of the CoinLedger universal manual import.
+pub struct CoinLedgerRow {
+  #[serde(rename = "Date (UTC)")]
+  pub date: String,
+  #[serde(rename = "Platform (Optional)")]
+  pub platform: String,
+  #[serde(rename = "Asset Sent")]
+  pub asset_sent: Option<String>,
+  #[serde(rename = "Amount Sent")]
+  pub amount_sent: Option<Decimal>,
+  #[serde(rename = "Asset Received")]
+  pub asset_received: Option<String>,
+  #[serde(rename = "Amount Received")]
+  pub amount_received: Option<Decimal>,
+  #[serde(rename = "Fee Currency (Optional)")]
+  pub fee_currency: Option<String>,
+  #[serde(rename = "Fee Amount (Optional)")]
+  pub fee_amount: Option<Decimal>,
+  #[serde(rename = "Type")]
+  pub kind: String,
+  #[serde(rename = "Description (Optional)")]
+  pub description: String,
+  #[serde(rename = "TxHash (Optional)")]
+  pub tx_hash: String,
+}
+
+/// CoinLedger has no loan types either: borrows and repayments are deposits
+/// and withdrawals described as such, and exchanges of two assets are trades.
+impl From<&Entry> for CoinLedgerRow {
+    fn from(entry: &Entry) -> Self {
+      let (kind, description) = match (entry.kind, &entry.sent, &entry.received) {
+        (Kind::Airdrop, _, Some(_)) => ("Airdrop", ""),
+        (Kind::Income, _, Some(_)) => ("Income", ""),
+        (Kind::Borrow, _, _) => ("Deposit", "Aave borrow"),
+        (Kind::Repay, _, _) => ("Withdrawal", "Aave repayment"),
+        (_, Some(_), Some(_)) => ("Trade", ""),
+        (_, None, _) => ("Deposit", ""),
+        (_, Some(_), None) => ("Withdrawal", ""),
+      };
+      CoinLedgerRow {
+        date: entry.datetime.format("%m/%d/%Y %H:%M:%S").to_string(),
+        platform: entry.platform.clone(),
+        asset_sent: entry.sent.as_ref().map(|x| x.1.clone()),
+        amount_sent: entry.sent.as_ref().map(|x| x.0),
+        asset_received: entry.received.as_ref().map(|x| x.1.clone()),
+        amount_received: entry.received.as_ref().map(|x| x.0),
+        fee_currency: None,
+        fee_amount: None,
+        kind: kind.to_string(),
+        description: description.to_string(),
+        tx_hash: entry.transfer_id.clone(),
+      }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Token;
+
+  fn transfer(direction: TransferDirection, symbol: &str, chain: Chain) -> Transfer {
+    Transfer {
+      transfer_id: String::from("0xabc"),
+      datetime: String::from("2024-01-01 00:00:00"),
+      token: Token { symbol: symbol.to_string(), ..Token::default() },
+      value: Decimal::ONE,
+      usd_value: None,
+      direction,
+      counterparty: vec![],
+      account: String::from("0xme"),
+      chain,
+    }
+  }
+
+  fn transaction(category: TransactionCategory) -> Transaction {
+    Transaction {
+      transfer_id: String::from("0xabc"),
+      datetime: String::from("2024-01-01 00:00:00"),
+      category,
+      net_transfers: vec![
+        transfer(TransferDirection::Outgoing, "ETH", Chain::Ethereum),
+        transfer(TransferDirection::Incoming, "ETH", Chain::Arbitrum),
+      ],
+      assets: String::new(),
+    }
+  }
+
+  #[test]
+  fn pairs_trade_legs() {
+    let entries = entries(&[transaction(TransactionCategory::Trade)]);
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].sent.is_some() && entries[0].received.is_some());
+  }
+
+  #[test]
+  fn keeps_transfer_legs_apart() {
+    let entries = entries(&[transaction(TransactionCategory::Bridge)]);
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[0].platform.as_str(), entries[0].received.is_none()), ("ethereum", true));
+    assert_eq!((entries[1].platform.as_str(), entries[1].sent.is_none()), ("arbitrum", true));
+
+    let row = CoinLedgerRow::from(&entries[0]);
+    assert_eq!(row.kind, "Withdrawal");
+  }
+}
@@ -0,0 +1,398 @@
+//! Gas and fee analytics.
+//!
+//! Network fees are totalled by month, by the category of the transaction that
+//! paid them and by protocol, the `description` of the matching
+//! `data/ref/categories.toml` entry (looked up by hash, then by the contract
+//! called). Fees of transactions that moved no tokens (approvals, cancelled
+//! orders) fall back to the category configured for the contract, or
+//! `(no transfers)`. Fees without a USD value are valued at the day's ETH price.
+//!
+//! GMX orders also cost an execution fee, sent in ETH along with the call that
+//! creates the order and partly refunded by the keeper transaction executing
+//! it, and price impact, which the GMX trade history reports per order. The
+//! ETH a call sends is its execution fee unless the call may also have
+//! deposited ETH as collateral: it created an increase order (a limit order
+//! in the call, or a market increase the keeper executed within a minute) and
+//! sent no token to pay for it. Refunds are the ETH received in the keeper
+//! transactions of the trade history, unless the orders executed there can
+//! pay ETH collateral out: decreases, withdrawals, and failed or cancelled
+//! increases that paid out no token. The ETH of calls and keeper transactions
+//! that cannot be told apart is left out and logged.
+
+use crate::category::{read_category_config, CategoryConfig, CategoryMapping};
+use crate::ingest::gmx::GmxTrade;
+use crate::ingest::DATETIME_FORMAT;
+use crate::price::{parse_date, PriceStore};
+use crate::{Fee, Transaction, Transfer, TransferDirection};
+use chrono::NaiveDateTime;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Protocol label of GMX in `data/ref/categories.toml`.
+pub const GMX: &str = "GMX";
+
+/// Seconds within which a keeper executes a market order after its creation.
+const KEEPER_DELAY: i64 = 60;
+
+/// Upper bounds in USD of the order size buckets.
+const SIZE_BUCKETS: [u32; 4] = [100, 1_000, 10_000, 100_000];
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Gas spent by a group of transactions.
+pub struct GasSummary {
+  pub key: String,
+  pub transactions: usize,
+  pub gas_eth: Decimal,
+  pub gas_usd: Decimal,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// GMX trading costs of a month or order size bucket.
+pub struct GmxSummary {
+  pub key: String,
+  /// Executed position changes.
+  pub trades: usize,
+  /// USD size of the executed position changes.
+  pub volume_usd: Decimal,
+  /// Price impact paid (negative) or received (positive).
+  pub price_impact_usd: Decimal,
+  /// Execution fees sent with orders, net of keeper refunds.
+  pub execution_fee_eth: Decimal,
+  pub execution_fee_usd: Decimal,
+  /// Gas of the transactions sent to GMX.
+  pub gas_usd: Decimal,
+  /// Realised PnL reported by GMX.
+  pub pnl_usd: Decimal,
+}
+
+impl GmxSummary {
+  /// Execution fees, gas and price impact paid, as a share of the volume traded.
+  pub fn cost_share(&self) -> Option<Decimal> {
+    let cost = self.execution_fee_usd + self.gas_usd - self.price_impact_usd;
+    (!self.volume_usd.is_zero()).then(|| cost / self.volume_usd)
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+/// Gas totals by month, category and protocol, and GMX costs by month and order size.
+pub struct GasReport {
+  pub by_month: Vec<GasSummary>,
+  pub by_category: Vec<GasSummary>,
+  pub by_protocol: Vec<GasSummary>,
+  pub gmx_by_month: Vec<GmxSummary>,
+  pub gmx_by_size: Vec<GmxSummary>,
+}
+
+/// Month of a normalized datetime, e.g. `2024-07`.
+fn month(datetime: &str) -> String {
+  datetime.get(..7).unwrap_or(datetime).to_string()
+}
+
+/// USD value of an ETH amount on the day of `datetime`.
+fn eth_usd(prices: &PriceStore, datetime: &str, value: Decimal) -> Decimal {
+  parse_date(datetime).and_then(|x| prices.price("ETH", x)).map(|x| x * value).unwrap_or_default()
+}
+
+/// Label of the first configured key, the description or the category name.
+fn configured<'a, F>(config: &'a CategoryConfig, keys: &[&String], label: F) -> Option<String>
+where
+    F: Fn(&'a CategoryMapping) -> Option<String>,
+{
+  keys.iter().find_map(|key| config.get(*key)).and_then(label)
+}
+
+/// Adds a fee to the summary of `key`.
+fn add(groups: &mut BTreeMap<String, GasSummary>, key: String, fee: &Fee, usd: Decimal) {
+  let summary = groups.entry(key.clone()).or_insert_with(|| GasSummary { key, ..Default::default() });
+  summary.transactions += 1;
+  summary.gas_eth += fee.value;
+  summary.gas_usd += usd;
+}
+
+/// Size bucket label of an order, e.g. `<$1000`.
+fn size_bucket(size: Decimal) -> String {
+  SIZE_BUCKETS
+    .iter()
+    .find(|x| size.abs() < Decimal::from(**x))
+    .map(|x| format!("<${}", x))
+    .unwrap_or_else(|| format!(">=${}", SIZE_BUCKETS[SIZE_BUCKETS.len() - 1]))
+}
+
+/// Hashes of the transactions in which we paid (`Outgoing`) or received
+/// (`Incoming`) tokens other than ETH.
+fn token_legs(transactions: &[Transaction], direction: TransferDirection) -> HashSet<&str> {
+  transactions
+    .iter()
+    .filter(|x| x.net_transfers.iter().any(|x| x.direction == direction && x.token.address != "ETH"))
+    .map(|x| x.transfer_id.as_str())
+    .collect()
+}
+
+/// Whether a GMX call may have deposited ETH collateral along with its
+/// execution fee: it created an increase order and sent no token to pay for it.
+fn may_deposit_eth(fee: &Fee, trades: &[GmxTrade], token_payments: &HashSet<&str>) -> bool {
+  let Ok(sent) = NaiveDateTime::parse_from_str(&fee.datetime, DATETIME_FORMAT) else { return true };
+  let executed_after = |trade: &GmxTrade| {
+    NaiveDateTime::parse_from_str(&trade.datetime, DATETIME_FORMAT)
+      .is_ok_and(|x| (0..=KEEPER_DELAY).contains(&(x - sent).num_seconds()))
+  };
+  let creates_increase = trades.iter().filter(|x| x.is_increase()).any(|x| match x.transfer_id == fee.transfer_id {
+    true => x.action.starts_with("Create "),
+    false => matches!(x.action.as_str(), "Market Increase" | "Failed Market Increase" | "Deposit") && executed_after(x),
+  });
+  creates_increase && !token_payments.contains(fee.transfer_id.as_str())
+}
+
+/// ETH received in the keeper transactions executing our orders, those of the
+/// trade history we paid no fee for. Also returns how many keeper transactions
+/// were left out because an order executed there may have paid ETH collateral
+/// out instead: it can pay out and no token was paid out with it.
+fn keeper_refunds<'a>(fees: &[Fee], transactions: &'a [Transaction], trades: &[GmxTrade]) -> (Vec<&'a Transfer>, usize) {
+  let fee_hashes: HashSet<&str> = fees.iter().map(|x| x.transfer_id.as_str()).collect();
+  let token_payouts = token_legs(transactions, TransferDirection::Incoming);
+  let mut keeper: HashMap<&str, Vec<&GmxTrade>> = HashMap::new();
+  for trade in trades.iter().filter(|x| !fee_hashes.contains(x.transfer_id.as_str())) {
+    keeper.entry(trade.transfer_id.as_str()).or_default().push(trade);
+  }
+
+  let mut refunds = vec![];
+  let mut payouts = 0;
+  for transaction in transactions {
+    let Some(orders) = keeper.get(transaction.transfer_id.as_str()) else { continue };
+    let received: Vec<&Transfer> = transaction
+      .net_transfers
+      .iter()
+      .filter(|x| x.direction == TransferDirection::Incoming && x.token.address == "ETH")
+      .collect();
+    if received.is_empty() {
+      continue;
+    }
+    match orders.iter().any(|x| x.pays_out()) && !token_payouts.contains(transaction.transfer_id.as_str()) {
+      true => payouts += 1,
+      false => refunds.extend(received),
+    }
+  }
+  (refunds, payouts)
+}
+
+/// Shares the execution fees and gas of all months out over the size buckets
+/// in proportion to their number of trades, since neither is tied to an order.
+fn spread_over_sizes(months: &BTreeMap<String, GmxSummary>, sizes: &mut BTreeMap<String, GmxSummary>) {
+  let trades: usize = months.values().map(|x| x.trades).sum();
+  if trades == 0 {
+    return;
+  }
+  let count = Decimal::from(trades);
+  let execution_fee_eth: Decimal = months.values().map(|x| x.execution_fee_eth).sum();
+  let execution_fee_usd: Decimal = months.values().map(|x| x.execution_fee_usd).sum();
+  let gas_usd: Decimal = months.values().map(|x| x.gas_usd).sum();
+  for summary in sizes.values_mut() {
+    let share = Decimal::from(summary.trades) / count;
+    summary.execution_fee_eth = execution_fee_eth * share;
+    summary.execution_fee_usd = execution_fee_usd * share;
+    summary.gas_usd = gas_usd * share;
+  }
+}
+
+/// Builds the gas and GMX cost report.
+pub fn gas_report(fees: &[Fee], transactions: &[Transaction], trades: &[GmxTrade], prices: &PriceStore) -> GasReport {
+    let config = read_category_config();
+    let by_hash: HashMap<&str, &Transaction> = transactions.iter().map(|x| (x.transfer_id.as_str(), x)).collect();
+
+    let mut by_month = BTreeMap::new();
+    let mut by_category = BTreeMap::new();
+    let mut by_protocol = BTreeMap::new();
+    let mut gmx_months: BTreeMap<String, GmxSummary> = BTreeMap::new();
+    let gmx = |key: String| -> GmxSummary { GmxSummary { key, ..Default::default() } };
+
+    let token_payments = token_legs(transactions, TransferDirection::Outgoing);
+    let mut deposits = 0;
+
+    for fee in fees {
+      let usd = fee.usd_value.unwrap_or_else(|| eth_usd(prices, &fee.datetime, fee.value));
+      let keys = [&fee.transfer_id, &fee.counterparty];
+      let transaction = by_hash.get(fee.transfer_id.as_str());
+
+      let category = match transaction {
+        Some(transaction) => transaction.category.name().to_string(),
+        None => configured(&config, &keys, |x| Some(x.category.clone())).unwrap_or_else(|| String::from("(no transfers)")),
+      };
+      let protocol = configured(&config, &keys, |x| x.description.clone()).unwrap_or_else(|| String::from("(not configured)"));
+
+      add(&mut by_month, month(&fee.datetime), fee, usd);
+      add(&mut by_category, category, fee, usd);
+      add(&mut by_protocol, protocol.clone(), fee, usd);
+
+      if protocol == GMX {
+        let summary = gmx_months.entry(month(&fee.datetime)).or_insert_with(|| gmx(month(&fee.datetime)));
+        summary.gas_usd += usd;
+        if fee.value_sent.is_zero() {
+          continue;
+        }
+        if may_deposit_eth(fee, trades, &token_payments) {
+          deposits += 1;
+          continue;
+        }
+        summary.execution_fee_eth += fee.value_sent;
+        summary.execution_fee_usd += eth_usd(prices, &fee.datetime, fee.value_sent);
+      }
+    }
+    if deposits > 0 {
+      warn!("left {} GMX calls that may have deposited ETH collateral out of execution fees", deposits);
+    }
+
+    let (refunds, payouts) = keeper_refunds(fees, transactions, trades);
+    for transfer in refunds {
+      let summary = gmx_months.entry(month(&transfer.datetime)).or_insert_with(|| gmx(month(&transfer.datetime)));
+      summary.execution_fee_eth -= transfer.value;
+      summary.execution_fee_usd -= transfer.usd_value.unwrap_or_else(|| eth_usd(prices, &transfer.datetime, transfer.value));
+    }
+    if payouts > 0 {
+      warn!("left {} GMX keeper transactions that may have paid out ETH collateral out of refunds", payouts);
+    }
+
+    let mut gmx_sizes: BTreeMap<String, GmxSummary> = BTreeMap::new();
+    for trade in trades.iter().filter(|x| x.is_executed()) {
+      let size = trade.size_usd.unwrap_or_default();
+      for summary in [
+        gmx_months.entry(month(&trade.datetime)).or_insert_with(|| gmx(month(&trade.datetime))),
+        gmx_sizes.entry(size_bucket(size)).or_insert_with(|| gmx(size_bucket(size))),
+      ] {
+        summary.trades += 1;
+        summary.volume_usd += size.abs();
+        summary.price_impact_usd += trade.price_impact.unwrap_or_default();
+        summary.pnl_usd += trade.pnl.unwrap_or_default();
+      }
+    }
+
+    spread_over_sizes(&gmx_months, &mut gmx_sizes);
+    let mut gmx_by_size: Vec<GmxSummary> = gmx_sizes.into_values().collect();
+    gmx_by_size.sort_by_key(|x| SIZE_BUCKETS.iter().position(|b| x.key == format!("<${}", b)).unwrap_or(SIZE_BUCKETS.len()));
+
+    GasReport {
+      by_month: by_month.into_values().collect(),
+      by_category: by_category.into_values().collect(),
+      by_protocol: by_protocol.into_values().collect(),
+      gmx_by_month: gmx_months.into_values().collect(),
+      gmx_by_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Chain, Token};
+
+  fn trade(transfer_id: &str, datetime: &str, action: &str) -> GmxTrade {
+    GmxTrade {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      action: action.to_string(),
+      market: String::from("Long ETH/USD"),
+      size_usd: None,
+      price_impact: None,
+      pnl: None,
+    }
+  }
+
+  fn fee(transfer_id: &str, datetime: &str) -> Fee {
+    Fee {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      chain: Chain::Arbitrum,
+      account: String::from("0xwallet"),
+      counterparty: String::from("0xrouter"),
+      token: Token::eth(Chain::Arbitrum),
+      value: Decimal::new(1, 5),
+      usd_value: None,
+      value_sent: Decimal::new(1, 3),
+    }
+  }
+
+  fn transaction(transfer_id: &str, legs: &[(&str, TransferDirection)]) -> Transaction {
+    let net_transfers = legs
+      .iter()
+      .map(|(address, direction)| Transfer {
+        transfer_id: transfer_id.to_string(),
+        datetime: String::from("2024-01-01 00:00:30"),
+        token: Token { address: address.to_string(), ..Token::eth(Chain::Arbitrum) },
+        value: Decimal::new(5, 4),
+        usd_value: None,
+        direction: direction.clone(),
+        counterparty: vec![String::from("0xkeeper")],
+        account: String::from("0xwallet"),
+        chain: Chain::Arbitrum,
+      })
+      .collect();
+    Transaction { transfer_id: transfer_id.to_string(), net_transfers, ..Default::default() }
+  }
+
+  #[test]
+  fn calls_creating_increases_without_tokens_may_deposit_eth() {
+    let call = fee("0xcall", "2024-01-01 00:00:00");
+    let none = HashSet::new();
+
+    let limit = [trade("0xcall", "2024-01-01 00:00:00", "Create Limit")];
+    assert!(may_deposit_eth(&call, &limit, &none));
+    let market = [trade("0xkeeper", "2024-01-01 00:00:45", "Market Increase")];
+    assert!(may_deposit_eth(&call, &market, &none));
+
+    let paid_in_token = HashSet::from(["0xcall"]);
+    assert!(!may_deposit_eth(&call, &limit, &paid_in_token));
+  }
+
+  #[test]
+  fn calls_without_increases_pay_execution_fees() {
+    let call = fee("0xcall", "2024-01-01 00:00:00");
+    let none = HashSet::new();
+
+    let late = [trade("0xkeeper", "2024-01-01 00:01:01", "Market Increase")];
+    assert!(!may_deposit_eth(&call, &late, &none));
+    let before = [trade("0xkeeper", "2023-12-31 23:59:59", "Market Increase")];
+    assert!(!may_deposit_eth(&call, &before, &none));
+    let decrease = [trade("0xcall", "2024-01-01 00:00:00", "Create Market Decrease")];
+    assert!(!may_deposit_eth(&call, &decrease, &none));
+  }
+
+  #[test]
+  fn keeper_eth_is_a_refund_unless_collateral_may_be_paid_out() {
+    let fees = [fee("0xcall", "2024-01-01 00:00:00")];
+    let trades = [
+      trade("0xcall", "2024-01-01 00:00:00", "Create Market Decrease"),
+      trade("0xincrease", "2024-01-01 00:00:30", "Market Increase"),
+      trade("0xdecrease", "2024-01-01 00:00:30", "Market Decrease"),
+      trade("0xpaid", "2024-01-01 00:00:30", "Market Decrease"),
+    ];
+    let transactions = vec![
+      transaction("0xcall", &[("ETH", TransferDirection::Incoming)]),
+      transaction("0xincrease", &[("ETH", TransferDirection::Incoming)]),
+      transaction("0xdecrease", &[("ETH", TransferDirection::Incoming)]),
+      transaction("0xpaid", &[("ETH", TransferDirection::Incoming), ("0xusdc", TransferDirection::Incoming)]),
+      transaction("0xother", &[("ETH", TransferDirection::Incoming)]),
+    ];
+
+    let (refunds, payouts) = keeper_refunds(&fees, &transactions, &trades);
+    let refunded: Vec<&str> = refunds.iter().map(|x| x.transfer_id.as_str()).collect();
+    assert_eq!(refunded, ["0xincrease", "0xpaid"]);
+    assert_eq!(payouts, 1);
+  }
+
+  #[test]
+  fn costs_are_spread_over_sizes_per_trade() {
+    let months = BTreeMap::from([(
+      String::from("2024-01"),
+      GmxSummary { trades: 4, execution_fee_eth: Decimal::from(4), execution_fee_usd: Decimal::from(8), gas_usd: Decimal::from(2), ..Default::default() },
+    )]);
+    let mut sizes = BTreeMap::from([
+      (size_bucket(Decimal::from(50)), GmxSummary { trades: 3, ..Default::default() }),
+      (size_bucket(Decimal::from(-5000)), GmxSummary { trades: 1, ..Default::default() }),
+    ]);
+
+    spread_over_sizes(&months, &mut sizes);
+    assert_eq!(sizes["<$100"].execution_fee_usd, Decimal::from(6));
+    assert_eq!(sizes["<$100"].gas_usd, Decimal::new(15, 1));
+    assert_eq!(sizes["<$10000"].execution_fee_eth, Decimal::from(1));
+  }
+}
@@ -0,0 +1,59 @@
+//! Income report.
+//!
+//! Airdrops and other income are recognised at their fair market value on
+//! receipt by the lot engine (see [`crate::portfolio::Portfolio::receive`]);
+//! this totals them by month and by token. Receipts without a known price count
+//! as unpriced and add nothing to the fair value.
+
+use crate::Income;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Income received in a month or in one token.
+pub struct IncomeSummary {
+  pub key: String,
+  pub receipts: usize,
+  /// Amount received, totalled per token only.
+  pub amount: Option<Decimal>,
+  pub fair_value_usd: Decimal,
+  /// Cost basis of the lots opened, below the fair value for `zero_basis` assets.
+  pub cost_basis_usd: Decimal,
+  /// Receipts without a known price.
+  pub unpriced: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+/// Income totals by month and by token.
+pub struct IncomeReport {
+  pub by_month: Vec<IncomeSummary>,
+  pub by_token: Vec<IncomeSummary>,
+}
+
+/// Adds a receipt to the summary of `key`, including its amount when the
+/// summary is of a single token.
+fn add(groups: &mut BTreeMap<String, IncomeSummary>, key: String, income: &Income, per_token: bool) {
+  let summary = groups.entry(key.clone()).or_insert_with(|| IncomeSummary { key, ..Default::default() });
+  summary.receipts += 1;
+  if per_token {
+    summary.amount = Some(summary.amount.unwrap_or_default() + income.value);
+  }
+  summary.fair_value_usd += income.fair_value.unwrap_or_default();
+  summary.cost_basis_usd += income.cost_basis;
+  summary.unpriced += usize::from(income.fair_value.is_none());
+}
+
+/// Builds the income report.
+pub fn income_report(income: &[Income]) -> IncomeReport {
+    let mut by_month = BTreeMap::new();
+    let mut by_token = BTreeMap::new();
+    for x in income {
+      add(&mut by_month, x.datetime.get(..7).unwrap_or(&x.datetime).to_string(), x, false);
+      add(&mut by_token, x.token.asset.clone(), x, true);
+    }
+    IncomeReport {
+      by_month: by_month.into_values().collect(),
+      by_token: by_token.into_values().collect(),
+    }
+}
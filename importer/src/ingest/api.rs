@@ -0,0 +1,415 @@
+//! Functions for ingesting saved Etherscan/Arbiscan API responses (`tokentx`,
+//! `txlist` and `txlistinternal`).
+//!
+//! API rows are converted into the same raw structs as the CSV exports and then
+//! go through the CSV conversions, so both paths yield identical [`Transfer`]s.
+//! The API carries no historical prices, so only tokens with a
+//! `stable_usd_value` receive a USD value.
+
+use crate::dedup::dedup;
+use crate::ingest::{internal, token, transaction, DATETIME_FORMAT};
+use crate::{read_json, Chain, Fee, Transfer};
+use chrono::DateTime;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Decimals of native ETH amounts (wei).
+const ETH_DECIMALS: u32 = 18;
+
+/// Scales a raw integer amount by `decimals`, e.g. `"1500000"` with 6 decimals
+/// is exactly `1.5`. The decimal point is moved in the digits, so raw amounts
+/// beyond a [`Decimal`]'s mantissa scale fine; digits past its precision are
+/// rounded, and only amounts that are too large or round to zero are errors.
+pub fn scale_units(raw: &str, decimals: u32) -> Result<Decimal, Box<dyn Error>> {
+  let raw = raw.trim();
+  if raw.is_empty() || !raw.chars().all(|x| x.is_ascii_digit()) {
+    return Err(format!("invalid amount {}", raw).into());
+  }
+  let digits = raw.trim_start_matches('0');
+  if digits.is_empty() {
+    return Ok(Decimal::ZERO);
+  }
+
+  let decimals = decimals as usize;
+  let padded = format!("{:0>width$}", digits, width = decimals + 1);
+  let (whole, fraction) = padded.split_at(padded.len() - decimals);
+  let value = Decimal::from_str(&format!("{}.{}", whole, fraction))
+    .map_err(|e| format!("amount {} with {} decimals: {}", raw, decimals, e))?;
+  match value.is_zero() {
+    true => Err(format!("amount {} with {} decimals is too small", raw, decimals).into()),
+    false => Ok(value.normalize()),
+  }
+}
+
+/// Converts result rows with `convert`, logging and skipping the rows it
+/// rejects, e.g. spam token amounts too large for a [`Decimal`], rather than
+/// failing the whole response.
+fn convert_rows<T, R>(
+  file_path: &str,
+  rows: Vec<T>,
+  hash: impl Fn(&T) -> &str,
+  convert: impl Fn(T) -> Result<R, Box<dyn Error>>,
+) -> Vec<R> {
+  rows
+    .into_iter()
+    .filter_map(|row| {
+      let hash = hash(&row).to_string();
+      convert(row).map_err(|e| warn!("{}: skipped {}: {}", file_path, hash, e)).ok()
+    })
+    .collect()
+}
+
+/// Formats a unix timestamp as the normalized UTC datetime.
+fn format_timestamp(raw: &str) -> Result<String, Box<dyn Error>> {
+  let datetime = DateTime::from_timestamp(raw.trim().parse()?, 0).ok_or("invalid timestamp")?;
+  Ok(datetime.format(DATETIME_FORMAT).to_string())
+}
+
+/// ETH amounts in and out of `address` as `(in, out)` strings.
+fn eth_in_out(address: &str, from: &str, to: &str, value: Decimal) -> (String, String) {
+  let address = address.to_lowercase();
+  let value_in = if to.to_lowercase() == address { value } else { Decimal::ZERO };
+  let value_out = if from.to_lowercase() == address { value } else { Decimal::ZERO };
+  (value_in.to_string(), value_out.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+/// Envelope shared by every Etherscan API response.
+pub struct ApiResponse {
+  pub status: String,
+  pub message: String,
+  pub result: Value,
+}
+
+impl ApiResponse {
+  /// Deserializes the result rows, surfacing API errors returned in place of rows.
+  fn rows<T>(self) -> Result<Vec<T>, Box<dyn Error>>
+  where
+      T: for<'de> Deserialize<'de>,
+  {
+    match self.result {
+      Value::Array(_) => Ok(serde_json::from_value(self.result)?),
+      result => Err(format!("{}: {}", self.message, result).into()),
+    }
+  }
+}
+
+/// Converts a `tokentx` row into the CSV token transfer representation.
+impl TryFrom<TokenTx> for token::Token {
+    type Error = Box<dyn Error>;
+
+    fn try_from(tx: TokenTx) -> Result<Self, Self::Error> {
+      Ok(token::Token {
+        datetime_utc: format_timestamp(&tx.time_stamp)?,
+        unix_timestamp: tx.time_stamp.parse()?,
+        blockno: tx.block_number.parse()?,
+        token_value: scale_units(&tx.value, tx.token_decimal.parse()?)?.to_string(),
+        usd_value_day_of_tx: String::new(),
+        transaction_hash: tx.hash,
+        from: tx.from,
+        to: tx.to,
+        contract_address: tx.contract_address,
+        token_name: tx.token_name,
+        token_symbol: tx.token_symbol,
+      })
+    }
+}
+
+/// Converts a `txlist` row into the CSV normal transaction representation; the
+/// fee is `gasUsed * gasPrice`.
+impl TryFrom<(&str, NormalTx)> for transaction::Transaction {
+    type Error = Box<dyn Error>;
+
+    fn try_from((address, tx): (&str, NormalTx)) -> Result<Self, Self::Error> {
+      let value = scale_units(&tx.value, ETH_DECIMALS)?;
+      let (value_in_eth, value_out_eth) = eth_in_out(address, &tx.from, &tx.to, value);
+      let fee: i128 = tx.gas_used.parse::<i128>()? * tx.gas_price.parse::<i128>()?;
+
+      Ok(transaction::Transaction {
+        datetime_utc: format_timestamp(&tx.time_stamp)?,
+        unix_timestamp: tx.time_stamp.parse()?,
+        blockno: tx.block_number.parse()?,
+        txn_fee_eth: scale_units(&fee.to_string(), ETH_DECIMALS)?.to_string(),
+        txn_fee_usd: String::new(),
+        current_value: String::new(),
+        historical_price_eth: String::new(),
+        status: if tx.is_error == "1" { String::from("Error(0)") } else { String::new() },
+        err_code: String::new(),
+        method: tx.function_name,
+        txhash: tx.hash,
+        from: tx.from,
+        to: tx.to,
+        contract_address: tx.contract_address,
+        value_in_eth,
+        value_out_eth,
+      })
+    }
+}
+
+/// Converts a `txlistinternal` row into the CSV internal transaction representation.
+impl TryFrom<(&str, InternalTx)> for internal::Internal {
+    type Error = Box<dyn Error>;
+
+    fn try_from((address, tx): (&str, InternalTx)) -> Result<Self, Self::Error> {
+      let value = scale_units(&tx.value, ETH_DECIMALS)?;
+      let (value_in_eth, value_out_eth) = eth_in_out(address, &tx.from, &tx.to, value);
+
+      Ok(internal::Internal {
+        datetime_utc: format_timestamp(&tx.time_stamp)?,
+        unix_timestamp: tx.time_stamp.parse()?,
+        blockno: tx.block_number.parse()?,
+        parent_tx_from: String::new(),
+        parent_tx_to: String::new(),
+        parent_tx_eth_value: String::new(),
+        current_value: String::new(),
+        historical_price_eth: String::new(),
+        status: if tx.is_error == "1" { String::from("1") } else { String::from("0") },
+        private_note: String::new(),
+        transaction_hash: tx.hash,
+        from: tx.from,
+        tx_to: tx.to,
+        contract_address: tx.contract_address,
+        err_code: tx.err_code,
+        tx_type: tx.tx_type,
+        value_in_eth,
+        value_out_eth,
+      })
+    }
+}
+
+/// Reads a saved `tokentx` response into [`Transfer`]s for the supplied address
+/// on `chain`, skipping rows that cannot be converted.
+pub fn read_api_tokens(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let rows = read_json::<ApiResponse>(file_path)?.rows::<TokenTx>()?;
+    Ok(convert_rows(file_path, rows, |x| &x.hash, |x| Ok((address, chain, token::Token::try_from(x)?).into())))
+}
+
+/// Reads a saved `txlist` response into [`Transfer`]s for the supplied address on `chain`.
+pub fn read_api_transactions(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let rows = read_json::<ApiResponse>(file_path)?.rows::<NormalTx>()?;
+    Ok(convert_rows(file_path, rows, |x| &x.hash, |x| {
+      Ok((address, chain, transaction::Transaction::try_from((address, x))?).into())
+    }))
+}
+
+/// Reads a saved `txlistinternal` response into [`Transfer`]s for the supplied address on `chain`.
+pub fn read_api_internals(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let rows = read_json::<ApiResponse>(file_path)?.rows::<InternalTx>()?;
+    Ok(convert_rows(file_path, rows, |x| &x.hash, |x| {
+      Ok((address, chain, internal::Internal::try_from((address, x))?).into())
+    }))
+}
+
+/// Reads the fees paid by the supplied address on `chain` from a saved
+/// `txlist` response; responses of other actions hold no fees.
+pub fn read_api_fees(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Fee>, Box<dyn Error>> {
+    let response = read_json::<ApiResponse>(file_path)?;
+    if ApiExport::detect(&response) != Some(ApiExport::Normal) {
+      return Ok(vec![]);
+    }
+    let rows: Vec<NormalTx> = response
+      .rows::<NormalTx>()?
+      .into_iter()
+      .filter(|x| x.from.to_lowercase() == address.to_lowercase())
+      .collect();
+    Ok(convert_rows(file_path, rows, |x| &x.hash, |x| {
+      Ok((address, chain, &transaction::Transaction::try_from((address, x))?).into())
+    }))
+}
+
+/// Reads the fees of every `txlist` response found in `dir`, returning nothing
+/// when the directory is absent.
+pub fn read_api_fees_dir(dir: &str, address: &str, chain: Chain) -> Result<Vec<Fee>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut fees = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path();
+      if file_path.extension().is_some_and(|x| x == "json") {
+        fees.extend(read_api_fees(&file_path.to_string_lossy(), address, chain)?);
+      }
+    }
+    Ok(fees)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Etherscan API actions whose responses can be ingested.
+pub enum ApiExport {
+  /// `action=tokentx`
+  TokenTransfers,
+  /// `action=txlist`
+  Normal,
+  /// `action=txlistinternal`
+  Internal,
+}
+
+impl ApiExport {
+  /// Determines the action from the fields of the first result row.
+  pub fn detect(response: &ApiResponse) -> Option<Self> {
+    let row = response.result.as_array()?.first()?.as_object()?;
+    if row.contains_key("tokenDecimal") {
+      Some(ApiExport::TokenTransfers)
+    } else if row.contains_key("traceId") || row.contains_key("errCode") {
+      Some(ApiExport::Internal)
+    } else if row.contains_key("gasPrice") {
+      Some(ApiExport::Normal)
+    } else {
+      None
+    }
+  }
+}
+
+/// Reads any supported API response, detecting its action from the rows. Empty
+/// responses yield no transfers.
+pub fn read_api(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let response = read_json::<ApiResponse>(file_path)?;
+    if response.result.as_array().is_some_and(|x| x.is_empty()) {
+      return Ok(vec![]);
+    }
+    match ApiExport::detect(&response) {
+      Some(ApiExport::TokenTransfers) => read_api_tokens(file_path, address, chain),
+      Some(ApiExport::Normal) => read_api_transactions(file_path, address, chain),
+      Some(ApiExport::Internal) => read_api_internals(file_path, address, chain),
+      None => Err(format!("{}: unrecognised API response", file_path).into()),
+    }
+}
+
+/// Reads every API response found in `dir`, returning nothing when the directory is absent.
+/// Transfers repeated across overlapping files are kept once.
+pub fn read_api_dir(dir: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path();
+      if file_path.extension().is_some_and(|x| x == "json") {
+        paths.push(file_path.to_string_lossy().to_string());
+      }
+    }
+    paths.sort();
+
+    let mut files = vec![];
+    for file_path in paths {
+      let transfers = read_api(&file_path, address, chain)?;
+      files.push((file_path, transfers));
+    }
+    Ok(dedup(files).0)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Raw representation of a `tokentx` result row.
+pub struct TokenTx {
+    pub block_number: String,
+    pub time_stamp: String,
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub contract_address: String,
+    pub token_name: String,
+    pub token_symbol: String,
+    pub token_decimal: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Raw representation of a `txlist` result row.
+pub struct NormalTx {
+    pub block_number: String,
+    pub time_stamp: String,
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub contract_address: String,
+    pub gas_price: String,
+    pub gas_used: String,
+    #[serde(default)]
+    pub is_error: String,
+    #[serde(default)]
+    pub function_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Raw representation of a `txlistinternal` result row.
+pub struct InternalTx {
+    pub block_number: String,
+    pub time_stamp: String,
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub contract_address: String,
+    #[serde(rename = "type", default)]
+    pub tx_type: String,
+    #[serde(default)]
+    pub is_error: String,
+    #[serde(default)]
+    pub err_code: String,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn token_tx(value: &str, token_decimal: &str) -> TokenTx {
+    TokenTx {
+      block_number: String::from("1"),
+      time_stamp: String::from("1704067200"),
+      hash: String::from("0xabc"),
+      from: String::from("0xfrom"),
+      to: String::from("0xto"),
+      value: value.to_string(),
+      contract_address: String::from("0xtoken"),
+      token_name: String::from("Token"),
+      token_symbol: String::from("TKN"),
+      token_decimal: token_decimal.to_string(),
+    }
+  }
+
+  #[test]
+  fn scales_units_exactly() {
+    assert_eq!(scale_units("1500000", 6).unwrap(), Decimal::new(15, 1));
+    assert_eq!(scale_units("1", 18).unwrap(), Decimal::new(1, 18));
+  }
+
+  #[test]
+  fn scales_units_beyond_the_mantissa() {
+    let raw = format!("1{}", "0".repeat(30));
+    assert_eq!(scale_units(&raw, 18).unwrap(), Decimal::new(1_000_000_000_000, 0));
+    assert_eq!(scale_units("0", 18).unwrap(), Decimal::ZERO);
+    assert_eq!(scale_units("1234567890123456789012345678901234", 18).unwrap().round_dp(4), Decimal::from_str("1234567890123456.7890").unwrap());
+  }
+
+  #[test]
+  fn unscalable_units_are_errors() {
+    assert!(scale_units("100000000000000000000000000000", 0).is_err());
+    assert!(scale_units("1", 40).is_err());
+    assert!(scale_units("1.5", 6).is_err());
+    assert!(scale_units("-1", 6).is_err());
+    assert!(scale_units("", 6).is_err());
+  }
+
+  #[test]
+  fn unconvertible_rows_are_skipped() {
+    let rows = vec![token_tx("1000000", "6"), token_tx("999999999999999999999999999999", "0"), token_tx("1", "40")];
+    let tokens = convert_rows("test.json", rows, |x| &x.hash, token::Token::try_from);
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].token_value, "1");
+    assert_eq!(tokens[0].datetime_utc, "2024-01-01 00:00:00");
+  }
+}
@@ -0,0 +1,230 @@
+//! Generic ingestion of CSV files described declaratively in `data/ref/sources.toml`.
+
+use crate::{read_csv, Chain, Token, Transfer, TransferDirection};
+use crate::ingest::DATETIME_FORMAT;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Mapping of source names to their column descriptions.
+pub type SourceConfig = HashMap<String, SourceMapping>;
+
+#[derive(Debug, Deserialize, Clone)]
+/// Describes how the columns of a third-party CSV map onto a [`Transfer`].
+pub struct SourceMapping {
+  /// Path of the CSV file to ingest.
+  pub file: String,
+  /// Chain the rows were recorded on; Arbitrum when absent.
+  #[serde(default)]
+  pub chain: Chain,
+  /// Column holding the transaction hash used to group transfers.
+  pub hash: String,
+  /// Column holding the transfer timestamp.
+  pub time: String,
+  /// `chrono` format string for `time`, or `"unix"` for epoch seconds.
+  #[serde(default = "default_time_format")]
+  pub time_format: String,
+  /// UTC offset of `time`, e.g. `"-04:00"`.
+  #[serde(default = "default_timezone")]
+  pub timezone: String,
+  /// Column holding the sending address.
+  pub from: String,
+  /// Column holding the receiving address.
+  pub to: String,
+  /// Column holding the token contract address; native ETH when absent or blank.
+  pub token_address: Option<String>,
+  /// Column holding the token amount.
+  pub amount: String,
+  /// Column holding the USD value of the transfer.
+  pub usd_value: Option<String>,
+  /// Character separating the integer and fractional parts of numbers.
+  #[serde(default = "default_decimal_separator")]
+  pub decimal_separator: char,
+  /// Character grouping thousands in numbers, stripped before parsing.
+  pub thousands_separator: Option<char>,
+}
+
+fn default_time_format() -> String {
+  String::from(DATETIME_FORMAT)
+}
+
+fn default_timezone() -> String {
+  String::from("+00:00")
+}
+
+fn default_decimal_separator() -> char {
+  '.'
+}
+
+impl SourceMapping {
+  /// Parses a number using the configured separators, ignoring currency symbols.
+  pub fn parse_number(&self, raw: &str) -> Option<Decimal> {
+    let cleaned: String = raw
+      .chars()
+      .filter(|c| Some(*c) != self.thousands_separator)
+      .map(|c| if c == self.decimal_separator { '.' } else { c })
+      .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+      .collect();
+    Decimal::from_str(&cleaned).ok()
+  }
+
+  /// Parses a timestamp into the normalized UTC representation.
+  pub fn parse_time(&self, raw: &str) -> Result<String, Box<dyn Error>> {
+    let utc: DateTime<Utc> = match self.time_format.as_str() {
+      "unix" => Utc
+        .timestamp_opt(raw.trim().parse()?, 0)
+        .single()
+        .ok_or("invalid unix timestamp")?,
+      format => {
+        let offset = FixedOffset::from_str(&self.timezone)?;
+        let naive = NaiveDateTime::parse_from_str(raw.trim(), format)?;
+        offset
+          .from_local_datetime(&naive)
+          .single()
+          .ok_or("ambiguous local time")?
+          .with_timezone(&Utc)
+      }
+    };
+    Ok(utc.format(DATETIME_FORMAT).to_string())
+  }
+}
+
+/// Converts a raw CSV row into a [`Transfer`] using the source's column mapping.
+impl TryFrom<(&str, &SourceMapping, HashMap<String, String>)> for Transfer {
+    type Error = Box<dyn Error>;
+
+    fn try_from((address, mapping, row): (&str, &SourceMapping, HashMap<String, String>)) -> Result<Self, Self::Error> {
+      let column = |name: &str| row.get(name).map(|x| x.trim().to_string()).ok_or(format!("missing column {}", name));
+
+      let from = column(&mapping.from)?;
+      let to = column(&mapping.to)?;
+      let token = match mapping.token_address.as_ref().map(|x| column(x)).transpose()? {
+        Some(contract) if !contract.is_empty() => Token::from((mapping.chain, &contract)),
+        _ => Token::eth(mapping.chain),
+      };
+
+      let amount = column(&mapping.amount)?;
+      let value = mapping.parse_number(&amount).ok_or(format!("invalid amount {}", amount))?;
+
+      let usd_value = match token.stable_usd_value {
+        Some(stable) => Some(value * stable),
+        None => match &mapping.usd_value {
+          Some(name) => mapping.parse_number(&column(name)?),
+          None => None,
+        },
+      };
+
+      let (direction, counterparty) = match from.to_lowercase() == address.to_lowercase() {
+        true => (TransferDirection::Outgoing, to),
+        false => (TransferDirection::Incoming, from),
+      };
+
+      Ok(Transfer {
+          transfer_id: column(&mapping.hash)?,
+          datetime: mapping.parse_time(&column(&mapping.time)?)?,
+          token,
+          value,
+          usd_value,
+          direction,
+          counterparty: vec![counterparty],
+          account: address.to_lowercase(),
+          chain: mapping.chain,
+      })
+    }
+}
+
+/// Loads the custom source descriptions from `data/ref/sources.toml`, returning
+/// an empty configuration when the file does not exist.
+pub fn read_sources() -> Result<SourceConfig, Box<dyn Error>> {
+    let path = Path::new("data/ref/sources.toml");
+    if !path.exists() {
+      return Ok(SourceConfig::new());
+    }
+    Ok(toml::de::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Reads the CSV described by `mapping` and converts each row into a [`Transfer`]
+/// for the supplied address.
+pub fn read_custom(mapping: &SourceMapping, address: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    read_csv::<HashMap<String, String>>(&mapping.file)?
+      .into_iter()
+      .map(|row| (address, mapping, row).try_into())
+      .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mapping() -> SourceMapping {
+    toml::de::from_str(
+      r#"
+      file = "export.csv"
+      hash = "Hash"
+      time = "Date"
+      time_format = "%d/%m/%Y %H:%M"
+      timezone = "-04:00"
+      from = "From"
+      to = "To"
+      amount = "Amount"
+      usd_value = "Value"
+      decimal_separator = ","
+      thousands_separator = "."
+      "#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn parses_localized_numbers() {
+    let mapping = mapping();
+    assert_eq!(mapping.parse_number("1.234,56"), Decimal::from_str("1234.56").ok());
+    assert_eq!(mapping.parse_number("$ -0,5"), Decimal::from_str("-0.5").ok());
+    assert_eq!(mapping.parse_number("n/a"), None);
+  }
+
+  #[test]
+  fn converts_times_to_utc() {
+    let mapping = mapping();
+    assert_eq!(mapping.parse_time("31/12/2023 22:30").unwrap(), "2024-01-01 02:30:00");
+    assert!(mapping.parse_time("2023-12-31").is_err());
+
+    let unix = SourceMapping { time_format: String::from("unix"), ..mapping };
+    assert_eq!(unix.parse_time("1704067200").unwrap(), "2024-01-01 00:00:00");
+  }
+
+  #[test]
+  fn maps_rows_onto_transfers() {
+    let row: HashMap<String, String> = [
+      ("Hash", "0xabc"),
+      ("Date", "01/06/2024 08:00"),
+      ("From", "0xOther"),
+      ("To", "0xMe"),
+      ("Amount", "1,5"),
+      ("Value", "4.500,00"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    let transfer: Transfer = ("0xME", &mapping(), row.clone()).try_into().unwrap();
+    assert_eq!(transfer.transfer_id, "0xabc");
+    assert_eq!(transfer.datetime, "2024-06-01 12:00:00");
+    assert_eq!(transfer.token.symbol, "ETH");
+    assert_eq!(transfer.value, Decimal::from_str("1.5").unwrap());
+    assert_eq!(transfer.usd_value, Some(Decimal::from(4500)));
+    assert_eq!(transfer.direction, TransferDirection::Incoming);
+    assert_eq!(transfer.counterparty, vec!["0xOther"]);
+    assert_eq!(transfer.account, "0xme");
+
+    let mut row = row;
+    row.remove("Amount");
+    let missing: Result<Transfer, _> = ("0xme", &mapping(), row).try_into();
+    assert!(missing.is_err());
+  }
+}
@@ -0,0 +1,156 @@
+//! Detection and header normalization for the various Etherscan CSV exports.
+
+use crate::dedup::dedup;
+use crate::{read_headers, Chain, Fee, Transfer};
+use crate::ingest::{internal, nft, token, transaction};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Header prefixes whose suffix changes between exports (spot prices, native
+/// currency symbols) mapped to the stable name the ingest structs expect.
+const HEADER_PREFIXES: [(&str, &str); 4] = [
+  ("CurrentValue @", "CurrentValue"),
+  ("Historical $Price/", "Historical $Price/ETH"),
+  ("Value_IN(", "Value_IN(ETH)"),
+  ("Value_OUT(", "Value_OUT(ETH)"),
+];
+
+/// Maps a raw Etherscan header onto its stable name, leaving unknown headers untouched.
+pub fn normalize_header(header: &str) -> String {
+  let header = header.trim();
+  HEADER_PREFIXES
+    .iter()
+    .find(|(prefix, _)| header.starts_with(prefix))
+    .map(|(_, name)| name.to_string())
+    .unwrap_or_else(|| header.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kinds of CSV export offered by Etherscan-family explorers.
+pub enum EtherscanExport {
+  /// ERC-20 token transfers.
+  TokenTransfers,
+  /// Normal (top-level) transactions.
+  Normal,
+  /// Internal transactions.
+  Internal,
+  /// ERC-721 / ERC-1155 transfers.
+  Nft,
+}
+
+impl EtherscanExport {
+  /// Determines the export type from a header row.
+  pub fn detect(headers: &[String]) -> Option<Self> {
+    let has = |name: &str| headers.iter().any(|x| normalize_header(x) == name);
+
+    if has("ParentTxFrom") {
+      Some(EtherscanExport::Internal)
+    } else if has("Token ID") || has("TokenId") {
+      Some(EtherscanExport::Nft)
+    } else if has("TokenValue") || has("TokenSymbol") {
+      Some(EtherscanExport::TokenTransfers)
+    } else if has("Value_OUT(ETH)") && (has("Method") || has("TxnFee(ETH)")) {
+      Some(EtherscanExport::Normal)
+    } else {
+      None
+    }
+  }
+
+  /// Reads the header row of `file_path` and determines its export type.
+  pub fn detect_file(file_path: &str) -> Result<Option<Self>, Box<dyn Error>> {
+    Ok(Self::detect(&read_headers(file_path)?))
+  }
+}
+
+/// Reads any supported Etherscan CSV export of `chain`'s explorer, detecting its
+/// type from the header.
+pub fn read_etherscan(
+    file_path: &str,
+    address: &str,
+    chain: Chain,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    match EtherscanExport::detect_file(file_path)? {
+      Some(EtherscanExport::TokenTransfers) => token::read_tokens(file_path, address, chain),
+      Some(EtherscanExport::Normal) => transaction::read_transactions(file_path, address, chain),
+      Some(EtherscanExport::Internal) => internal::read_internals(file_path, address, chain),
+      Some(EtherscanExport::Nft) => nft::read_nfts(file_path, address, chain),
+      None => Err(format!("{}: unrecognised Etherscan export", file_path).into()),
+    }
+}
+
+/// Reads every Etherscan CSV export found in `dir`, returning nothing when the
+/// directory is absent. Transfers repeated across overlapping files are kept once.
+pub fn read_etherscan_dir(
+    dir: &str,
+    address: &str,
+    chain: Chain,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path();
+      if file_path.extension().is_some_and(|x| x == "csv") {
+        paths.push(file_path.to_string_lossy().to_string());
+      }
+    }
+    paths.sort();
+
+    let mut files = vec![];
+    for file_path in paths {
+      let transfers = read_etherscan(&file_path, address, chain)?;
+      files.push((file_path, transfers));
+    }
+    Ok(dedup(files).0)
+}
+
+/// Reads the fees paid by `address` from every normal transaction export found
+/// in `dir`, returning nothing when the directory is absent.
+pub fn read_fees_dir(dir: &str, address: &str, chain: Chain) -> Result<Vec<Fee>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut fees = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path().to_string_lossy().to_string();
+      if file_path.ends_with(".csv") && EtherscanExport::detect_file(&file_path)? == Some(EtherscanExport::Normal) {
+        fees.extend(transaction::read_fees(&file_path, address, chain)?);
+      }
+    }
+    Ok(fees)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn headers(names: &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+  }
+
+  #[test]
+  fn normalizes_varying_headers() {
+    assert_eq!(normalize_header(" Value_IN(ETH) "), "Value_IN(ETH)");
+    assert_eq!(normalize_header("Value_OUT(BNB)"), "Value_OUT(ETH)");
+    assert_eq!(normalize_header("CurrentValue @ $3000/ETH"), "CurrentValue");
+    assert_eq!(normalize_header("Historical $Price/MATIC"), "Historical $Price/ETH");
+    assert_eq!(normalize_header("Txhash"), "Txhash");
+  }
+
+  #[test]
+  fn detects_export_types() {
+    let detect = |names: &[&str]| EtherscanExport::detect(&headers(names));
+
+    assert_eq!(detect(&["Txhash", "ParentTxFrom", "Value_IN(ETH)"]), Some(EtherscanExport::Internal));
+    assert_eq!(detect(&["Txhash", "TokenValue", "Token ID"]), Some(EtherscanExport::Nft));
+    assert_eq!(detect(&["Txhash", "TokenValue", "TokenSymbol"]), Some(EtherscanExport::TokenTransfers));
+    assert_eq!(detect(&["Txhash", "Value_OUT(OETH)", "Method"]), Some(EtherscanExport::Normal));
+    assert_eq!(detect(&["Txhash", "Value_OUT(ETH)"]), None);
+  }
+}
@@ -0,0 +1,262 @@
+//! Functions for ingesting Binance trade, deposit and withdrawal history exports,
+//! and the rewards and internal moves of the transaction history statement.
+
+use crate::ingest::exchange::{exchange_transfer, internal_transfer_id, parse_amount, parse_datetime, value_trade_legs};
+use crate::{read_csv, Transfer, TransferDirection};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+
+const EXCHANGE: &str = "binance";
+
+/// Counterparty of staking, earn and other rewards, configured as `Income` in
+/// `data/ref/categories.toml`.
+pub const INCOME: &str = "binance:income";
+
+/// Counterparty of moves between the spot, funding and earn wallets,
+/// configured as `Transfer` in `data/ref/categories.toml`.
+pub const TRANSFER: &str = "binance:transfer";
+
+/// Words of statement operations crediting rewards, e.g. `Staking Rewards` or
+/// `Simple Earn Flexible Interest`.
+const INCOME_OPERATIONS: [&str; 4] = ["reward", "interest", "distribution", "airdrop"];
+
+/// Words of statement operations moving funds between the account's own wallets,
+/// e.g. `Transfer Between Main and Funding Wallet` or `Simple Earn Flexible Subscription`.
+const TRANSFER_OPERATIONS: [&str; 4] = ["transfer between", "subscription", "redemption", "staking purchase"];
+
+/// Splits Binance amounts such as `"1,000.5USDT"` into their value and symbol.
+fn split_amount(raw: &str) -> Option<(Decimal, String)> {
+  let idx = raw.find(|c: char| c.is_ascii_alphabetic())?;
+  Some((parse_amount(&raw[..idx])?, raw[idx..].trim().to_string()))
+}
+
+/// Converts a Binance trade into its purchased, sold and fee legs.
+impl TryFrom<BinanceTrade> for Vec<Transfer> {
+    type Error = Box<dyn Error>;
+
+    fn try_from(row: BinanceTrade) -> Result<Self, Self::Error> {
+      let datetime = parse_datetime(&row.date)?;
+      let transfer_id = format!("{}:{}:{}", EXCHANGE, datetime, row.pair);
+      let (base_value, base) = split_amount(&row.executed).ok_or(format!("invalid amount {}", row.executed))?;
+      let (quote_value, quote) = split_amount(&row.amount).ok_or(format!("invalid amount {}", row.amount))?;
+
+      let (base_direction, quote_direction) = match row.side.to_uppercase().as_str() {
+        "BUY" => (TransferDirection::Incoming, TransferDirection::Outgoing),
+        "SELL" => (TransferDirection::Outgoing, TransferDirection::Incoming),
+        side => Err(format!("unknown trade side {}", side))?,
+      };
+
+      let mut legs = vec![
+        exchange_transfer(EXCHANGE, &transfer_id, &datetime, &base, base_value, None, base_direction, EXCHANGE),
+        exchange_transfer(EXCHANGE, &transfer_id, &datetime, &quote, quote_value, None, quote_direction, EXCHANGE),
+      ];
+      if let Some((fee_value, fee)) = split_amount(&row.fee).filter(|(x, _)| !x.is_zero()) {
+        legs.push(exchange_transfer(EXCHANGE, &transfer_id, &datetime, &fee, fee_value, None, TransferDirection::Outgoing, EXCHANGE));
+      }
+      Ok(legs)
+    }
+}
+
+/// Converts a deposit or withdrawal history row into a [`Transfer`]. Withdrawals
+/// debit the network fee on top of the amount received.
+impl TryFrom<(BinanceTransfer, &TransferDirection)> for Transfer {
+    type Error = Box<dyn Error>;
+
+    fn try_from((row, direction): (BinanceTransfer, &TransferDirection)) -> Result<Self, Self::Error> {
+      let amount = parse_amount(&row.amount).ok_or(format!("invalid amount {}", row.amount))?;
+      let fee = parse_amount(&row.transaction_fee).unwrap_or_default();
+      let datetime = parse_datetime(&row.date)?;
+
+      let (value, counterparty) = match direction {
+        TransferDirection::Outgoing => (amount + fee, row.address.to_lowercase()),
+        TransferDirection::Incoming => (amount, row.source_address.to_lowercase()),
+      };
+      let transfer_id = match row.txid.is_empty() {
+        true => format!("{}:{}:{}", EXCHANGE, datetime, row.coin),
+        false => row.txid.clone(),
+      };
+
+      Ok(exchange_transfer(EXCHANGE, &transfer_id, &datetime, &row.coin, value, None, direction.clone(), &counterparty))
+    }
+}
+
+/// Converts a transaction history statement row into a [`Transfer`] when it is a
+/// reward or a move between wallets. Other operations (trades, deposits,
+/// withdrawals) are read from their own exports and imply none.
+impl TryFrom<BinanceStatement> for Option<Transfer> {
+    type Error = Box<dyn Error>;
+
+    fn try_from(row: BinanceStatement) -> Result<Self, Self::Error> {
+      let operation = row.operation.to_lowercase();
+      let datetime = parse_datetime(&row.utc_time)?;
+      let (transfer_id, counterparty) = if INCOME_OPERATIONS.iter().any(|x| operation.contains(x)) {
+        (format!("{}:{}:{}:{}", EXCHANGE, datetime, row.coin, row.operation), INCOME)
+      } else if TRANSFER_OPERATIONS.iter().any(|x| operation.contains(x)) {
+        (internal_transfer_id(EXCHANGE, &datetime, &row.coin), TRANSFER)
+      } else {
+        return Ok(None);
+      };
+
+      let change = parse_amount(&row.change).ok_or(format!("invalid change {}", row.change))?;
+      let direction = match change.is_sign_negative() {
+        true => TransferDirection::Outgoing,
+        false => TransferDirection::Incoming,
+      };
+      Ok(Some(exchange_transfer(EXCHANGE, &transfer_id, &datetime, &row.coin, change, None, direction, counterparty)))
+    }
+}
+
+/// Reads a Binance spot trade history export.
+pub fn read_binance_trades(file_path: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let mut transfers = vec![];
+    for row in read_csv::<BinanceTrade>(file_path)? {
+      let legs: Vec<Transfer> = row.try_into()?;
+      transfers.extend(legs);
+    }
+    Ok(value_trade_legs(transfers))
+}
+
+/// Reads a Binance deposit or withdrawal history export, skipping entries that
+/// did not complete.
+pub fn read_binance_transfers(file_path: &str, direction: TransferDirection) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    read_csv::<BinanceTransfer>(file_path)?
+      .into_iter()
+      .filter(|x| matches!(x.status.to_lowercase().as_str(), "completed" | "success" | ""))
+      .map(|x| (x, &direction).try_into())
+      .collect()
+}
+
+/// Reads the rewards and moves between wallets of a Binance transaction history
+/// statement.
+pub fn read_binance_statement(file_path: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let mut transfers = vec![];
+    for row in read_csv::<BinanceStatement>(file_path)? {
+      let transfer: Option<Transfer> = row.try_into()?;
+      transfers.extend(transfer);
+    }
+    Ok(transfers)
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of a Binance spot trade history row.
+pub struct BinanceTrade {
+    #[serde(rename = "Date(UTC)")]
+    pub date: String,
+
+    #[serde(rename = "Pair")]
+    pub pair: String,
+
+    #[serde(rename = "Side")]
+    pub side: String,
+
+    #[serde(rename = "Price")]
+    pub price: String,
+
+    #[serde(rename = "Executed")]
+    pub executed: String,
+
+    #[serde(rename = "Amount")]
+    pub amount: String,
+
+    #[serde(rename = "Fee")]
+    pub fee: String,
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of a Binance deposit or withdrawal history row.
+pub struct BinanceTransfer {
+    #[serde(rename = "Date(UTC)")]
+    pub date: String,
+
+    #[serde(rename = "Coin")]
+    pub coin: String,
+
+    #[serde(rename = "Network")]
+    pub network: String,
+
+    #[serde(rename = "Amount")]
+    pub amount: String,
+
+    #[serde(rename = "TransactionFee", default)]
+    pub transaction_fee: String,
+
+    #[serde(rename = "Address", default)]
+    pub address: String,
+
+    #[serde(rename = "TXID")]
+    pub txid: String,
+
+    #[serde(rename = "SourceAddress", default)]
+    pub source_address: String,
+
+    #[serde(rename = "Status", default)]
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of a Binance transaction history statement row.
+pub struct BinanceStatement {
+    #[serde(rename = "UTC_Time")]
+    pub utc_time: String,
+
+    #[serde(rename = "Account")]
+    pub account: String,
+
+    #[serde(rename = "Operation")]
+    pub operation: String,
+
+    #[serde(rename = "Coin")]
+    pub coin: String,
+
+    #[serde(rename = "Change")]
+    pub change: String,
+
+    #[serde(rename = "Remark", default)]
+    pub remark: String,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(account: &str, operation: &str, change: &str) -> BinanceStatement {
+    BinanceStatement {
+      utc_time: String::from("2024-01-02 03:04:05"),
+      account: account.to_string(),
+      operation: operation.to_string(),
+      coin: String::from("ETH"),
+      change: change.to_string(),
+      remark: String::new(),
+    }
+  }
+
+  #[test]
+  fn rewards_are_income() {
+    for operation in ["Staking Rewards", "Simple Earn Flexible Interest", "Distribution"] {
+      let transfer: Option<Transfer> = row("Earn", operation, "0.001").try_into().unwrap();
+      let transfer = transfer.unwrap();
+      assert_eq!(transfer.counterparty, vec![String::from(INCOME)]);
+      assert_eq!(transfer.direction, TransferDirection::Incoming);
+    }
+  }
+
+  #[test]
+  fn internal_moves_are_grouped_per_day_and_asset() {
+    let out: Option<Transfer> = row("Spot", "Simple Earn Flexible Subscription", "-1").try_into().unwrap();
+    let into: Option<Transfer> = row("Earn", "Simple Earn Flexible Subscription", "1").try_into().unwrap();
+    let (out, into) = (out.unwrap(), into.unwrap());
+    assert_eq!(out.counterparty, vec![String::from(TRANSFER)]);
+    assert_eq!(out.direction, TransferDirection::Outgoing);
+    assert_eq!(out.transfer_id, "binance:transfer:2024-01-02:ETH");
+    assert_eq!(into.transfer_id, out.transfer_id);
+  }
+
+  #[test]
+  fn other_operations_are_left_to_their_exports() {
+    for operation in ["Deposit", "Withdraw", "Buy", "Transaction Related"] {
+      let transfer: Option<Transfer> = row("Spot", operation, "1").try_into().unwrap();
+      assert!(transfer.is_none());
+    }
+  }
+}
@@ -0,0 +1,191 @@
+//! Functions for ingesting the Coinbase transaction history report.
+
+use crate::ingest::exchange::{exchange_transfer, parse_amount, parse_datetime};
+use crate::{read_csv_after, Transfer, TransferDirection};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+
+const EXCHANGE: &str = "coinbase";
+
+/// Counterparty of staking, learning and other rewards, configured as
+/// `Income` in `data/ref/categories.toml`.
+pub const INCOME: &str = "coinbase:income";
+
+/// Transaction types crediting rewards rather than trading.
+const INCOME_TYPES: [&str; 5] = ["staking income", "rewards income", "learning reward", "inflation reward", "coinbase earn"];
+
+/// Converts a Coinbase report row into the exchange-side [`Transfer`]s it implies.
+/// Rows of an unknown transaction type imply none and are logged.
+impl TryFrom<Coinbase> for Vec<Transfer> {
+    type Error = Box<dyn Error>;
+
+    fn try_from(row: Coinbase) -> Result<Self, Self::Error> {
+      let datetime = parse_datetime(&row.timestamp)?;
+      let transfer_id = match row.id.is_empty() {
+        true => format!("{}:{}:{}", EXCHANGE, datetime, row.asset),
+        false => format!("{}:{}", EXCHANGE, row.id),
+      };
+      let quantity = parse_amount(&row.quantity).ok_or(format!("invalid quantity {}", row.quantity))?;
+      let subtotal = parse_amount(&row.subtotal)
+        .or(parse_amount(&row.price).map(|price| price * quantity.abs()));
+      let total = parse_amount(&row.total).or(subtotal);
+
+      let leg = |symbol: &str, value: Decimal, usd_value: Option<Decimal>, direction: TransferDirection, counterparty: &str| {
+        exchange_transfer(EXCHANGE, &transfer_id, &datetime, symbol, value, usd_value, direction, counterparty)
+      };
+
+      Ok(match row.transaction_type.to_lowercase().as_str() {
+        "buy" | "advanced trade buy" => vec![
+          leg(&row.asset, quantity, total, TransferDirection::Incoming, EXCHANGE),
+          leg(&row.price_currency, total.unwrap_or_default(), total, TransferDirection::Outgoing, EXCHANGE),
+        ],
+        "sell" | "advanced trade sell" => vec![
+          leg(&row.asset, quantity, total, TransferDirection::Outgoing, EXCHANGE),
+          leg(&row.price_currency, total.unwrap_or_default(), total, TransferDirection::Incoming, EXCHANGE),
+        ],
+        "convert" => {
+          let (value, symbol) = row.converted_to().ok_or(format!("unparsable convert note {}", row.notes))?;
+          vec![
+            leg(&row.asset, quantity, subtotal, TransferDirection::Outgoing, EXCHANGE),
+            leg(&symbol, value, subtotal, TransferDirection::Incoming, EXCHANGE),
+          ]
+        },
+        "send" | "withdrawal" => vec![
+          leg(&row.asset, quantity, subtotal, TransferDirection::Outgoing, &row.destination().unwrap_or_default()),
+        ],
+        "receive" | "deposit" => vec![
+          leg(&row.asset, quantity, subtotal, TransferDirection::Incoming, ""),
+        ],
+        x if INCOME_TYPES.contains(&x) => vec![
+          leg(&row.asset, quantity, subtotal, TransferDirection::Incoming, INCOME),
+        ],
+        x => {
+          warn!("{}: skipped Coinbase row of unknown type {}", transfer_id, x);
+          vec![]
+        },
+      })
+    }
+}
+
+/// Reads a Coinbase transaction history report, skipping its preamble.
+pub fn read_coinbase(file_path: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let rows = read_csv_after::<Coinbase, _>(file_path, |line| line.contains("Transaction Type"))?;
+    let mut transfers = vec![];
+    for row in rows {
+      let legs: Vec<Transfer> = row.try_into()?;
+      transfers.extend(legs);
+    }
+    Ok(transfers)
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of a Coinbase transaction history row.
+pub struct Coinbase {
+    #[serde(rename = "ID", default)]
+    pub id: String,
+
+    #[serde(rename = "Timestamp")]
+    pub timestamp: String,
+
+    #[serde(rename = "Transaction Type")]
+    pub transaction_type: String,
+
+    #[serde(rename = "Asset")]
+    pub asset: String,
+
+    #[serde(rename = "Quantity Transacted")]
+    pub quantity: String,
+
+    #[serde(rename = "Price Currency", alias = "Spot Price Currency")]
+    pub price_currency: String,
+
+    #[serde(rename = "Price at Transaction", alias = "Spot Price at Transaction")]
+    pub price: String,
+
+    #[serde(rename = "Subtotal")]
+    pub subtotal: String,
+
+    #[serde(rename = "Total (inclusive of fees and/or spread)")]
+    pub total: String,
+
+    #[serde(rename = "Fees and/or Spread")]
+    pub fees: String,
+
+    #[serde(rename = "Notes")]
+    pub notes: String,
+}
+
+impl Coinbase {
+  /// Amount and symbol received in a convert, from notes such as
+  /// `"Converted 0.5 ETH to 1,000.12 USDC"`.
+  fn converted_to(&self) -> Option<(Decimal, String)> {
+    let target = self.notes.split(" to ").nth(1)?;
+    let mut words = target.split_whitespace();
+    let value = parse_amount(words.next()?)?;
+    let symbol = words.next()?.to_string();
+    Some((value, symbol))
+  }
+
+  /// Destination address of a send, from notes such as `"Sent 0.1 ETH to 0xabc..."`.
+  fn destination(&self) -> Option<String> {
+    self.notes
+      .split_whitespace()
+      .find(|x| x.starts_with("0x"))
+      .map(|x| x.to_lowercase())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(transaction_type: &str, notes: &str) -> Coinbase {
+    Coinbase {
+      id: String::from("1"),
+      timestamp: String::from("2024-01-02 03:04:05 UTC"),
+      transaction_type: transaction_type.to_string(),
+      asset: String::from("ETH"),
+      quantity: String::from("0.5"),
+      price_currency: String::from("USD"),
+      price: String::from("$2,000.00"),
+      subtotal: String::from("$1,000.00"),
+      total: String::from("$1,010.00"),
+      fees: String::from("$10.00"),
+      notes: notes.to_string(),
+    }
+  }
+
+  #[test]
+  fn buy_pays_total_in_price_currency() {
+    let legs: Vec<Transfer> = row("Buy", "").try_into().unwrap();
+    assert_eq!(legs.len(), 2);
+    assert_eq!(legs[0].token.asset, "ETH");
+    assert_eq!(legs[0].direction, TransferDirection::Incoming);
+    assert_eq!(legs[1].token.asset, "USD");
+    assert_eq!(legs[1].value, Decimal::from(1010));
+    assert_eq!(legs[1].direction, TransferDirection::Outgoing);
+  }
+
+  #[test]
+  fn convert_receives_noted_amount() {
+    let legs: Vec<Transfer> = row("Convert", "Converted 0.5 ETH to 1,000.12 USDC").try_into().unwrap();
+    assert_eq!(legs[1].token.asset, "USDC");
+    assert_eq!(legs[1].value, Decimal::new(100012, 2));
+  }
+
+  #[test]
+  fn rewards_are_income() {
+    let legs: Vec<Transfer> = row("Staking Income", "").try_into().unwrap();
+    assert_eq!(legs.len(), 1);
+    assert_eq!(legs[0].counterparty, vec![String::from(INCOME)]);
+    assert_eq!(legs[0].usd_value, Some(Decimal::from(1000)));
+  }
+
+  #[test]
+  fn unknown_types_are_skipped() {
+    let legs: Vec<Transfer> = row("Something New", "").try_into().unwrap();
+    assert!(legs.is_empty());
+  }
+}
@@ -0,0 +1,153 @@
+//! Functions for ingesting the Kraken ledgers export.
+
+use crate::ingest::exchange::{exchange_transfer, internal_transfer_id, parse_amount, parse_datetime, value_trade_legs};
+use crate::{read_csv, Transfer, TransferDirection};
+use serde::Deserialize;
+use std::error::Error;
+
+const EXCHANGE: &str = "kraken";
+
+/// Counterparty of staking, earn and dividend rewards, configured as `Income`
+/// in `data/ref/categories.toml`.
+pub const INCOME: &str = "kraken:income";
+
+/// Counterparty of moves between the spot, staking and earn balances,
+/// configured as `Transfer` in `data/ref/categories.toml`.
+pub const TRANSFER: &str = "kraken:transfer";
+
+/// Maps Kraken's legacy asset codes (`XXBT`, `XETH`, `ZUSD`) and staking
+/// suffixes (`ETH2.S`) onto plain ticker symbols.
+fn kraken_symbol(asset: &str) -> String {
+  let asset = asset.split('.').next().unwrap_or(asset);
+  match asset {
+    "XXBT" | "XBT" => String::from("BTC"),
+    "XXDG" | "XDG" => String::from("DOGE"),
+    "ETH2" => String::from("ETH"),
+    x if x.len() == 4 && (x.starts_with('X') || x.starts_with('Z')) => x[1..].to_string(),
+    x => x.to_string(),
+  }
+}
+
+/// Converts a Kraken ledger entry into a [`Transfer`] of its net balance change.
+/// Moves between the account's own balances are grouped per day and asset.
+impl TryFrom<Kraken> for Transfer {
+    type Error = Box<dyn Error>;
+
+    fn try_from(row: Kraken) -> Result<Self, Self::Error> {
+      let amount = parse_amount(&row.amount).ok_or(format!("invalid amount {}", row.amount))?;
+      let fee = parse_amount(&row.fee).unwrap_or_default();
+      let change = amount - fee;
+
+      let direction = match change.is_sign_negative() {
+        true => TransferDirection::Outgoing,
+        false => TransferDirection::Incoming,
+      };
+      let datetime = parse_datetime(&row.time)?;
+      let symbol = kraken_symbol(&row.asset);
+      let by_refid = format!("{}:{}", EXCHANGE, row.refid);
+      let (transfer_id, counterparty) = match (row.ledger_type.as_str(), row.subtype.as_str()) {
+        ("deposit" | "withdrawal", _) => (by_refid, ""),
+        ("staking" | "dividend", _) | ("earn", "reward") => (by_refid, INCOME),
+        ("transfer" | "earn", _) => (internal_transfer_id(EXCHANGE, &datetime, &symbol), TRANSFER),
+        _ => (by_refid, EXCHANGE),
+      };
+
+      Ok(exchange_transfer(
+        EXCHANGE,
+        &transfer_id,
+        &datetime,
+        &symbol,
+        change,
+        None,
+        direction,
+        counterparty,
+      ))
+    }
+}
+
+/// Reads a Kraken ledgers export. Unconfirmed entries (without a `txid`) are
+/// skipped and trade legs are valued from their stablecoin counter leg.
+pub fn read_kraken(file_path: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let transfers = read_csv::<Kraken>(file_path)?
+      .into_iter()
+      .filter(|x| !x.txid.is_empty())
+      .map(|x| x.try_into())
+      .collect::<Result<Vec<Transfer>, _>>()?;
+    Ok(value_trade_legs(transfers))
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of a Kraken ledger entry.
+pub struct Kraken {
+    pub txid: String,
+
+    pub refid: String,
+
+    pub time: String,
+
+    #[serde(rename = "type")]
+    pub ledger_type: String,
+
+    #[serde(default)]
+    pub subtype: String,
+
+    pub asset: String,
+
+    pub amount: String,
+
+    pub fee: String,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(refid: &str, ledger_type: &str, subtype: &str, asset: &str, amount: &str) -> Kraken {
+    Kraken {
+      txid: String::from("L1"),
+      refid: refid.to_string(),
+      time: String::from("2024-01-02 03:04:05"),
+      ledger_type: ledger_type.to_string(),
+      subtype: subtype.to_string(),
+      asset: asset.to_string(),
+      amount: amount.to_string(),
+      fee: String::from("0"),
+    }
+  }
+
+  #[test]
+  fn trades_use_the_exchange_counterparty() {
+    let transfer = Transfer::try_from(row("T1", "trade", "", "XETH", "-0.5")).unwrap();
+    assert_eq!(transfer.transfer_id, "kraken:T1");
+    assert_eq!(transfer.token.symbol, "ETH");
+    assert_eq!(transfer.direction, TransferDirection::Outgoing);
+    assert_eq!(transfer.counterparty, vec![String::from(EXCHANGE)]);
+  }
+
+  #[test]
+  fn rewards_are_income() {
+    for (ledger_type, subtype) in [("staking", ""), ("earn", "reward"), ("dividend", "")] {
+      let transfer = Transfer::try_from(row("R1", ledger_type, subtype, "ETH2.S", "0.01")).unwrap();
+      assert_eq!(transfer.transfer_id, "kraken:R1");
+      assert_eq!(transfer.token.symbol, "ETH");
+      assert_eq!(transfer.counterparty, vec![String::from(INCOME)]);
+    }
+  }
+
+  #[test]
+  fn internal_moves_are_grouped_per_day_and_asset() {
+    let out = Transfer::try_from(row("A", "transfer", "spottostaking", "XETH", "-1")).unwrap();
+    let into = Transfer::try_from(row("B", "earn", "allocation", "ETH2.S", "1")).unwrap();
+    assert_eq!(out.counterparty, vec![String::from(TRANSFER)]);
+    assert_eq!(out.transfer_id, "kraken:transfer:2024-01-02:ETH");
+    assert_eq!(into.transfer_id, out.transfer_id);
+    assert_eq!(into.direction, TransferDirection::Incoming);
+  }
+
+  #[test]
+  fn deposits_have_no_counterparty() {
+    let transfer = Transfer::try_from(row("D1", "deposit", "", "XXBT", "0.1")).unwrap();
+    assert_eq!(transfer.token.symbol, "BTC");
+    assert_eq!(transfer.counterparty, vec![String::new()]);
+  }
+}
@@ -0,0 +1,273 @@
+//! Ingestion of centralized exchange CSV exports (Coinbase, Kraken, Binance).
+//!
+//! Exchange rows become [`Transfer`]s whose `account` is the exchange name. Trades
+//! use the exchange name as counterparty so they can be classified through
+//! `data/ref/categories.toml`, while deposits and withdrawals keep the external
+//! address (when known) and are linked to the matching on-chain leg by
+//! [`link_transfers`].
+
+pub mod binance;
+pub mod coinbase;
+pub mod kraken;
+
+use crate::dedup::dedup;
+use crate::ingest::DATETIME_FORMAT;
+use crate::{Chain, Token, Transfer, TransferDirection};
+use chrono::{Duration, NaiveDateTime};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Exchange account names, also used as the counterparty of exchange trades.
+pub const EXCHANGES: [&str; 3] = ["coinbase", "kraken", "binance"];
+
+/// Symbols valued at one US dollar.
+const STABLECOINS: [&str; 7] = ["USD", "USDC", "USDT", "BUSD", "FDUSD", "TUSD", "DAI"];
+
+/// Maximum time between the sending and receiving legs of a deposit or withdrawal.
+const LINK_WINDOW_HOURS: i64 = 6;
+
+/// Maximum share of the sent amount that may be lost to network and withdrawal fees.
+const LINK_TOLERANCE: &str = "0.01";
+
+/// Timestamp layouts found across exchange exports.
+const DATETIME_FORMATS: [&str; 5] = [
+  "%Y-%m-%d %H:%M:%S UTC",
+  "%Y-%m-%dT%H:%M:%SZ",
+  "%Y-%m-%d %H:%M:%S%.f",
+  "%Y-%m-%d %H:%M:%S",
+  "%y-%m-%d %H:%M:%S",
+];
+
+/// Builds the [`Token`] for an asset held on an exchange account.
+pub fn exchange_token(exchange: &str, symbol: &str) -> Token {
+  let symbol = symbol.trim().to_uppercase();
+  let is_usd = STABLECOINS.contains(&symbol.as_str());
+  Token {
+    asset: symbol.clone(),
+    address: format!("{}:{}", exchange, symbol),
+    chain: Chain::Offchain,
+    stable_usd_value: is_usd.then_some(Decimal::ONE),
+    is_usd,
+    symbol,
+    ..Default::default()
+  }
+}
+
+/// Parses an exchange amount, ignoring currency symbols and thousands separators.
+pub fn parse_amount(raw: &str) -> Option<Decimal> {
+  let cleaned: String = raw
+    .chars()
+    .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+    .collect();
+  Decimal::from_str(&cleaned).ok()
+}
+
+/// Parses an exchange timestamp into the normalized UTC representation.
+pub fn parse_datetime(raw: &str) -> Result<String, Box<dyn Error>> {
+  DATETIME_FORMATS
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(raw.trim(), format).ok())
+    .map(|x| x.format(DATETIME_FORMAT).to_string())
+    .ok_or(format!("unrecognised timestamp {}", raw).into())
+}
+
+/// Builds a single exchange-side [`Transfer`]; the USD value of stablecoin legs is
+/// always their face value.
+#[allow(clippy::too_many_arguments)]
+pub fn exchange_transfer(
+    exchange: &str,
+    transfer_id: &str,
+    datetime: &str,
+    symbol: &str,
+    value: Decimal,
+    usd_value: Option<Decimal>,
+    direction: TransferDirection,
+    counterparty: &str,
+) -> Transfer {
+    let token = exchange_token(exchange, symbol);
+    let value = value.abs();
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      usd_value: token.stable_usd_value.map(|x| x * value).or(usd_value.map(|x| x.abs())),
+      token,
+      value,
+      direction,
+      counterparty: vec![counterparty.to_string()],
+      account: exchange.to_string(),
+      chain: Chain::Offchain,
+    }
+}
+
+/// Identifier grouping the moves of `symbol` between the balances of one
+/// exchange account (spot, staking, earn) on the day of `datetime`, so both
+/// sides of each move net out in one [`crate::Transaction`].
+pub fn internal_transfer_id(exchange: &str, datetime: &str, symbol: &str) -> String {
+  format!("{}:transfer:{}:{}", exchange, &datetime[..10], symbol.trim().to_uppercase())
+}
+
+/// Values trade legs lacking a USD value from the stablecoin leg moving the other
+/// way in the same trade, when there is exactly one.
+pub fn value_trade_legs(mut transfers: Vec<Transfer>) -> Vec<Transfer> {
+    let mut stable: HashMap<(String, bool), Vec<Decimal>> = HashMap::new();
+    for transfer in transfers.iter().filter(|x| x.token.is_usd) {
+      stable
+        .entry((transfer.transfer_id.clone(), transfer.direction == TransferDirection::Incoming))
+        .or_default()
+        .extend(transfer.usd_value);
+    }
+
+    for transfer in transfers.iter_mut().filter(|x| x.usd_value.is_none()) {
+      let key = (transfer.transfer_id.clone(), transfer.direction == TransferDirection::Outgoing);
+      if let Some([usd_value]) = stable.get(&key).map(|x| x.as_slice()) {
+        transfer.usd_value = Some(*usd_value);
+      }
+    }
+    transfers
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kinds of exchange CSV export understood by the importer.
+pub enum ExchangeExport {
+  /// Coinbase transaction history report.
+  Coinbase,
+  /// Kraken ledgers export.
+  Kraken,
+  /// Binance spot trade history.
+  BinanceTrades,
+  /// Binance crypto deposit history.
+  BinanceDeposits,
+  /// Binance crypto withdrawal history.
+  BinanceWithdrawals,
+  /// Binance transaction history statement.
+  BinanceStatement,
+}
+
+impl ExchangeExport {
+  /// Determines the export type from a header row. Binance deposit and withdrawal
+  /// histories share their columns and are told apart by `file_path`.
+  pub fn detect(headers: &[String], file_path: &str) -> Option<Self> {
+    let has = |name: &str| headers.iter().any(|x| x.trim() == name);
+
+    if has("Transaction Type") && has("Quantity Transacted") {
+      Some(ExchangeExport::Coinbase)
+    } else if has("refid") && has("aclass") {
+      Some(ExchangeExport::Kraken)
+    } else if has("Pair") && has("Side") && has("Executed") {
+      Some(ExchangeExport::BinanceTrades)
+    } else if has("Operation") && has("Change") && has("Coin") {
+      Some(ExchangeExport::BinanceStatement)
+    } else if has("TXID") && has("Coin") && has("Network") {
+      match file_path.to_lowercase().contains("withdraw") {
+        true => Some(ExchangeExport::BinanceWithdrawals),
+        false => Some(ExchangeExport::BinanceDeposits),
+      }
+    } else {
+      None
+    }
+  }
+
+  /// Scans the first lines of `file_path` for a recognised header row, skipping
+  /// any preamble the exchange prepends.
+  pub fn detect_file(file_path: &str) -> Result<Option<Self>, Box<dyn Error>> {
+    Ok(fs::read_to_string(file_path)?
+      .lines()
+      .take(10)
+      .map(|line| line.split(',').map(|x| x.trim_matches('"').to_string()).collect::<Vec<String>>())
+      .find_map(|headers| Self::detect(&headers, file_path)))
+  }
+}
+
+/// Reads any supported exchange export, detecting its type from the header.
+pub fn read_exchange(file_path: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    match ExchangeExport::detect_file(file_path)? {
+      Some(ExchangeExport::Coinbase) => coinbase::read_coinbase(file_path),
+      Some(ExchangeExport::Kraken) => kraken::read_kraken(file_path),
+      Some(ExchangeExport::BinanceTrades) => binance::read_binance_trades(file_path),
+      Some(ExchangeExport::BinanceDeposits) => binance::read_binance_transfers(file_path, TransferDirection::Incoming),
+      Some(ExchangeExport::BinanceWithdrawals) => binance::read_binance_transfers(file_path, TransferDirection::Outgoing),
+      Some(ExchangeExport::BinanceStatement) => binance::read_binance_statement(file_path),
+      None => Err(format!("{}: unrecognised exchange export", file_path).into()),
+    }
+}
+
+/// Reads every exchange export found in `dir`, returning nothing when the directory is absent.
+/// Transfers repeated across overlapping files are kept once.
+pub fn read_exchange_dir(dir: &str) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path();
+      if file_path.extension().is_some_and(|x| x == "csv") {
+        paths.push(file_path.to_string_lossy().to_string());
+      }
+    }
+    paths.sort();
+
+    let mut files = vec![];
+    for file_path in paths {
+      let transfers = read_exchange(&file_path)?;
+      files.push((file_path, transfers));
+    }
+    Ok(dedup(files).0)
+}
+
+/// Whether an exchange transfer moved funds on or off the exchange rather than
+/// trading, earning or moving between the account's own balances (counterparties
+/// such as `kraken` or `kraken:income`).
+fn is_deposit_or_withdrawal(transfer: &Transfer) -> bool {
+  !transfer.counterparty.iter().any(|x| EXCHANGES.contains(&x.split(':').next().unwrap_or_default()))
+}
+
+/// Rewrites the `transfer_id` of exchange deposits and withdrawals to the hash of
+/// the matching on-chain transfer, so both legs group into one [`crate::Transaction`]
+/// and the moved lots keep their original cost basis.
+///
+/// Legs match when they carry the same underlying asset in opposite directions,
+/// the receiving leg arrives within [`LINK_WINDOW_HOURS`] after the sending leg,
+/// and at most [`LINK_TOLERANCE`] of the amount was lost to fees.
+pub fn link_transfers(exchange: &mut [Transfer], onchain: &[Transfer]) {
+    let tolerance = Decimal::from_str(LINK_TOLERANCE).unwrap();
+    let parse = |x: &str| NaiveDateTime::parse_from_str(x, DATETIME_FORMAT).ok();
+    let mut used: Vec<bool> = onchain.iter().map(|_| false).collect();
+
+    for transfer in exchange.iter_mut().filter(|x| is_deposit_or_withdrawal(x)) {
+      if onchain.iter().any(|x| x.transfer_id == transfer.transfer_id) {
+        continue;
+      }
+      let Some(time) = parse(&transfer.datetime) else { continue };
+
+      let candidate = onchain
+        .iter()
+        .enumerate()
+        .filter(|(idx, x)| !used[*idx]
+          && x.token.asset == transfer.token.asset
+          && x.direction != transfer.direction)
+        .filter_map(|(idx, x)| {
+          let (sent, received) = match transfer.direction {
+            TransferDirection::Outgoing => ((transfer.value, time), (x.value, parse(&x.datetime)?)),
+            TransferDirection::Incoming => ((x.value, parse(&x.datetime)?), (transfer.value, time)),
+          };
+          let delay = received.1 - sent.1;
+          let matches = delay >= Duration::zero()
+            && delay <= Duration::hours(LINK_WINDOW_HOURS)
+            && received.0 <= sent.0
+            && received.0 >= sent.0 * (Decimal::ONE - tolerance);
+          matches.then_some((idx, delay))
+        })
+        .min_by_key(|(_, delay)| *delay);
+
+      if let Some((idx, _)) = candidate {
+        used[idx] = true;
+        transfer.transfer_id = onchain[idx].transfer_id.clone();
+      }
+    }
+}
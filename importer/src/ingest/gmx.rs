@@ -0,0 +1,166 @@
+//! Functions for ingesting the trade history CSV exported from the GMX app.
+//!
+//! The export is not a ledger of transfers (collateral moves are already in the
+//! Etherscan exports) but carries what the chain does not show directly: the
+//! size of each order and the price impact it paid.
+
+use crate::ingest::DATETIME_FORMAT;
+use crate::{read_csv_with, read_headers};
+use chrono::{Duration, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Parses a USD amount such as `-$9,645.87` or `+$1.21`, ignoring any comparison
+/// sign in front of it.
+pub fn parse_usd(raw: &str) -> Option<Decimal> {
+  let amount: String = raw.chars().filter(|x| x.is_ascii_digit() || *x == '.' || *x == '-').collect();
+  Decimal::from_str(&amount).ok()
+}
+
+/// Reads the UTC offset in hours from a `Date (GMT-4)` header.
+fn utc_offset(headers: &[String]) -> Result<i64, Box<dyn Error>> {
+  let header = headers.iter().find(|x| x.starts_with("Date")).ok_or("no Date column")?;
+  let offset = header.trim_start_matches("Date").trim_matches(|x: char| x == ' ' || x == '(' || x == ')').trim_start_matches("GMT");
+  Ok(if offset.is_empty() { 0 } else { offset.parse()? })
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Raw representation of a row of the GMX trade history export.
+pub struct GmxTradeRow {
+    #[serde(rename = "Date")]
+    pub date: String,
+    #[serde(rename = "Action")]
+    pub action: String,
+    #[serde(rename = "Size")]
+    pub size: String,
+    #[serde(rename = "Market")]
+    pub market: String,
+    #[serde(rename = "Price Impact", default)]
+    pub price_impact: String,
+    #[serde(rename = "Transaction ID")]
+    pub transaction_id: String,
+    #[serde(rename = "PnL ($)", default)]
+    pub pnl: String,
+}
+
+#[derive(Debug, Clone)]
+/// Order or position change from the GMX trade history.
+pub struct GmxTrade {
+  /// Hash of the transaction, taken from the explorer link.
+  pub transfer_id: String,
+  /// UTC datetime in the normalized format.
+  pub datetime: String,
+  /// e.g. `Market Increase`, `Create Limit` or `Execute Stop Loss`.
+  pub action: String,
+  /// e.g. `Long BTC/USD`.
+  pub market: String,
+  /// USD size of the position change; empty for collateral deposits and withdrawals.
+  pub size_usd: Option<Decimal>,
+  /// USD price impact paid (negative) or received (positive); amounts shown
+  /// as `< $0.01` count as zero.
+  pub price_impact: Option<Decimal>,
+  /// Realised USD PnL of decreases.
+  pub pnl: Option<Decimal>,
+}
+
+impl GmxTrade {
+  /// Whether the row is a position change that was executed.
+  pub fn is_executed(&self) -> bool {
+    self.action.starts_with("Market ") || self.action.starts_with("Execute ")
+  }
+
+  /// Whether the order adds to a position, taking collateral in.
+  pub fn is_increase(&self) -> bool {
+    ["Market Increase", "Limit", "Stop Market", "Deposit"].iter().any(|x| self.action.ends_with(x))
+  }
+
+  /// Whether the row can pay collateral out to the account: an executed
+  /// decrease, a withdrawal, or an increase that failed or was cancelled.
+  pub fn pays_out(&self) -> bool {
+    match self.is_increase() {
+      true => self.action.starts_with("Failed ") || self.action.starts_with("Cancel "),
+      false => self.is_executed() || self.action == "Withdraw",
+    }
+  }
+}
+
+/// Converts a raw row and the export's UTC offset into a [`GmxTrade`].
+impl TryFrom<(i64, GmxTradeRow)> for GmxTrade {
+    type Error = Box<dyn Error>;
+
+    fn try_from((offset, row): (i64, GmxTradeRow)) -> Result<Self, Self::Error> {
+      let local = NaiveDateTime::parse_from_str(&row.date, DATETIME_FORMAT)?;
+      let datetime = local - Duration::hours(offset);
+      Ok(GmxTrade {
+        transfer_id: row.transaction_id.rsplit('/').next().unwrap_or_default().to_lowercase(),
+        datetime: datetime.format(DATETIME_FORMAT).to_string(),
+        size_usd: row.size.contains('$').then(|| parse_usd(&row.size)).flatten(),
+        price_impact: match row.price_impact.trim_start().starts_with('<') {
+          true => Some(Decimal::ZERO),
+          false => parse_usd(&row.price_impact),
+        },
+        pnl: parse_usd(&row.pnl),
+        action: row.action,
+        market: row.market,
+      })
+    }
+}
+
+/// Reads the GMX trade history export, converting its local times to UTC.
+pub fn read_gmx_trades(file_path: &str) -> Result<Vec<GmxTrade>, Box<dyn Error>> {
+    let offset = utc_offset(&read_headers(file_path)?)?;
+    read_csv_with::<GmxTradeRow, _>(file_path, |x| match x.starts_with("Date") {
+      true => String::from("Date"),
+      false => x.to_string(),
+    })?
+    .into_iter()
+    .map(|x| GmxTrade::try_from((offset, x)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn trade(action: &str) -> GmxTrade {
+    GmxTrade {
+      transfer_id: String::from("0xabc"),
+      datetime: String::from("2024-01-01 00:00:00"),
+      action: action.to_string(),
+      market: String::from("Long ETH/USD"),
+      size_usd: None,
+      price_impact: None,
+      pnl: None,
+    }
+  }
+
+  #[test]
+  fn parses_usd_amounts() {
+    assert_eq!(parse_usd("-$9,645.87"), Decimal::from_str("-9645.87").ok());
+    assert_eq!(parse_usd(">  $95,492.56"), Decimal::from_str("95492.56").ok());
+    assert_eq!(parse_usd(""), None);
+  }
+
+  #[test]
+  fn reads_utc_offset() {
+    assert_eq!(utc_offset(&[String::from("Date (GMT-4)")]).unwrap(), -4);
+    assert_eq!(utc_offset(&[String::from("Date")]).unwrap(), 0);
+    assert!(utc_offset(&[String::from("Action")]).is_err());
+  }
+
+  #[test]
+  fn classifies_collateral_flows() {
+    for action in ["Market Increase", "Execute Limit", "Create Limit", "Execute Stop Market", "Deposit"] {
+      assert!(trade(action).is_increase(), "{}", action);
+      assert!(!trade(action).pays_out(), "{}", action);
+    }
+    for action in ["Market Decrease", "Execute Stop Loss", "Execute Take Profit", "Withdraw", "Failed Market Increase", "Cancel Limit"] {
+      assert!(trade(action).pays_out(), "{}", action);
+    }
+    for action in ["Create Stop Loss", "Cancel Take Profit", "Update Stop Loss"] {
+      assert!(!trade(action).is_increase() && !trade(action).pays_out(), "{}", action);
+    }
+  }
+}
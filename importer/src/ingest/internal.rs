@@ -1,14 +1,15 @@
 //! Functions for ingesting normal transaction CSVs exported from Etherscan.
 
 use serde::Deserialize;
-use crate::{read_csv, Token, Transfer, TransferDirection};
+use crate::{read_csv_with, Chain, Token, Transfer, TransferDirection};
+use crate::ingest::etherscan::normalize_header;
 use rust_decimal::Decimal;
 use std::error::Error;
 use std::str::FromStr;
 
 /// Converts a CSV transaction row into a [`Transfer`] capturing its ETH movement.
-impl From<(&str, Internal)> for Transfer {
-    fn from((address, tx): (&str, Internal)) -> Self {
+impl From<(&str, Chain, Internal)> for Transfer {
+    fn from((address, chain, tx): (&str, Chain, Internal)) -> Self {
         let (value, counterparty, direction) = match tx.from.to_lowercase() == address.to_lowercase() {
           true => (
             Decimal::from_str(&tx.value_out_eth).unwrap(),
@@ -29,26 +30,21 @@ impl From<(&str, Internal)> for Transfer {
         Transfer {
             transfer_id: tx.transaction_hash,
             datetime: tx.datetime_utc.to_string(),
-            token: Token {
-              asset: "ETH".to_string(),
-              symbol: "ETH".to_string(),
-              address: "ETH".to_string(),
-              stable_usd_value: None,
-              is_usd: false,
-              is_debt: false,
-            },
+            token: Token::eth(chain),
             value,
             usd_value,
             direction,
             counterparty: vec![counterparty],
+            account: address.to_lowercase(),
+            chain,
         }
     }
 }
 
 /// Reads a transaction CSV and converts each row into a [`Transfer`] for the
-/// supplied address.
-pub fn read_internals(file_path: &str, address: &'static str) -> Result<Vec<Transfer>, Box<dyn Error>> {
-    Ok(read_csv::<Internal>(file_path)?.into_iter().map(|x| (address, x).into()).collect())
+/// supplied address on `chain`.
+pub fn read_internals(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    Ok(read_csv_with::<Internal, _>(file_path, normalize_header)?.into_iter().map(|x| (address, chain, x).into()).collect())
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,7 +59,7 @@ pub struct Internal {
     #[serde(rename = "UnixTimestamp")]
     pub unix_timestamp: u64,
 
-    #[serde(rename = "DateTime (UTC)")]
+    #[serde(rename = "DateTime (UTC)", alias = "DateTime")]
     pub datetime_utc: String, // Consider chrono's `NaiveDateTime` if you want date parsing
 
     #[serde(rename = "ParentTxFrom")]
@@ -90,7 +86,7 @@ pub struct Internal {
     #[serde(rename = "Value_OUT(ETH)")]
     pub value_out_eth: String,
 
-    #[serde(rename = "CurrentValue @ $4045.59366105672/ETH")]
+    #[serde(rename = "CurrentValue", default)]
     pub current_value: String,
 
     #[serde(rename = "Historical $Price/ETH")]
@@ -1,5 +1,15 @@
 //! CSV ingestion helpers for tokens and transactions.
 
+/// Datetime format used for every normalized [`crate::Transfer`], matching Etherscan exports.
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
 pub mod token;
 pub mod transaction;
-pub mod internal;
\ No newline at end of file
+pub mod internal;
+pub mod nft;
+pub mod etherscan;
+pub mod custom;
+pub mod exchange;
+pub mod api;
+pub mod rpc;
+pub mod gmx;
\ No newline at end of file
@@ -0,0 +1,139 @@
+//! Functions for ingesting ERC-721 and ERC-1155 transfer CSVs exported from Etherscan.
+
+use crate::{read_csv_with, Chain, Token, Transfer, TransferDirection};
+use crate::ingest::etherscan::normalize_header;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Converts a raw NFT transfer row, the account address and its chain into a
+/// [`Transfer`] of a single token ID.
+impl From<(&str, Chain, Nft)> for Transfer {
+    fn from((address, chain, event): (&str, Chain, Nft)) -> Self {
+        let registered = Token::from((chain, &event.contract_address));
+        let token = Token {
+          asset: if registered.asset == "Unknown" { event.token_name.clone() } else { registered.asset },
+          symbol: if registered.symbol == "Unknown" { event.token_symbol.clone() } else { registered.symbol },
+          token_id: Some(event.token_id.clone()),
+          ..registered
+        };
+
+        let value = event.amount();
+
+        let (direction, counterparty) = match event.from.to_lowercase() == address.to_lowercase() {
+          true => (TransferDirection::Outgoing, event.to.clone()),
+          false => (TransferDirection::Incoming, event.from.clone()),
+        };
+
+        Transfer {
+            transfer_id: event.transaction_hash,
+            datetime: event.datetime_utc.to_string(),
+            token,
+            value,
+            usd_value: None,
+            direction,
+            counterparty: vec![counterparty],
+            account: address.to_lowercase(),
+            chain,
+        }
+    }
+}
+
+impl Nft {
+    /// Number of tokens moved, one when the export has no quantity column.
+    pub fn amount(&self) -> Decimal {
+        Decimal::from_str(&self.quantity.replace(",", "")).unwrap_or(Decimal::ONE)
+    }
+}
+
+/// Reads an NFT transfer CSV and converts each row into a [`Transfer`] for the
+/// provided address on `chain`.
+pub fn read_nfts(
+    file_path: &str,
+    address: &str,
+    chain: Chain,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    Ok(read_csv_with::<Nft, _>(file_path, normalize_header)?
+        .into_iter()
+        .map(|x| (address, chain, x).into())
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+/// Raw representation of an ERC-721 or ERC-1155 transfer row as exported by Etherscan.
+pub struct Nft {
+    #[serde(rename = "Transaction Hash", alias = "Txhash")]
+    pub transaction_hash: String,
+
+    #[serde(rename = "Blockno")]
+    pub blockno: u64,
+
+    #[serde(rename = "UnixTimestamp")]
+    pub unix_timestamp: u64,
+
+    #[serde(rename = "DateTime (UTC)", alias = "DateTime")]
+    pub datetime_utc: String,
+
+    #[serde(rename = "From")]
+    pub from: String,
+
+    #[serde(rename = "To")]
+    pub to: String,
+
+    #[serde(rename = "ContractAddress")]
+    pub contract_address: String,
+
+    #[serde(rename = "TokenName", default)]
+    pub token_name: String,
+
+    #[serde(rename = "TokenSymbol", default)]
+    pub token_symbol: String,
+
+    #[serde(rename = "Token ID", alias = "TokenId")]
+    pub token_id: String,
+
+    /// Number of tokens moved; always one for ERC-721.
+    #[serde(rename = "Quantity", alias = "TokenValue", default)]
+    pub quantity: String,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  fn read(name: &str, contents: &str) -> Vec<Nft> {
+    let file_path = std::env::temp_dir().join(format!("nft-{}-{}.csv", name, std::process::id()));
+    fs::write(&file_path, contents).unwrap();
+    let rows = read_csv_with::<Nft, _>(&file_path.to_string_lossy(), normalize_header).unwrap();
+    fs::remove_file(file_path).unwrap();
+    rows
+  }
+
+  #[test]
+  fn reads_erc721_export() {
+    let rows = read(
+      "erc721",
+      "Txhash,Blockno,UnixTimestamp,DateTime,From,To,ContractAddress,TokenName,TokenSymbol,TokenId\n\
+       0xabc,1,1704067200,2024-01-01 00:00:00,0xfrom,0xto,0xnft,Positions,UNI-V3-POS,42\n",
+    );
+
+    assert_eq!(rows[0].transaction_hash, "0xabc");
+    assert_eq!(rows[0].token_id, "42");
+    assert_eq!(rows[0].amount(), Decimal::ONE);
+  }
+
+  #[test]
+  fn reads_erc1155_export() {
+    let rows = read(
+      "erc1155",
+      "Transaction Hash,Blockno,UnixTimestamp,DateTime (UTC),From,To,ContractAddress,Token ID,TokenValue\n\
+       0xdef,1,1704067200,2024-01-01 00:00:00,0xfrom,0xto,0xnft,7,\"1,000\"\n",
+    );
+
+    assert_eq!(rows[0].token_id, "7");
+    assert_eq!(rows[0].token_name, "");
+    assert_eq!(rows[0].amount(), Decimal::from(1000));
+  }
+}
@@ -0,0 +1,227 @@
+//! Functions for decoding ERC-20 and WETH events from local JSON-RPC dumps.
+//!
+//! A dump may hold `eth_getTransactionReceipt` results, `eth_getLogs` results,
+//! raw JSON-RPC responses wrapping either, or arrays of any of these. Logs carry
+//! no timestamp unless the node returned `blockTimestamp`; otherwise include the
+//! `eth_getBlockByNumber` responses for the relevant blocks in the dump.
+
+use crate::dedup::dedup;
+use crate::ingest::api::scale_units;
+use crate::ingest::DATETIME_FORMAT;
+use crate::token::{lookup, read_token_config, TokenConfig};
+use crate::{read_json, Chain, Token, Transfer, TransferDirection};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// `Transfer(address,address,uint256)`
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+/// WETH `Deposit(address,uint256)`
+const DEPOSIT_TOPIC: &str = "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+/// WETH `Withdrawal(address,uint256)`
+const WITHDRAWAL_TOPIC: &str = "0x7fcf532c15f0a6db0bd6d0e038bea71d30d808c7d98cb3bf7268a95bf5081b65";
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+/// Raw representation of an event log.
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_number: String,
+    pub transaction_hash: String,
+    pub log_index: String,
+    pub block_timestamp: Option<String>,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// Parses a hex quantity such as `"0x1a"`.
+fn parse_quantity(raw: &str) -> Result<u128, Box<dyn Error>> {
+  Ok(u128::from_str_radix(raw.trim_start_matches("0x"), 16)?)
+}
+
+/// Decodes a 32-byte word as an unsigned amount, rejecting values above 128 bits.
+fn decode_amount(word: &str) -> Result<u128, Box<dyn Error>> {
+  let word = word.trim_start_matches("0x");
+  let (high, low) = word.split_at(word.len().saturating_sub(32));
+  if high.chars().any(|x| x != '0') {
+    Err(format!("amount 0x{} exceeds 128 bits", word))?
+  }
+  parse_quantity(low)
+}
+
+/// Decodes an indexed address topic into a lowercase `0x` address.
+fn decode_address(topic: &str) -> String {
+  let topic = topic.trim_start_matches("0x");
+  format!("0x{}", &topic[topic.len().saturating_sub(40)..]).to_lowercase()
+}
+
+/// Recursively collects logs and block timestamps from any supported dump shape.
+fn collect(value: &Value, logs: &mut Vec<Log>, blocks: &mut HashMap<u128, u128>) -> Result<(), Box<dyn Error>> {
+  match value {
+    Value::Array(items) => items.iter().try_for_each(|x| collect(x, logs, blocks)),
+    Value::Object(object) if object.contains_key("result") => collect(&object["result"], logs, blocks),
+    Value::Object(object) if object.contains_key("logs") => collect(&object["logs"], logs, blocks),
+    Value::Object(object) if object.contains_key("topics") => {
+      logs.push(serde_json::from_value(value.clone())?);
+      Ok(())
+    },
+    Value::Object(object) if object.contains_key("timestamp") && object.contains_key("number") => {
+      let number = parse_quantity(object["number"].as_str().ok_or("invalid block number")?)?;
+      let timestamp = parse_quantity(object["timestamp"].as_str().ok_or("invalid block timestamp")?)?;
+      blocks.insert(number, timestamp);
+      Ok(())
+    },
+    _ => Ok(()),
+  }
+}
+
+/// Decodes a log into a [`Transfer`] if it moves a registered token to or from
+/// one of `addresses`. Tokens missing from the registry (typically spam) are
+/// skipped; registered tokens without `decimals` are an error.
+fn decode(
+    log: &Log,
+    chain: Chain,
+    addresses: &HashSet<String>,
+    config: &TokenConfig,
+    blocks: &HashMap<u128, u128>,
+) -> Result<Option<Transfer>, Box<dyn Error>> {
+    let contract = log.address.to_lowercase();
+    let topic = |idx: usize| log.topics.get(idx).map(|x| x.to_lowercase());
+
+    let (from, to) = match topic(0).as_deref() {
+      Some(TRANSFER_TOPIC) if log.topics.len() == 3 => (decode_address(&log.topics[1]), decode_address(&log.topics[2])),
+      Some(DEPOSIT_TOPIC) if log.topics.len() == 2 => (contract.clone(), decode_address(&log.topics[1])),
+      Some(WITHDRAWAL_TOPIC) if log.topics.len() == 2 => (decode_address(&log.topics[1]), contract.clone()),
+      _ => return Ok(None),
+    };
+
+    let (account, counterparty, direction) = if addresses.contains(&from) {
+      (from, to, TransferDirection::Outgoing)
+    } else if addresses.contains(&to) {
+      (to, from, TransferDirection::Incoming)
+    } else {
+      return Ok(None);
+    };
+
+    let Some(meta) = lookup(config, chain, &contract) else { return Ok(None) };
+    let decimals = meta.decimals.ok_or(format!("{}:{}: no decimals in data/ref/tokens.toml", chain, contract))?;
+
+    let block = parse_quantity(&log.block_number)?;
+    let timestamp = match &log.block_timestamp {
+      Some(timestamp) => parse_quantity(timestamp)?,
+      None => *blocks.get(&block).ok_or(format!("no timestamp for block {}; include eth_getBlockByNumber responses", block))?,
+    };
+    let datetime = DateTime::from_timestamp(timestamp as i64, 0).ok_or("invalid timestamp")?;
+
+    let token = Token::from((chain, &contract));
+    let value = scale_units(&decode_amount(&log.data)?.to_string(), decimals)?;
+
+    Ok(Some(Transfer {
+      transfer_id: log.transaction_hash.to_lowercase(),
+      datetime: datetime.format(DATETIME_FORMAT).to_string(),
+      usd_value: token.stable_usd_value.map(|x| x * value),
+      token,
+      value,
+      direction,
+      counterparty: vec![counterparty],
+      account,
+      chain,
+    }))
+}
+
+/// Reads a JSON-RPC dump and decodes the ERC-20 transfers and WETH deposits and
+/// withdrawals of `addresses` on `chain`. Logs present more than once are decoded once.
+pub fn read_rpc(file_path: &str, addresses: &[&str], chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let mut logs = vec![];
+    let mut blocks = HashMap::new();
+    collect(&read_json::<Value>(file_path)?, &mut logs, &mut blocks)?;
+
+    let addresses: HashSet<String> = addresses.iter().map(|x| x.to_lowercase()).collect();
+    let config = read_token_config();
+
+    let mut seen = HashSet::new();
+    let mut transfers = vec![];
+    for log in logs.iter().filter(|x| !x.removed) {
+      if !seen.insert((log.transaction_hash.to_lowercase(), log.log_index.clone())) {
+        continue;
+      }
+      transfers.extend(decode(log, chain, &addresses, &config, &blocks)?);
+    }
+    Ok(transfers)
+}
+
+/// Reads every JSON-RPC dump found in `dir`, returning nothing when the directory is absent.
+/// Transfers repeated across overlapping files are kept once.
+pub fn read_rpc_dir(dir: &str, addresses: &[&str], chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+      return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(path)? {
+      let file_path = entry?.path();
+      if file_path.extension().is_some_and(|x| x == "json") {
+        paths.push(file_path.to_string_lossy().to_string());
+      }
+    }
+    paths.sort();
+
+    let mut files = vec![];
+    for file_path in paths {
+      let transfers = read_rpc(&file_path, addresses, chain)?;
+      files.push((file_path, transfers));
+    }
+    Ok(dedup(files).0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn log(hash: &str) -> Value {
+    json!({
+      "address": "0xToken",
+      "topics": [TRANSFER_TOPIC],
+      "data": "0x",
+      "blockNumber": "0x10",
+      "transactionHash": hash,
+      "logIndex": "0x0",
+    })
+  }
+
+  #[test]
+  fn decodes_words() {
+    assert_eq!(parse_quantity("0x1a").unwrap(), 26);
+    assert_eq!(decode_amount(&format!("0x{:0>64}", "de0b6b3a7640000")).unwrap(), 1_000_000_000_000_000_000);
+    assert!(decode_amount(&format!("0x1{:0>63}", "1")).is_err());
+    assert_eq!(
+      decode_address("0x000000000000000000000000AbCdEf0123456789aBcDeF0123456789AbCdEf01"),
+      "0xabcdef0123456789abcdef0123456789abcdef01"
+    );
+  }
+
+  #[test]
+  fn collects_every_dump_shape() {
+    let dump = json!([
+      {"jsonrpc": "2.0", "id": 1, "result": [log("0x1")]},
+      {"jsonrpc": "2.0", "id": 2, "result": {"status": "0x1", "logs": [log("0x2")]}},
+      log("0x3"),
+      {"jsonrpc": "2.0", "id": 3, "result": {"number": "0x10", "timestamp": "0x65920080"}},
+    ]);
+
+    let (mut logs, mut blocks) = (vec![], HashMap::new());
+    collect(&dump, &mut logs, &mut blocks).unwrap();
+
+    let hashes: Vec<&str> = logs.iter().map(|x| x.transaction_hash.as_str()).collect();
+    assert_eq!(hashes, vec!["0x1", "0x2", "0x3"]);
+    assert_eq!(blocks.get(&16), Some(&1_704_067_200));
+  }
+}
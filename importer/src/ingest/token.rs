@@ -1,16 +1,22 @@
 //! Functions for ingesting token transfer CSVs exported from Etherscan.
 
-use crate::{read_csv, Token as TokenMeta, Transfer, TransferDirection};
+use crate::{read_csv_with, Chain, Token as TokenMeta, Transfer, TransferDirection};
+use crate::ingest::etherscan::normalize_header;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::error::Error;
 use std::str::FromStr;
 
-/// Converts a raw CSV token transfer and the account address into a normalized [`Transfer`].
-impl From<(&str, Token)> for Transfer {
-    fn from((address, event): (&str, Token)) -> Self {
+/// Converts a raw CSV token transfer, the account address and its chain into a
+/// normalized [`Transfer`].
+impl From<(&str, Chain, Token)> for Transfer {
+    fn from((address, chain, event): (&str, Chain, Token)) -> Self {
 
-        let token: TokenMeta = (&event.contract_address).into();
+        let mut token: TokenMeta = (chain, &event.contract_address).into();
+        // Unregistered contracts keep the symbol they claim for the spam filter.
+        if token.asset == TokenMeta::default().asset {
+            token.symbol = event.token_symbol;
+        }
 
         let value = Decimal::from_str(&event.token_value.replace(",", "")).unwrap();
         let mut usd_value = Decimal::from_str(&event.usd_value_day_of_tx.replace(",", "").replace("$", "")).ok();
@@ -41,19 +47,22 @@ impl From<(&str, Token)> for Transfer {
             usd_value,
             direction,
             counterparty: vec![counterparty],
+            account: address.to_lowercase(),
+            chain,
         }
     }
 }
 
 /// Reads a token transfer CSV and converts each row into a [`Transfer`] for the
-/// provided address.
+/// provided address on `chain`.
 pub fn read_tokens(
     file_path: &str,
-    address: &'static str,
+    address: &str,
+    chain: Chain,
 ) -> Result<Vec<Transfer>, Box<dyn Error>> {
-    Ok(read_csv::<Token>(file_path)?
+    Ok(read_csv_with::<Token, _>(file_path, normalize_header)?
         .into_iter()
-        .map(|x| (address, x).into())
+        .map(|x| (address, chain, x).into())
         .collect())
 }
 
@@ -61,7 +70,7 @@ pub fn read_tokens(
 #[serde(rename_all = "PascalCase")] // Automatically handles camel case, e.g., "Transaction Hash" -> "TransactionHash"
 /// Raw representation of a token transfer row as exported by Etherscan.
 pub struct Token {
-    #[serde(rename = "Transaction Hash")]
+    #[serde(rename = "Transaction Hash", alias = "Txhash")]
     pub transaction_hash: String,
 
     pub blockno: u64,
@@ -69,7 +78,7 @@ pub struct Token {
     #[serde(rename = "UnixTimestamp")]
     pub unix_timestamp: u64,
 
-    #[serde(rename = "DateTime (UTC)")]
+    #[serde(rename = "DateTime (UTC)", alias = "DateTime")]
     pub datetime_utc: String, // You could use `chrono::NaiveDateTime` here for better handling of time
 
     pub from: String,
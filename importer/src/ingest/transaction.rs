@@ -1,14 +1,15 @@
 //! Functions for ingesting normal transaction CSVs exported from Etherscan.
 
 use serde::Deserialize;
-use crate::{read_csv, Token, Transfer, TransferDirection};
+use crate::{read_csv_with, Chain, Fee, Token, Transfer, TransferDirection};
+use crate::ingest::etherscan::normalize_header;
 use rust_decimal::Decimal;
 use std::error::Error;
 use std::str::FromStr;
 
 /// Converts a CSV transaction row into a [`Transfer`] capturing its ETH movement.
-impl From<(&str, Transaction)> for Transfer {
-    fn from((address, tx): (&str, Transaction)) -> Self {
+impl From<(&str, Chain, Transaction)> for Transfer {
+    fn from((address, chain, tx): (&str, Chain, Transaction)) -> Self {
         let (value, counterparty, direction) = match tx.from.to_lowercase() == address.to_lowercase() {
           true => (
             Decimal::from_str(&tx.value_out_eth).unwrap(),
@@ -29,33 +30,62 @@ impl From<(&str, Transaction)> for Transfer {
         Transfer {
             transfer_id: tx.txhash,
             datetime: tx.datetime_utc.to_string(),
-            token: Token {
-              asset: "ETH".to_string(),
-              symbol: "ETH".to_string(),
-              address: "ETH".to_string(),
-              stable_usd_value: None,
-              is_usd: false,
-              is_debt: false,
-            },
+            token: Token::eth(chain),
             value,
             usd_value,
             direction,
             counterparty: vec![counterparty],
+            account: address.to_lowercase(),
+            chain,
         }
     }
 }
 
 /// Reads a transaction CSV and converts each row into a [`Transfer`] for the
-/// supplied address.
-pub fn read_transactions(file_path: &str, address: &'static str) -> Result<Vec<Transfer>, Box<dyn Error>> {
-    Ok(read_csv::<Transaction>(file_path)?.into_iter().map(|x| (address, x).into()).collect())
+/// supplied address on `chain`.
+pub fn read_transactions(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    Ok(read_csv_with::<Transaction, _>(file_path, normalize_header)?.into_iter().map(|x| (address, chain, x).into()).collect())
+}
+
+/// Converts a CSV transaction row sent by the account into the [`Fee`] it paid.
+impl From<(&str, Chain, &Transaction)> for Fee {
+    fn from((address, chain, tx): (&str, Chain, &Transaction)) -> Self {
+        let value = Decimal::from_str(&tx.txn_fee_eth).unwrap_or_default();
+        // TxnFee(USD) is at the price of the export date, so prefer the historical one
+        let usd_value = Decimal::from_str(&tx.historical_price_eth)
+          .ok()
+          .map(|price| price * value)
+          .or_else(|| Decimal::from_str(&tx.txn_fee_usd).ok());
+
+        Fee {
+            transfer_id: tx.txhash.clone(),
+            datetime: tx.datetime_utc.to_string(),
+            chain,
+            account: address.to_lowercase(),
+            counterparty: tx.to.to_lowercase(),
+            token: Token::eth(chain),
+            value,
+            usd_value,
+            value_sent: Decimal::from_str(&tx.value_out_eth).unwrap_or_default(),
+        }
+    }
+}
+
+/// Reads a transaction CSV and returns the fees paid by the supplied address
+/// on `chain`, including those of failed transactions.
+pub fn read_fees(file_path: &str, address: &str, chain: Chain) -> Result<Vec<Fee>, Box<dyn Error>> {
+    Ok(read_csv_with::<Transaction, _>(file_path, normalize_header)?
+        .iter()
+        .filter(|x| x.from.to_lowercase() == address.to_lowercase())
+        .map(|x| (address, chain, x).into())
+        .collect())
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 /// Raw representation of an Etherscan transaction export.
 pub struct Transaction {
-    #[serde(rename = "Txhash")]
+    #[serde(rename = "Txhash", alias = "Transaction Hash")]
     pub txhash: String,
 
     pub blockno: u64,
@@ -63,7 +93,7 @@ pub struct Transaction {
     #[serde(rename = "UnixTimestamp")]
     pub unix_timestamp: u64,
 
-    #[serde(rename = "DateTime (UTC)")]
+    #[serde(rename = "DateTime (UTC)", alias = "DateTime")]
     pub datetime_utc: String,  // You could use chrono for better handling of time
 
     pub from: String,
@@ -79,7 +109,7 @@ pub struct Transaction {
     #[serde(rename = "Value_OUT(ETH)")]
     pub value_out_eth: String,
 
-    #[serde(rename = "CurrentValue @ $3525.11740105424/ETH")]
+    #[serde(rename = "CurrentValue", default)]
     pub current_value: String,
 
     #[serde(rename = "TxnFee(ETH)")]
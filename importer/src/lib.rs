@@ -1,24 +1,48 @@
 //! Library utilities for reading, normalizing and classifying blockchain data.
 
 mod read_csv;
-pub use read_csv::{read_csv, write_csv};
+pub use read_csv::{
+  read_csv, read_csv_after, read_csv_with, read_headers, read_json, write_csv, write_json, write_ndjson,
+};
 pub mod category;
 pub mod categories;
 pub mod token;
 pub mod direction;
+pub mod chain;
+pub mod bridge;
 
 mod types;
 pub use types::{
-  SwapSubCategory, Token, Transaction, TransactionCategory,
+  Chain, SwapSubCategory, Token, Transaction, TransactionCategory,
   Transfer, TransferDirection, TwoAssetSwap, SwapDirection,
-  DebtSwap, DebtDirection, PortfolioItem
+  DebtSwap, DebtDirection, PortfolioItem, CostBasisTransfer, Lot, Fee, Income
 };
 
 pub mod ingest;
 pub use ingest::token::read_tokens;
-pub use ingest::transaction::read_transactions;
+pub use ingest::transaction::{read_fees, read_transactions};
 pub use ingest::internal::read_internals;
+pub use ingest::nft::read_nfts;
+pub use ingest::etherscan::{read_etherscan, read_etherscan_dir, read_fees_dir, EtherscanExport};
+pub use ingest::custom::{read_custom, read_sources};
+pub use ingest::exchange::{link_transfers, read_exchange, read_exchange_dir};
+pub use ingest::api::{read_api, read_api_dir, read_api_fees_dir};
+pub use ingest::rpc::{read_rpc, read_rpc_dir};
 
 pub mod transaction;
 pub mod transfer;
-pub mod portfolio;
\ No newline at end of file
+pub mod portfolio;
+pub mod schema;
+pub mod sqlite;
+pub mod store;
+pub mod dedup;
+pub mod export;
+pub mod price;
+pub mod nav;
+pub mod performance;
+pub mod gas;
+pub mod slippage;
+pub mod income;
+pub mod spam;
+pub mod review;
+pub mod adjustment;
\ No newline at end of file
@@ -0,0 +1,110 @@
+//! Daily net asset value (NAV) of the portfolio.
+//!
+//! Transactions and network fees are replayed through the lot engine in the
+//! same order as for the holdings, and the open lots at the end of each UTC
+//! day are valued with the
+//! [`PriceStore`]. Amounts without a known price (NFTs, unregistered tokens)
+//! are left out of the valuation.
+
+use crate::portfolio::{replay_order, Portfolio};
+use crate::price::{parse_date, PriceStore};
+use crate::schema::SCHEMA_VERSION;
+use crate::{Fee, Transaction};
+use chrono::{Days, NaiveDate};
+use csv::Writer;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Value of the portfolio at the end of a day.
+pub struct NavRecord {
+  pub schema_version: u32,
+  pub date: NaiveDate,
+  /// USD value of everything held.
+  pub total_usd: Decimal,
+  /// USD value held per underlying asset.
+  pub assets: BTreeMap<String, Decimal>,
+  /// USD value owed on Aave debt.
+  pub debt_usd: Decimal,
+  /// `total_usd - debt_usd`.
+  pub net_usd: Decimal,
+}
+
+/// Values the open lots of `portfolio` at the prices of `date`.
+fn valuation(portfolio: &Portfolio, prices: &PriceStore, date: NaiveDate) -> NavRecord {
+  let mut assets: BTreeMap<String, Decimal> = BTreeMap::new();
+  let mut debt_usd = Decimal::ZERO;
+
+  for item in portfolio.holdings().iter().filter(|x| !x.token.is_nft()) {
+    let asset = item.token.asset.trim_start_matches("Debt ");
+    let Some(price) = prices.price(asset, date) else { continue };
+    match item.token.is_debt {
+      true => debt_usd += item.amount * price,
+      false => *assets.entry(asset.to_string()).or_default() += item.amount * price,
+    }
+  }
+
+  // Rounded to cents so the breakdown adds up to the total
+  assets.values_mut().for_each(|x| *x = x.round_dp(2));
+  let debt_usd = debt_usd.round_dp(2);
+  let total_usd: Decimal = assets.values().sum();
+  NavRecord {
+    schema_version: SCHEMA_VERSION,
+    date,
+    total_usd,
+    assets,
+    debt_usd,
+    net_usd: total_usd - debt_usd,
+  }
+}
+
+/// One record per day from the first transaction to the last transaction or
+/// price, whichever is later. `fees` and `lot_assignments` are those the
+/// holdings were derived with.
+pub fn nav(
+    transactions: &[Transaction],
+    fees: &[Fee],
+    prices: &PriceStore,
+    lot_assignments: &HashMap<String, Vec<String>>,
+) -> Vec<NavRecord> {
+    let steps = replay_order(transactions, fees);
+    let dates: Vec<NaiveDate> = steps.iter().filter_map(|x| parse_date(x.datetime())).collect();
+    let (Some(&first), Some(&last)) = (dates.first(), dates.last()) else { return vec![] };
+    let last = prices.last_date().map_or(last, |x| x.max(last));
+
+    let mut portfolio = Portfolio::new(prices, lot_assignments);
+    let mut pending = steps.iter().peekable();
+    let mut records = vec![];
+    let mut date = first;
+    while date <= last {
+      while let Some(step) = pending.next_if(|x| parse_date(x.datetime()).is_some_and(|x| x <= date)) {
+        portfolio.replay(step);
+      }
+      records.push(valuation(&portfolio, prices, date));
+      date = date + Days::new(1);
+    }
+    records
+}
+
+/// Writes the records as `nav.csv`: one column per underlying asset ever held
+/// between the total and the debt.
+pub fn write_nav_csv(records: &[NavRecord], file_path: &str) -> Result<(), Box<dyn Error>> {
+    let assets: BTreeSet<&String> = records.iter().flat_map(|x| x.assets.keys()).collect();
+    let mut wtr = Writer::from_path(file_path)?;
+
+    let mut header = vec![String::from("schema_version"), String::from("date"), String::from("total_usd")];
+    header.extend(assets.iter().map(|x| x.to_string()));
+    header.extend([String::from("debt_usd"), String::from("net_usd")]);
+    wtr.write_record(&header)?;
+
+    for record in records {
+      let mut row = vec![record.schema_version.to_string(), record.date.to_string(), record.total_usd.to_string()];
+      row.extend(assets.iter().map(|x| record.assets.get(*x).map(|x| x.to_string()).unwrap_or_default()));
+      row.extend([record.debt_usd.to_string(), record.net_usd.to_string()]);
+      wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,347 @@
+//! Time- and money-weighted returns on top of the NAV series.
+//!
+//! Deposits into and withdrawals out of our wallets (`Transfer` or `Bridge`
+//! transactions whose legs all go one way) are the external cash flows of the
+//! portfolio; moves between our own accounts are not. Money coming in is
+//! assumed to arrive at the start of its day and money going out to leave at
+//! its end, so a day's return is
+//! `(value + outflow) / (previous value + inflow) - 1`. Days starting with
+//! less than [`MIN_VALUE`] invested, or ending below zero (net of debt), are
+//! skipped. Each underlying asset is
+//! measured as a sleeve of its own, for which every leg moving the asset is a
+//! flow.
+//!
+//! Statistics are computed in `f64`: returns compound daily, volatility is
+//! annualised over 365 days since markets never close, and the Sharpe-like
+//! ratio is the annualised mean daily return above the risk-free rate divided
+//! by that volatility.
+
+use crate::nav::NavRecord;
+use crate::price::{parse_date, PriceStore};
+use crate::{Transaction, TransactionCategory, Transfer, TransferDirection};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use itertools::Itertools;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Days per year used to annualise.
+const DAYS_PER_YEAR: f64 = 365.0;
+
+/// USD invested below which a day's return is too noisy to count.
+pub const MIN_VALUE: f64 = 1.0;
+
+#[derive(Debug, Default, Clone, Copy)]
+/// USD moved into and out of the portfolio or an asset on one day.
+pub struct DailyFlow {
+  pub inflow: Decimal,
+  pub outflow: Decimal,
+}
+
+impl DailyFlow {
+  /// Inflow minus outflow.
+  pub fn net(&self) -> Decimal {
+    self.inflow - self.outflow
+  }
+}
+
+/// Daily flows keyed by date.
+pub type Flows = BTreeMap<NaiveDate, DailyFlow>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Window ending on the last day of the NAV series.
+pub enum Period {
+  All,
+  YearToDate,
+  Days(u32),
+  Months(u32),
+  Years(u32),
+}
+
+impl Period {
+  /// First day of the period ending on `end`, not before `first`.
+  pub fn start(&self, first: NaiveDate, end: NaiveDate) -> NaiveDate {
+    let start = match self {
+      Period::All => first,
+      Period::YearToDate => NaiveDate::from_ymd_opt(end.year(), 1, 1).unwrap_or(first),
+      Period::Days(days) => end.checked_sub_days(Days::new(*days as u64)).unwrap_or(first),
+      Period::Months(months) => end.checked_sub_months(Months::new(*months)).unwrap_or(first),
+      Period::Years(years) => end.checked_sub_months(Months::new(years * 12)).unwrap_or(first),
+    };
+    start.max(first)
+  }
+}
+
+/// Parses `all`, `ytd` or a count of days, months or years such as `30d`, `6m` or `1y`.
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+      let s = s.trim().to_lowercase();
+      match s.as_str() {
+        "all" => return Ok(Period::All),
+        "ytd" => return Ok(Period::YearToDate),
+        _ => {},
+      }
+      let invalid = || format!("invalid period '{}': expected all, ytd, <n>d, <n>m or <n>y", s);
+      let unit = s.chars().last().ok_or_else(invalid)?;
+      let count: u32 = s.strip_suffix(['d', 'm', 'y']).and_then(|x| x.parse().ok()).ok_or_else(invalid)?;
+      match unit {
+        'd' => Ok(Period::Days(count)),
+        'm' => Ok(Period::Months(count)),
+        _ => Ok(Period::Years(count)),
+      }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+        Period::All => write!(f, "all"),
+        Period::YearToDate => write!(f, "ytd"),
+        Period::Days(days) => write!(f, "{}d", days),
+        Period::Months(months) => write!(f, "{}m", months),
+        Period::Years(years) => write!(f, "{}y", years),
+      }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+/// Returns and risk statistics of the portfolio or one asset over a period.
+pub struct Performance {
+  /// `portfolio` or the underlying asset.
+  pub scope: String,
+  pub period: String,
+  pub from: NaiveDate,
+  pub to: NaiveDate,
+  pub start_value: Decimal,
+  pub end_value: Decimal,
+  /// Deposits minus withdrawals within the period.
+  pub net_flows: Decimal,
+  /// Time-weighted return over the period.
+  pub twr: Option<f64>,
+  /// Time-weighted return per year, for periods of at least a year.
+  pub twr_annualized: Option<f64>,
+  /// Money-weighted return per year (XIRR).
+  pub xirr: Option<f64>,
+  /// Largest fall of the time-weighted index from a previous peak.
+  pub max_drawdown: Option<f64>,
+  /// Annualised standard deviation of daily returns.
+  pub volatility: Option<f64>,
+  pub sharpe: Option<f64>,
+}
+
+/// USD value of a leg on its day: its own USD value when known, else the
+/// day's price of its underlying asset.
+fn flow_value(transfer: &Transfer, prices: &PriceStore) -> Option<Decimal> {
+  let date = parse_date(&transfer.datetime)?;
+  match transfer.usd_value {
+    Some(usd_value) => Some(usd_value),
+    None => Some(transfer.value * prices.price(transfer.token.asset.trim_start_matches("Debt "), date)?),
+  }
+}
+
+/// Whether a transaction moves value between our own accounts or chains; any
+/// fee it costs stays part of the return.
+fn is_own_move(transaction: &Transaction) -> bool {
+  matches!(transaction.category, TransactionCategory::Transfer | TransactionCategory::Bridge)
+    && !transaction.net_transfers.iter().map(|x| &x.direction).all_equal()
+}
+
+/// Adds the legs selected by `filter` to the daily flows.
+fn add_flows<F>(flows: &mut Flows, transaction: &Transaction, prices: &PriceStore, filter: F)
+where
+    F: Fn(&Transfer) -> bool,
+{
+  for transfer in transaction.net_transfers.iter().filter(|x| !x.token.is_nft() && filter(x)) {
+    if let (Some(date), Some(value)) = (parse_date(&transfer.datetime), flow_value(transfer, prices)) {
+      let flow = flows.entry(date).or_default();
+      match transfer.direction {
+        TransferDirection::Incoming => flow.inflow += value,
+        TransferDirection::Outgoing => flow.outflow += value,
+      }
+    }
+  }
+}
+
+/// Daily external flows of the portfolio: deposits in, withdrawals out.
+pub fn external_flows(transactions: &[Transaction], prices: &PriceStore) -> Flows {
+  let mut flows = BTreeMap::new();
+  let deposits_and_withdrawals = transactions.iter().filter(|x| {
+    matches!(x.category, TransactionCategory::Transfer | TransactionCategory::Bridge) && !is_own_move(x)
+  });
+  for transaction in deposits_and_withdrawals {
+    add_flows(&mut flows, transaction, prices, |_| true);
+  }
+  flows
+}
+
+/// Daily flows into and out of the sleeve of `asset`: every leg moving it,
+/// except moves between our own accounts.
+pub fn asset_flows(transactions: &[Transaction], prices: &PriceStore, asset: &str) -> Flows {
+  let mut flows = BTreeMap::new();
+  for transaction in transactions.iter().filter(|x| x.category != TransactionCategory::Ignore && !is_own_move(x)) {
+    add_flows(&mut flows, transaction, prices, |x| !x.token.is_debt && x.token.asset == asset);
+  }
+  flows
+}
+
+/// Annual rate at which the dated cash flows have a net present value of zero,
+/// found by bisection. Flows are from the investor's side: money put in is
+/// negative, money taken out (or held at the end) positive.
+pub fn xirr(flows: &[(NaiveDate, f64)]) -> Option<f64> {
+  let first = flows.first()?.0;
+  let npv = |rate: f64| -> f64 {
+    flows
+      .iter()
+      .map(|(date, flow)| flow / (1.0 + rate).powf((*date - first).num_days() as f64 / DAYS_PER_YEAR))
+      .sum()
+  };
+
+  let (mut low, mut high) = (-0.9999, 1.0);
+  while npv(high) > 0.0 && high < 1e9 {
+    high *= 2.0;
+  }
+  if npv(low).signum() == npv(high).signum() {
+    return None;
+  }
+  for _ in 0..200 {
+    let mid = (low + high) / 2.0;
+    if npv(mid).signum() == npv(low).signum() {
+      low = mid;
+    } else {
+      high = mid;
+    }
+  }
+  Some((low + high) / 2.0)
+}
+
+/// Performance of a daily value series with its flows between `from` and `to`.
+pub fn performance(
+    scope: &str,
+    period: Period,
+    values: &BTreeMap<NaiveDate, Decimal>,
+    flows: &Flows,
+    from: NaiveDate,
+    to: NaiveDate,
+    risk_free: f64,
+) -> Performance {
+    let value = |date: NaiveDate| values.get(&date).copied().unwrap_or_default();
+    let f = |x: Decimal| x.to_f64().unwrap_or_default();
+
+    // The value at the start is taken as invested on `from`; flows count from the next day
+    let mut returns = vec![];
+    let mut index = vec![1.0];
+    let mut date = from;
+    while date < to {
+      let next = date + Days::new(1);
+      let flow = flows.get(&next).copied().unwrap_or_default();
+      let (base, end) = (f(value(date) + flow.inflow), f(value(next) + flow.outflow));
+      if base >= MIN_VALUE && end >= 0.0 {
+        let daily = end / base - 1.0;
+        returns.push(daily);
+        index.push(index.last().unwrap() * (1.0 + daily));
+      }
+      date = next;
+    }
+
+    let days = (to - from).num_days() as f64;
+    let twr = (!returns.is_empty()).then(|| index.last().unwrap() - 1.0);
+    let twr_annualized = twr.filter(|_| days >= DAYS_PER_YEAR).map(|x| (1.0 + x).powf(DAYS_PER_YEAR / days) - 1.0);
+
+    let mut peak = f64::MIN;
+    let max_drawdown = (!returns.is_empty()).then(|| {
+      index.iter().fold(0.0, |drawdown: f64, x| {
+        peak = peak.max(*x);
+        drawdown.max(1.0 - x / peak)
+      })
+    });
+
+    let count = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / count;
+    let volatility = (returns.len() > 1).then(|| {
+      let variance = returns.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0);
+      variance.sqrt() * DAYS_PER_YEAR.sqrt()
+    });
+    let sharpe = volatility.filter(|x| *x > 0.0).map(|x| (mean * DAYS_PER_YEAR - risk_free) / x);
+
+    let period_flows: Vec<(NaiveDate, Decimal)> = flows.range(from + Days::new(1)..=to).map(|(d, x)| (*d, x.net())).collect();
+    let mut cash_flows = vec![(from, -f(value(from)))];
+    cash_flows.extend(period_flows.iter().map(|(date, flow)| (*date, -f(*flow))));
+    cash_flows.push((to, f(value(to))));
+    let xirr = xirr(&cash_flows).filter(|_| days > 0.0);
+
+    Performance {
+      scope: scope.to_string(),
+      period: period.to_string(),
+      from,
+      to,
+      start_value: value(from),
+      end_value: value(to),
+      net_flows: period_flows.iter().map(|x| x.1).sum(),
+      twr,
+      twr_annualized,
+      xirr,
+      max_drawdown,
+      volatility,
+      sharpe,
+    }
+}
+
+/// Performance of the portfolio (net of debt) and of every asset held, over each period.
+pub fn report(
+    transactions: &[Transaction],
+    records: &[NavRecord],
+    prices: &PriceStore,
+    periods: &[Period],
+    risk_free: f64,
+) -> Vec<Performance> {
+    let (Some(first), Some(last)) = (records.first(), records.last()) else { return vec![] };
+
+    let mut scopes: Vec<(String, BTreeMap<NaiveDate, Decimal>, Flows)> = vec![(
+      String::from("portfolio"),
+      records.iter().map(|x| (x.date, x.net_usd)).collect(),
+      external_flows(transactions, prices),
+    )];
+    let assets: BTreeSet<&String> = records.iter().flat_map(|x| x.assets.keys()).collect();
+    for asset in assets {
+      scopes.push((
+        asset.clone(),
+        records.iter().map(|x| (x.date, x.assets.get(asset).copied().unwrap_or_default())).collect(),
+        asset_flows(transactions, prices, asset),
+      ));
+    }
+
+    let mut report = vec![];
+    for period in periods {
+      let from = period.start(first.date, last.date);
+      for (scope, values, flows) in &scopes {
+        report.push(performance(scope, *period, values, flows, from, last.date, risk_free));
+      }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_periods() {
+    assert_eq!("All".parse::<Period>(), Ok(Period::All));
+    assert_eq!(" ytd ".parse::<Period>(), Ok(Period::YearToDate));
+    assert_eq!("30d".parse::<Period>(), Ok(Period::Days(30)));
+    assert_eq!("6M".parse::<Period>(), Ok(Period::Months(6)));
+    assert_eq!("1y".parse::<Period>(), Ok(Period::Years(1)));
+  }
+
+  #[test]
+  fn rejects_invalid_periods() {
+    for input in ["", "d", "30", "30w", "-1d", "1é", "€"] {
+      assert!(input.parse::<Period>().is_err(), "{}", input);
+    }
+  }
+}
@@ -1,42 +1,524 @@
-use crate::{Transaction, PortfolioItem, Token, Transfer, TransactionCategory, SwapSubCategory};
-use std::collections::HashMap;
-use itertools::Itertools;
+//! FIFO lot tracking used to derive holdings, cost basis and realised gains.
+//!
+//! Lots are pooled by underlying asset (`Token::asset`), so moving a token
+//! between our own wallets, exchange accounts and chains never touches its lots. NFTs
+//! are pooled per chain, contract and token ID. Network fees consume the
+//! native token they were paid in without realising anything.
 
+use crate::price::{parse_date, PriceStore};
+use crate::token::{read_token_config, zero_basis_assets};
+use crate::{
+  CostBasisTransfer, Fee, Income, Lot, PortfolioItem, SwapSubCategory, Token, Transaction, TransactionCategory,
+  Transfer, TransferDirection,
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
+/// Convert classified transactions into the resulting holdings.
 pub trait ToPortfolio {
+    /// Consumes the implementor and returns one [`PortfolioItem`] per held asset.
     fn to_portfolio(self) -> Vec<PortfolioItem>;
 }
 
-impl ToPortfolio for Vec<Transaction> {
-    fn to_portfolio(self) -> Vec<PortfolioItem> {
-      let portfolio: Vec<PortfolioItem> = vec![];
-
-      let asset_map = self.iter().fold(HashMap::new(), |mut map: HashMap<Token, PortfolioItem>, transaction| {
-        transaction.net_transfers.iter().for_each(|transfer|{
-          let item = map.entry(transfer.token.clone());
-          match &transaction.category {
-            TransactionCategory::Swap(SwapSubCategory::TwoAsset(data)) => {
-            },
-            TransactionCategory::Swap(SwapSubCategory::Debt(data)) => {
-            },
-            _ => {
-              /*let (value, cost_basis) match transfer.direction {
-                TransferDirection::Incoming => 
-              }*/
-
-            },
-          };
+#[derive(Debug, Clone)]
+/// Lot opened by an incoming transfer or slice of a lot consumed by an
+/// outgoing one.
+pub struct LotMovement {
+  /// Identifier of the transaction the transfer belongs to.
+  pub transfer_id: String,
+  /// Token of the transfer, which may differ from the lot's token within the
+  /// same underlying asset.
+  pub token: Token,
+  /// `Incoming` when the lot was opened, `Outgoing` when it was consumed.
+  pub direction: TransferDirection,
+  /// Lot opened, or slice consumed together with the cost basis it carried.
+  pub lot: Lot,
+}
+
+#[derive(Debug, Default, Clone)]
+/// Open lots per underlying asset and the disposals realised so far.
+pub struct Portfolio {
+  /// Open lots keyed by underlying asset, oldest first.
+  pub lots: HashMap<String, VecDeque<Lot>>,
+  /// Realised disposals in the order they occurred.
+  pub disposals: Vec<CostBasisTransfer>,
+  /// Income received in the order it occurred.
+  pub income: Vec<Income>,
+  /// Every lot opened or consumed, in the order it happened.
+  pub journal: Vec<LotMovement>,
+  /// Prices valuing income received without a USD value.
+  pub prices: PriceStore,
+  /// Assets whose income opens lots at zero cost.
+  pub zero_basis: HashSet<String>,
+  /// Acquisitions whose lots a transaction consumes first, by `transfer_id`.
+  pub lot_assignments: HashMap<String, Vec<String>>,
+}
+
+impl Portfolio {
+  /// Empty portfolio valuing income with `prices` and the `zero_basis`
+  /// assets of `data/ref/tokens.toml`, and consuming first the lots in
+  /// `lot_assignments` (see [`crate::adjustment::Adjustments::lot_assignments`]).
+  pub fn new(prices: &PriceStore, lot_assignments: &HashMap<String, Vec<String>>) -> Self {
+    Self {
+      prices: prices.clone(),
+      zero_basis: zero_basis_assets(&read_token_config()),
+      lot_assignments: lot_assignments.clone(),
+      ..Default::default()
+    }
+  }
+
+  /// Opens a lot for an incoming transfer.
+  pub fn acquire(&mut self, transfer: &Transfer, cost_basis: Decimal) {
+    let lot = Lot {
+      transfer_id: transfer.transfer_id.clone(),
+      datetime: transfer.datetime.clone(),
+      token: transfer.token.clone(),
+      amount: transfer.value,
+      cost_basis,
+    };
+    self.journal.push(LotMovement {
+      transfer_id: transfer.transfer_id.clone(),
+      token: transfer.token.clone(),
+      direction: TransferDirection::Incoming,
+      lot: lot.clone(),
+    });
+    self.lots.entry(transfer.token.lot_key()).or_default().push_back(lot);
+  }
+
+  /// Opens a lot for income received at its fair market value: the USD value
+  /// of the transfer, or its amount at the day's price. Assets configured with
+  /// `zero_basis` open the lot at zero cost instead.
+  pub fn receive(&mut self, transfer: &Transfer, category: &TransactionCategory) {
+    let fair_value = transfer.usd_value.or_else(|| {
+      let price = self.prices.price(&transfer.token.asset, parse_date(&transfer.datetime)?)?;
+      Some(price * transfer.value)
+    });
+    let cost_basis = match self.zero_basis.contains(&transfer.token.asset) {
+      true => Decimal::ZERO,
+      false => fair_value.unwrap_or_default(),
+    };
+    self.income.push(Income {
+      transfer_id: transfer.transfer_id.clone(),
+      datetime: transfer.datetime.clone(),
+      category: category.clone(),
+      token: transfer.token.clone(),
+      value: transfer.value,
+      fair_value,
+      cost_basis,
+    });
+    self.acquire(transfer, cost_basis);
+  }
+
+  /// Removes `amount` of the transfer's asset from the oldest lots, or first
+  /// from the lots assigned to the transaction, and returns the consumed
+  /// slices. Any shortfall is returned as a lot without cost basis.
+  pub fn take(&mut self, transfer: &Transfer, amount: Decimal) -> Vec<Lot> {
+    let taken = self.consume(transfer, amount);
+    self.journal.extend(taken.iter().map(|lot| LotMovement {
+      transfer_id: transfer.transfer_id.clone(),
+      token: transfer.token.clone(),
+      direction: TransferDirection::Outgoing,
+      lot: lot.clone(),
+    }));
+    taken
+  }
+
+  /// Consumes the native token a network fee was paid in. The fee is booked
+  /// on its own, so the slices are kept out of the journal.
+  pub fn pay(&mut self, fee: &Fee) {
+    let transfer = Transfer {
+      transfer_id: fee.transfer_id.clone(),
+      datetime: fee.datetime.clone(),
+      token: fee.token.clone(),
+      value: fee.value,
+      usd_value: fee.usd_value,
+      direction: TransferDirection::Outgoing,
+      counterparty: vec![fee.counterparty.clone()],
+      account: fee.account.clone(),
+      chain: fee.chain,
+    };
+    self.consume(&transfer, fee.value);
+  }
+
+  /// Removes lots as [`Portfolio::take`] does without journaling them.
+  fn consume(&mut self, transfer: &Transfer, amount: Decimal) -> Vec<Lot> {
+    let assigned = self.lot_assignments.get(&transfer.transfer_id).cloned().unwrap_or_default();
+    let lots = self.lots.entry(transfer.token.lot_key()).or_default();
+    let mut remaining = amount;
+    let mut taken = vec![];
+
+    while remaining > Decimal::ZERO {
+      let index = assigned
+        .iter()
+        .find_map(|id| lots.iter().position(|x| &x.transfer_id == id))
+        .unwrap_or_default();
+      let Some(lot) = lots.get_mut(index) else {
+        taken.push(Lot {
+          transfer_id: String::new(),
+          datetime: String::new(),
+          token: transfer.token.clone(),
+          amount: remaining,
+          cost_basis: Decimal::ZERO,
         });
-        map
-      });
-      /*flat_map(|transaction| transaction.net_transfers.iter().map(|transfer| {
-        (transfer.token.clone(), transfer.clone(), transaction.clone())
+        break;
+      };
+
+      if lot.amount <= remaining {
+        remaining -= lot.amount;
+        taken.extend(lots.remove(index));
+      } else {
+        let cost_basis = lot.cost_basis * remaining / lot.amount;
+        lot.amount -= remaining;
+        lot.cost_basis -= cost_basis;
+        taken.push(Lot { amount: remaining, cost_basis, ..lot.clone() });
+        remaining = Decimal::ZERO;
+      }
+    }
+    taken
+  }
+
+  /// Disposes of an outgoing transfer for `proceeds`, realising the difference
+  /// to the cost basis of the consumed lots.
+  pub fn dispose(&mut self, transfer: &Transfer, proceeds: Decimal) {
+    let lots = self.take(transfer, transfer.value);
+    let cost_basis: Decimal = lots.iter().map(|x| x.cost_basis).sum();
+    self.disposals.push(CostBasisTransfer {
+      transfer_id: transfer.transfer_id.clone(),
+      datetime: transfer.datetime.clone(),
+      token: transfer.token.clone(),
+      value: transfer.value,
+      sale_price: proceeds,
+      cost_basis,
+      pnl: proceeds - cost_basis,
+      lots,
+    });
+  }
+
+  /// Applies a movement between our own accounts or chains: only the net amount
+  /// per asset (e.g. a withdrawal or bridge fee) opens or closes lots, and
+  /// nothing is realised.
+  fn apply_move(&mut self, transfers: &[Transfer]) {
+    let mut net: BTreeMap<String, Vec<&Transfer>> = BTreeMap::new();
+    for transfer in transfers {
+      net.entry(transfer.token.lot_key()).or_default().push(transfer);
+    }
+
+    for legs in net.values() {
+      let signed = |x: &&Transfer| match x.direction {
+        TransferDirection::Incoming => x.value,
+        TransferDirection::Outgoing => -x.value,
+      };
+      let amount: Decimal = legs.iter().map(signed).sum();
+
+      if amount > Decimal::ZERO {
+        let leg = legs.iter().find(|x| x.direction == TransferDirection::Incoming).unwrap();
+        let cost_basis = leg.usd_value.unwrap_or_default() * amount / leg.value;
+        self.acquire(&Transfer { value: amount, ..(*leg).clone() }, cost_basis);
+      } else if amount < Decimal::ZERO {
+        let leg = legs.iter().find(|x| x.direction == TransferDirection::Outgoing).unwrap();
+        self.take(leg, -amount);
+      }
+    }
+  }
+
+  /// Applies a transaction moving NFTs. The fungible legs paid set the cost
+  /// basis of the NFTs received (e.g. the tokens deposited when minting a
+  /// Uniswap V3 position) and the fungible legs received are the proceeds of
+  /// the NFTs sent, split evenly across the NFTs moved.
+  fn apply_nft(&mut self, transfers: &[Transfer]) {
+    let (nfts, fungible): (Vec<&Transfer>, Vec<&Transfer>) = transfers.iter().partition(|x| x.token.is_nft());
+    let mut paid = Decimal::ZERO;
+    let mut received = Decimal::ZERO;
+
+    for leg in fungible {
+      match (&leg.direction, leg.usd_value) {
+        (TransferDirection::Outgoing, Some(usd_value)) => {
+          self.dispose(leg, usd_value);
+          paid += usd_value;
+        },
+        (TransferDirection::Outgoing, None) => {
+          paid += self.take(leg, leg.value).iter().map(|x| x.cost_basis).sum::<Decimal>();
+        },
+        (TransferDirection::Incoming, usd_value) => {
+          self.acquire(leg, usd_value.unwrap_or_default());
+          received += usd_value.unwrap_or_default();
+        },
+      }
+    }
+
+    let count = |direction: TransferDirection| Decimal::from(nfts.iter().filter(|x| x.direction == direction).count());
+    let (incoming, outgoing) = (count(TransferDirection::Incoming), count(TransferDirection::Outgoing));
+    for nft in nfts {
+      match nft.direction {
+        TransferDirection::Incoming => self.acquire(nft, paid / incoming),
+        TransferDirection::Outgoing => self.dispose(nft, received / outgoing),
+      }
+    }
+  }
+
+  /// Applies a classified transaction to the open lots.
+  ///
+  /// Two-asset swaps exchange lots at the swap's USD cost basis; any other
+  /// category opens incoming legs at their USD value and disposes outgoing legs
+  /// at their USD value, or at cost when no value is known. Tokens received as
+  /// income open lots at their fair market value. Transactions moving NFTs
+  /// value them from their fungible legs regardless of category.
+  pub fn apply(&mut self, transaction: &Transaction) {
+    match &transaction.category {
+      TransactionCategory::Ignore => {},
+      _ if transaction.net_transfers.iter().any(|x| x.token.is_nft()) => self.apply_nft(&transaction.net_transfers),
+      TransactionCategory::Transfer | TransactionCategory::Bridge => self.apply_move(&transaction.net_transfers),
+      category => transaction.net_transfers.iter().for_each(|transfer| {
+        let usd_value = match category {
+          TransactionCategory::Swap(SwapSubCategory::TwoAsset(swap)) => Some(swap.cost_basis),
+          _ => transfer.usd_value,
+        };
+        match (&transfer.direction, usd_value) {
+          (TransferDirection::Incoming, _) if category.is_income() => self.receive(transfer, category),
+          (TransferDirection::Incoming, usd_value) => self.acquire(transfer, usd_value.unwrap_or_default()),
+          (TransferDirection::Outgoing, Some(proceeds)) => self.dispose(transfer, proceeds),
+          (TransferDirection::Outgoing, None) => {
+            self.take(transfer, transfer.value);
+          },
+        }
+      }),
+    }
+  }
+
+  /// Applies a step of a replay.
+  pub fn replay(&mut self, step: &Replayed) {
+    match step {
+      Replayed::Transaction(transaction) => self.apply(transaction),
+      Replayed::Fee(fee) => self.pay(fee),
+    }
+  }
+
+  /// Current holdings, one item per underlying asset (or NFT) with open lots.
+  pub fn holdings(&self) -> Vec<PortfolioItem> {
+    let mut holdings: Vec<PortfolioItem> = self
+      .lots
+      .values()
+      .filter_map(|lots| Some(PortfolioItem {
+        token: lots.front()?.token.clone(),
+        cost_basis: lots.iter().map(|x| x.cost_basis).sum(),
+        amount: lots.iter().map(|x| x.amount).sum(),
       }))
-      .fold(HashMap::new(), |map, (token, transaction)|)
-      ;*/
+      .collect();
+    holdings.sort_by(|a, b| (&a.token.asset, &a.token.token_id).cmp(&(&b.token.asset, &b.token.token_id)));
+    holdings
+  }
+}
 
-        //.collect::<HashMap<Token, Vec<Transaction, Transfer>>();
-        
-      panic!();
+/// Step of a chronological replay through the lot engine.
+pub enum Replayed<'a> {
+  Transaction(&'a Transaction),
+  /// Network fee paid, consuming the native token it was paid in.
+  Fee(&'a Fee),
+}
+
+impl Replayed<'_> {
+  pub fn datetime(&self) -> &str {
+    match self {
+      Replayed::Transaction(x) => &x.datetime,
+      Replayed::Fee(x) => &x.datetime,
     }
+  }
+}
+
+/// Transactions and the network fees paid in chronological order, each fee
+/// after the transaction it paid for. Every replay of the lot engine follows
+/// this order so that its outputs agree.
+pub fn replay_order<'a>(transactions: &'a [Transaction], fees: &'a [Fee]) -> Vec<Replayed<'a>> {
+  let mut ordered: Vec<&Transaction> = transactions.iter().collect();
+  ordered.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+  let mut fees: Vec<&Fee> = fees.iter().filter(|x| !x.value.is_zero()).collect();
+  fees.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+  let mut fees = fees.into_iter().peekable();
+
+  let mut steps = vec![];
+  for transaction in ordered {
+    while let Some(fee) = fees.next_if(|x| x.datetime < transaction.datetime) {
+      steps.push(Replayed::Fee(fee));
+    }
+    steps.push(Replayed::Transaction(transaction));
+  }
+  steps.extend(fees.map(Replayed::Fee));
+  steps
+}
+
+/// Replays transactions and the network fees paid in the [`replay_order`],
+/// valuing income with `prices` and consuming the assigned lots first.
+impl From<(&Vec<Transaction>, &[Fee], &PriceStore, &HashMap<String, Vec<String>>)> for Portfolio {
+    fn from(
+      (transactions, fees, prices, lot_assignments): (&Vec<Transaction>, &[Fee], &PriceStore, &HashMap<String, Vec<String>>),
+    ) -> Self {
+      let mut portfolio = Portfolio::new(prices, lot_assignments);
+      replay_order(transactions, fees).iter().for_each(|x| portfolio.replay(x));
+      portfolio
+    }
+}
+
+/// Replays transactions in chronological order, valuing income with `prices`.
+impl From<(&Vec<Transaction>, &PriceStore)> for Portfolio {
+    fn from((transactions, prices): (&Vec<Transaction>, &PriceStore)) -> Self {
+      (transactions, &[][..], prices, &HashMap::new()).into()
+    }
+}
+
+/// Replays transactions in chronological order, valuing income with the
+/// prices observed in them.
+impl From<&Vec<Transaction>> for Portfolio {
+    fn from(transactions: &Vec<Transaction>) -> Self {
+      (transactions, &PriceStore::observed(transactions)).into()
+    }
+}
+
+impl ToPortfolio for Vec<Transaction> {
+    fn to_portfolio(self) -> Vec<PortfolioItem> {
+      Portfolio::from(&self).holdings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Chain;
+
+  fn transfer(transfer_id: &str, asset: &str, value: i64, usd_value: i64, direction: TransferDirection) -> Transfer {
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: format!("2024-01-01T00:00:0{}Z", transfer_id.len()),
+      token: Token { asset: asset.to_string(), symbol: asset.to_string(), ..Default::default() },
+      value: Decimal::from(value),
+      usd_value: Some(Decimal::from(usd_value)),
+      direction,
+      counterparty: vec![],
+      account: String::from("0xwallet"),
+      chain: Default::default(),
+    }
+  }
+
+  fn buy(portfolio: &mut Portfolio, transfer_id: &str, value: i64, usd_value: i64) {
+    let leg = transfer(transfer_id, "ETH", value, usd_value, TransferDirection::Incoming);
+    portfolio.acquire(&leg, leg.usd_value.unwrap());
+  }
+
+  fn fee(transfer_id: &str, datetime: &str) -> Fee {
+    Fee {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      chain: Chain::Arbitrum,
+      account: String::from("0xwallet"),
+      counterparty: String::from("0xcontract"),
+      token: Token { asset: String::from("ETH"), symbol: String::from("ETH"), ..Default::default() },
+      value: Decimal::new(1, 1),
+      usd_value: Some(Decimal::from(300)),
+      value_sent: Decimal::ZERO,
+    }
+  }
+
+  #[test]
+  fn disposals_consume_oldest_lots_first() {
+    let mut portfolio = Portfolio::default();
+    buy(&mut portfolio, "a", 1, 1000);
+    buy(&mut portfolio, "b", 2, 4000);
+
+    portfolio.dispose(&transfer("c", "ETH", 2, 5000, TransferDirection::Outgoing), Decimal::from(5000));
+
+    let disposal = &portfolio.disposals[0];
+    assert_eq!(disposal.cost_basis, Decimal::from(3000));
+    assert_eq!(disposal.pnl, Decimal::from(2000));
+    assert_eq!(disposal.lots.iter().map(|x| x.transfer_id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+
+    let holdings = portfolio.holdings();
+    assert_eq!(holdings[0].amount, Decimal::ONE);
+    assert_eq!(holdings[0].cost_basis, Decimal::from(2000));
+  }
+
+  #[test]
+  fn shortfall_has_no_cost_basis() {
+    let mut portfolio = Portfolio::default();
+    buy(&mut portfolio, "a", 1, 1000);
+
+    let lots = portfolio.take(&transfer("b", "ETH", 3, 0, TransferDirection::Outgoing), Decimal::from(3));
+
+    assert_eq!(lots.len(), 2);
+    assert_eq!(lots[1].amount, Decimal::from(2));
+    assert_eq!(lots[1].cost_basis, Decimal::ZERO);
+    assert!(portfolio.holdings().is_empty());
+  }
+
+  #[test]
+  fn assigned_lots_are_consumed_first() {
+    let mut portfolio = Portfolio::default();
+    buy(&mut portfolio, "a", 1, 1000);
+    buy(&mut portfolio, "b", 1, 3000);
+    portfolio.lot_assignments.insert(String::from("c"), vec![String::from("b")]);
+
+    portfolio.dispose(&transfer("c", "ETH", 1, 3000, TransferDirection::Outgoing), Decimal::from(3000));
+
+    assert_eq!(portfolio.disposals[0].pnl, Decimal::ZERO);
+    assert_eq!(portfolio.holdings()[0].cost_basis, Decimal::from(1000));
+  }
+
+  #[test]
+  fn fees_consume_lots_without_realising() {
+    let mut portfolio = Portfolio::default();
+    buy(&mut portfolio, "a", 1, 1000);
+
+    portfolio.pay(&fee("b", "2024-01-01 00:00:00"));
+
+    assert!(portfolio.disposals.is_empty());
+    assert_eq!(portfolio.journal.len(), 1);
+    let holdings = portfolio.holdings();
+    assert_eq!(holdings[0].amount, Decimal::new(9, 1));
+    assert_eq!(holdings[0].cost_basis, Decimal::from(900));
+  }
+
+  #[test]
+  fn fees_are_replayed_after_the_transaction_they_paid_for() {
+    let transaction = |transfer_id: &str, datetime: &str| Transaction {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      ..Default::default()
+    };
+    let transactions = vec![transaction("b", "2024-01-02 00:00:00"), transaction("a", "2024-01-01 00:00:00")];
+    let fees = vec![
+      fee("late", "2024-01-03 00:00:00"),
+      fee("b", "2024-01-02 00:00:00"),
+      fee("a", "2024-01-01 00:00:00"),
+      Fee { value: Decimal::ZERO, ..fee("free", "2024-01-01 12:00:00") },
+    ];
+
+    let order: Vec<String> = replay_order(&transactions, &fees)
+      .iter()
+      .map(|x| match x {
+        Replayed::Transaction(x) => format!("tx {}", x.transfer_id),
+        Replayed::Fee(x) => format!("fee {}", x.transfer_id),
+      })
+      .collect();
+    assert_eq!(order, ["tx a", "fee a", "tx b", "fee b", "fee late"]);
+  }
+
+  #[test]
+  fn moves_only_apply_the_net_amount() {
+    let mut portfolio = Portfolio::default();
+    buy(&mut portfolio, "a", 2, 2000);
+
+    let transaction = Transaction {
+      transfer_id: String::from("b"),
+      category: TransactionCategory::Bridge,
+      net_transfers: vec![
+        transfer("b", "ETH", 2, 2000, TransferDirection::Outgoing),
+        Transfer { chain: Chain::Ethereum, ..transfer("b", "ETH", 1, 1000, TransferDirection::Incoming) },
+      ],
+      ..Default::default()
+    };
+    portfolio.apply(&transaction);
+
+    assert!(portfolio.disposals.is_empty());
+    let holdings = portfolio.holdings();
+    assert_eq!(holdings[0].amount, Decimal::ONE);
+    assert_eq!(holdings[0].cost_basis, Decimal::from(1000));
+  }
 }
@@ -0,0 +1,116 @@
+//! Daily USD prices per underlying asset.
+//!
+//! Prices are observed from the transfers themselves (the token's stable value,
+//! or the USD value of the leg divided by its amount; the last observation of a
+//! day wins) and can be supplied or overridden in `data/ref/prices.csv` with
+//! `date,asset,usd_price` rows, e.g. from a price provider's daily export. A
+//! price holds until the next known one.
+
+use crate::{read_csv, Transaction};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+/// Optional file of daily prices.
+const PRICE_FILE: &str = "data/ref/prices.csv";
+
+#[derive(Debug, Deserialize)]
+/// Row of `data/ref/prices.csv`.
+pub struct PriceRow {
+  /// `YYYY-MM-DD`.
+  pub date: NaiveDate,
+  /// Underlying asset as in `data/ref/tokens.toml`, e.g. `WETH`.
+  pub asset: String,
+  pub usd_price: Decimal,
+}
+
+#[derive(Debug, Default, Clone)]
+/// Daily USD prices keyed by underlying asset.
+pub struct PriceStore {
+  pub prices: HashMap<String, BTreeMap<NaiveDate, Decimal>>,
+}
+
+/// Date part of a normalized datetime.
+pub fn parse_date(datetime: &str) -> Option<NaiveDate> {
+  NaiveDate::parse_from_str(datetime.get(..10)?, "%Y-%m-%d").ok()
+}
+
+impl PriceStore {
+  /// Records the price of `asset` on `date`, replacing any earlier one of that day.
+  pub fn insert(&mut self, asset: &str, date: NaiveDate, usd_price: Decimal) {
+    self.prices.entry(asset.to_string()).or_default().insert(date, usd_price);
+  }
+
+  /// Price of `asset` on `date`, or the last one known before it.
+  pub fn price(&self, asset: &str, date: NaiveDate) -> Option<Decimal> {
+    self.prices.get(asset)?.range(..=date).next_back().map(|x| *x.1)
+  }
+
+  /// Latest known price of `asset`.
+  pub fn latest(&self, asset: &str) -> Option<Decimal> {
+    self.prices.get(asset)?.values().next_back().copied()
+  }
+
+  /// Last date any price is known for.
+  pub fn last_date(&self) -> Option<NaiveDate> {
+    self.prices.values().filter_map(|x| x.keys().next_back()).max().copied()
+  }
+
+  /// Prices observed in the fungible legs of the transactions. Debt tokens
+  /// are priced as their underlying asset; their negative stable values are
+  /// not prices and are skipped.
+  pub fn observed(transactions: &[Transaction]) -> Self {
+    Self::observed_except(transactions, None)
+  }
+
+  /// Prices observed as in [`Self::observed`], leaving out the transaction
+  /// `excluded`, e.g. the one being measured against the prices.
+  pub fn observed_except(transactions: &[Transaction], excluded: Option<&str>) -> Self {
+    let mut ordered: Vec<&Transaction> = transactions
+      .iter()
+      .filter(|x| excluded.is_none_or(|excluded| x.transfer_id != excluded))
+      .collect();
+    ordered.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+
+    let mut store = Self::default();
+    for transfer in ordered.iter().flat_map(|x| &x.net_transfers) {
+      if transfer.token.is_nft() || transfer.value.is_zero() {
+        continue;
+      }
+      let Some(date) = parse_date(&transfer.datetime) else { continue };
+      let price = transfer.token.stable_usd_value.or(transfer.usd_value.map(|x| x / transfer.value));
+      if let Some(price) = price.filter(|x| x.is_sign_positive() && !x.is_zero()) {
+        store.insert(transfer.token.asset.trim_start_matches("Debt "), date, price);
+      }
+    }
+    store
+  }
+
+  /// Prices of `data/ref/prices.csv`, none when it is absent.
+  pub fn read_file() -> Result<Self, Box<dyn Error>> {
+    let mut store = Self::default();
+    if Path::new(PRICE_FILE).exists() {
+      for row in read_csv::<PriceRow>(PRICE_FILE)? {
+        store.insert(&row.asset, row.date, row.usd_price);
+      }
+    }
+    Ok(store)
+  }
+
+  /// Adds the prices of `other`, replacing those known for the same day.
+  pub fn extend(&mut self, other: &PriceStore) {
+    for (asset, prices) in &other.prices {
+      prices.iter().for_each(|(date, price)| self.insert(asset, *date, *price));
+    }
+  }
+
+  /// Prices observed in the transactions, overridden by `data/ref/prices.csv` when present.
+  pub fn load(transactions: &[Transaction]) -> Result<Self, Box<dyn Error>> {
+    let mut store = Self::observed(transactions);
+    store.extend(&Self::read_file()?);
+    Ok(store)
+  }
+}
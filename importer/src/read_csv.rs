@@ -1,10 +1,14 @@
-//! Lightweight wrappers around the [`csv`] crate for reading and writing data.
+//! Lightweight wrappers around the [`csv`] and [`serde_json`] crates for reading
+//! and writing data.
 
 use csv::ReaderBuilder;
+use csv::StringRecord;
 use csv::WriterBuilder;
 use serde::Deserialize;
 use serde::Serialize;
 use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 
 /// Generic function to read CSV into a vector of structs.
 pub fn read_csv<T>(file_path: &str) -> Result<Vec<T>, Box<dyn Error>>
@@ -26,14 +30,95 @@ where
     Ok(records)
 }
 
-/// Writes a slice of serializable records to a CSV file without headers.
+/// Reads only the header row of a CSV file.
+pub fn read_headers(file_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(file_path)?;
+
+    Ok(rdr.headers()?.iter().map(|x| x.to_string()).collect())
+}
+
+/// Reads a CSV into a vector of structs after passing every header through
+/// `normalize`, allowing columns whose names vary between exports to be
+/// matched against a stable serde name.
+pub fn read_csv_with<T, F>(file_path: &str, normalize: F) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T: for<'de> Deserialize<'de>,
+    F: Fn(&str) -> String,
+{
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(file_path)?;
+
+    let headers: StringRecord = rdr.headers()?.iter().map(&normalize).collect();
+    rdr.set_headers(headers);
+
+    let mut records: Vec<T> = Vec::new();
+
+    for result in rdr.deserialize() {
+        let record: T = result?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Reads a CSV whose header is preceded by free-form preamble lines, starting
+/// at the first line accepted by `is_header`.
+pub fn read_csv_after<T, F>(file_path: &str, is_header: F) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T: for<'de> Deserialize<'de>,
+    F: Fn(&str) -> bool,
+{
+    let contents = fs::read_to_string(file_path)?;
+    let start = contents
+        .lines()
+        .position(is_header)
+        .ok_or(format!("{}: no header row found", file_path))?;
+    let body: String = contents.lines().skip(start).collect::<Vec<&str>>().join("\n");
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(body.as_bytes());
+
+    let mut records: Vec<T> = Vec::new();
+
+    for result in rdr.deserialize() {
+        let record: T = result?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Generic function to read a JSON document into a struct.
+pub fn read_json<T>(file_path: &str) -> Result<T, Box<dyn Error>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    Ok(serde_json::from_str(&fs::read_to_string(file_path)?)?)
+}
+
+/// Writes a slice of flat serializable records to a CSV file with a header row.
+/// Without records the header is still written, taken from `T::default()`.
 pub fn write_csv<T>(t: &[T], file_path: &str) -> Result<(), Box<dyn Error>>
 where
-    T: Serialize, // Ensures that T can be serialized
+    T: Serialize + Default, // Ensures that T can be serialized
 {
+    if t.is_empty() {
+        // csv only emits the header together with the first record
+        let mut wtr = WriterBuilder::new().from_writer(vec![]);
+        wtr.serialize(T::default())?;
+        let csv = wtr.into_inner().map_err(|e| e.to_string())?;
+        let header_end = csv.iter().position(|x| *x == b'\n').map_or(csv.len(), |x| x + 1);
+        fs::write(file_path, &csv[..header_end])?;
+        return Ok(());
+    }
+
     // Create a CSV writer
     let mut wtr = WriterBuilder::new()
-        .has_headers(false)  // Disable headers
+        .has_headers(true)  // Header row from the field names of T
         .from_path(file_path)?;
 
     // Write the data (each element in t)
@@ -45,4 +130,56 @@ where
     wtr.flush()?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Writes a slice of serializable records to a file as a pretty-printed JSON array.
+pub fn write_json<T>(t: &[T], file_path: &str) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize,
+{
+    let mut file = BufWriter::new(File::create(file_path)?);
+    serde_json::to_writer_pretty(&mut file, t)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes a slice of serializable records to a file as newline-delimited JSON,
+/// one compact object per line.
+pub fn write_ndjson<T>(t: &[T], file_path: &str) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize,
+{
+    let mut file = BufWriter::new(File::create(file_path)?);
+    for item in t {
+        serde_json::to_writer(&mut file, item)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Default)]
+    struct Row {
+        date: String,
+        amount: u32,
+    }
+
+    #[test]
+    fn empty_csv_keeps_the_header() {
+        let path = std::env::temp_dir().join("read_csv_empty_header.csv");
+        let path = path.to_str().unwrap();
+
+        write_csv::<Row>(&[], path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "date,amount\n");
+
+        let rows = vec![Row { date: "2024-01-01".to_string(), amount: 3 }];
+        write_csv(&rows, path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "date,amount\n2024-01-01,3\n");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Review of the transactions the classifier could not make sense of.
+//!
+//! [`review_rows`] lists `Unknown` and `SwapUnknown` transactions with their
+//! net legs, counterparties and a suggested category, to be written as a CSV
+//! and filled in. [`merge_review`] reads the categories back and appends them
+//! to `data/ref/categories.toml`, keyed by the transaction hash or by a
+//! counterparty, leaving the rest of the file untouched.
+
+use crate::category::{read_category_config, CATEGORY_FILE, CATEGORY_NAMES};
+use crate::{SwapSubCategory, Transaction, TransactionCategory, TransferDirection};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// `key` value selecting the transaction hash.
+const HASH_KEY: &str = "hash";
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+/// Row of the review file. `category`, `key` and `description` are filled in
+/// by the reviewer.
+pub struct ReviewRow {
+  pub transfer_id: String,
+  pub datetime: String,
+  /// `Unknown` or `SwapUnknown`.
+  pub current: String,
+  /// Net legs, e.g. `-100 USDC | +0.04 ETH`.
+  pub legs: String,
+  /// Counterparties of the legs, space separated.
+  pub counterparties: String,
+  /// Category guessed from the shape of the legs, if any.
+  pub suggested: String,
+  /// One of the categories of `data/ref/categories.toml`; rows left blank are skipped.
+  pub category: String,
+  /// Blank or `hash` to categorise this transaction, or a counterparty to
+  /// categorise every transaction with it.
+  pub key: String,
+  pub description: String,
+}
+
+#[derive(Debug, Default, Clone)]
+/// Outcome of merging a review file.
+pub struct Merge {
+  /// Keys appended to the configuration with their category.
+  pub added: Vec<(String, String)>,
+  /// Rows not merged, with the reason.
+  pub skipped: Vec<(String, String)>,
+}
+
+/// Whether a transaction awaits review.
+fn is_unclassified(transaction: &Transaction) -> bool {
+  matches!(
+    transaction.category,
+    TransactionCategory::Unknown | TransactionCategory::Swap(SwapSubCategory::UnknownSwap)
+  )
+}
+
+/// Category suggested by the shape of the legs: debt legs are Aave swaps, one
+/// asset in and out is a transfer, one asset for another is a swap, and legs
+/// in one direction only are transfers, or an airdrop when received without a
+/// USD value.
+pub fn suggest(transaction: &Transaction) -> Option<&'static str> {
+  let legs = &transaction.net_transfers;
+  let count = |direction: TransferDirection| legs.iter().filter(|x| x.direction == direction).count();
+  let assets = legs.iter().map(|x| x.token.asset.trim_start_matches("Debt ")).unique().count();
+  match (count(TransferDirection::Incoming), count(TransferDirection::Outgoing)) {
+    (0, 0) => None,
+    _ if legs.iter().any(|x| x.token.is_debt) => Some("Swap"),
+    (_, 0) if legs.iter().all(|x| x.usd_value.is_none()) => Some("Airdrop"),
+    (_, 0) | (0, _) => Some("Transfer"),
+    _ if assets == 1 => Some("Transfer"),
+    (1, 1) => Some("Swap"),
+    _ => None,
+  }
+}
+
+/// Review rows of the unclassified transactions, in order.
+pub fn review_rows(transactions: &[Transaction]) -> Vec<ReviewRow> {
+  transactions
+    .iter()
+    .filter(|x| is_unclassified(x))
+    .map(|transaction| {
+      let legs = transaction.net_transfers.iter().map(|x| {
+        let sign = if x.direction == TransferDirection::Incoming { "+" } else { "-" };
+        format!("{}{} {}", sign, x.value.normalize(), x.token.symbol)
+      });
+      ReviewRow {
+        transfer_id: transaction.transfer_id.clone(),
+        datetime: transaction.datetime.clone(),
+        current: transaction.category.name().to_string(),
+        legs: legs.collect::<Vec<_>>().join(" | "),
+        counterparties: transaction.net_transfers.iter().flat_map(|x| &x.counterparty).unique().join(" "),
+        suggested: suggest(transaction).unwrap_or_default().to_string(),
+        category: String::new(),
+        key: String::new(),
+        description: String::new(),
+      }
+    })
+    .collect()
+}
+
+/// Appends the categories filled in `rows` to `data/ref/categories.toml`.
+///
+/// Rows without a category are ignored. A row is skipped when its category is
+/// not a known name, or its key is already configured (by the file or an
+/// earlier row) with another category.
+pub fn merge_review(rows: &[ReviewRow]) -> Result<Merge, Box<dyn Error>> {
+  let mut configured: HashMap<String, String> = read_category_config()
+    .into_iter()
+    .map(|(key, mapping)| (key, mapping.category))
+    .collect();
+  let mut merge = Merge::default();
+  let mut entries = String::new();
+
+  for row in rows.iter().filter(|x| !x.category.trim().is_empty()) {
+    let category = row.category.trim();
+    let key = match row.key.trim() {
+      "" | HASH_KEY => row.transfer_id.clone(),
+      key => key.to_string(),
+    };
+    if !CATEGORY_NAMES.contains(&category) {
+      merge.skipped.push((key, format!("unknown category {}", category)));
+      continue;
+    }
+    match configured.get(&key) {
+      Some(existing) if existing == category => continue,
+      Some(existing) => {
+        merge.skipped.push((key, format!("already configured as {}", existing)));
+        continue;
+      },
+      None => {},
+    }
+
+    entries.push_str(&format!("\n[{}]\ncategory = {}\n", toml::Value::from(key.as_str()), toml::Value::from(category)));
+    if !row.description.trim().is_empty() {
+      entries.push_str(&format!("description = {}\n", toml::Value::from(row.description.trim())));
+    }
+    configured.insert(key.clone(), category.to_string());
+    merge.added.push((key, category.to_string()));
+  }
+
+  if !entries.is_empty() {
+    let mut file = OpenOptions::new().append(true).open(CATEGORY_FILE)?;
+    file.write_all(entries.as_bytes())?;
+  }
+  Ok(merge)
+}
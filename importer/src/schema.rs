@@ -0,0 +1,204 @@
+//! Versioned layouts of the importer outputs: flat rows for CSV and nested
+//! records for JSON, which also carry the fields CSV leaves out (net transfers
+//! per transaction, counterparties, token metadata).
+//!
+//! Every row and record carries [`SCHEMA_VERSION`] so consumers can detect
+//! layout changes; bump it whenever a field is added, removed or reordered. The
+//! layouts are documented in `docs/output-schemas.md`.
+
+use crate::{Chain, PortfolioItem, Token, Transaction, TransactionCategory, Transfer, TransferDirection};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Version of the output layouts below.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of `transactions.csv`.
+pub struct TransactionRow {
+  pub schema_version: u32,
+  pub transfer_id: String,
+  pub datetime: String,
+  pub category: TransactionCategory,
+  pub assets: String,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of `transfers.csv`.
+pub struct TransferRow {
+  pub schema_version: u32,
+  pub transfer_id: String,
+  pub datetime: String,
+  pub chain: Chain,
+  pub account: String,
+  pub direction: TransferDirection,
+  pub token_symbol: String,
+  pub token_asset: String,
+  pub token_address: String,
+  pub token_id: Option<String>,
+  pub value: Decimal,
+  pub usd_value: Option<Decimal>,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Row of `portfolio.csv`.
+pub struct PortfolioRow {
+  pub schema_version: u32,
+  pub token_symbol: String,
+  pub token_asset: String,
+  pub token_address: String,
+  pub token_id: Option<String>,
+  pub amount: Decimal,
+  pub cost_basis: Decimal,
+}
+
+impl From<&Transaction> for TransactionRow {
+    fn from(transaction: &Transaction) -> Self {
+      Self {
+        schema_version: SCHEMA_VERSION,
+        transfer_id: transaction.transfer_id.clone(),
+        datetime: transaction.datetime.clone(),
+        category: transaction.category.clone(),
+        assets: transaction.assets.clone(),
+      }
+    }
+}
+
+impl From<&Transfer> for TransferRow {
+    fn from(transfer: &Transfer) -> Self {
+      let Token { symbol, asset, address, token_id, .. } = transfer.token.clone();
+      Self {
+        schema_version: SCHEMA_VERSION,
+        transfer_id: transfer.transfer_id.clone(),
+        datetime: transfer.datetime.clone(),
+        chain: transfer.chain,
+        account: transfer.account.clone(),
+        direction: transfer.direction.clone(),
+        token_symbol: symbol,
+        token_asset: asset,
+        token_address: address,
+        token_id,
+        value: transfer.value,
+        usd_value: transfer.usd_value,
+      }
+    }
+}
+
+impl From<&PortfolioItem> for PortfolioRow {
+    fn from(item: &PortfolioItem) -> Self {
+      let Token { symbol, asset, address, token_id, .. } = item.token.clone();
+      Self {
+        schema_version: SCHEMA_VERSION,
+        token_symbol: symbol,
+        token_asset: asset,
+        token_address: address,
+        token_id,
+        amount: item.amount,
+        cost_basis: item.cost_basis,
+      }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Complete token metadata as written to JSON outputs.
+pub struct TokenRecord {
+  pub symbol: String,
+  pub asset: String,
+  pub address: String,
+  pub chain: Chain,
+  pub token_id: Option<String>,
+  pub stable_usd_value: Option<Decimal>,
+  pub is_usd: bool,
+  pub is_debt: bool,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Transfer as written to JSON outputs.
+pub struct TransferRecord {
+  pub schema_version: u32,
+  pub transfer_id: String,
+  pub datetime: String,
+  pub chain: Chain,
+  pub account: String,
+  pub direction: TransferDirection,
+  pub token: TokenRecord,
+  pub value: Decimal,
+  pub usd_value: Option<Decimal>,
+  pub counterparty: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Transaction and its net transfers as written to JSON outputs.
+pub struct TransactionRecord {
+  pub schema_version: u32,
+  pub transfer_id: String,
+  pub datetime: String,
+  pub category: TransactionCategory,
+  pub assets: String,
+  pub net_transfers: Vec<TransferRecord>,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Holding as written to JSON outputs.
+pub struct PortfolioRecord {
+  pub schema_version: u32,
+  pub token: TokenRecord,
+  pub amount: Decimal,
+  pub cost_basis: Decimal,
+}
+
+impl From<&Token> for TokenRecord {
+    fn from(token: &Token) -> Self {
+      Self {
+        symbol: token.symbol.clone(),
+        asset: token.asset.clone(),
+        address: token.address.clone(),
+        chain: token.chain,
+        token_id: token.token_id.clone(),
+        stable_usd_value: token.stable_usd_value,
+        is_usd: token.is_usd,
+        is_debt: token.is_debt,
+      }
+    }
+}
+
+impl From<&Transfer> for TransferRecord {
+    fn from(transfer: &Transfer) -> Self {
+      Self {
+        schema_version: SCHEMA_VERSION,
+        transfer_id: transfer.transfer_id.clone(),
+        datetime: transfer.datetime.clone(),
+        chain: transfer.chain,
+        account: transfer.account.clone(),
+        direction: transfer.direction.clone(),
+        token: (&transfer.token).into(),
+        value: transfer.value,
+        usd_value: transfer.usd_value,
+        counterparty: transfer.counterparty.clone(),
+      }
+    }
+}
+
+impl From<&Transaction> for TransactionRecord {
+    fn from(transaction: &Transaction) -> Self {
+      Self {
+        schema_version: SCHEMA_VERSION,
+        transfer_id: transaction.transfer_id.clone(),
+        datetime: transaction.datetime.clone(),
+        category: transaction.category.clone(),
+        assets: transaction.assets.clone(),
+        net_transfers: transaction.net_transfers.iter().map(TransferRecord::from).collect(),
+      }
+    }
+}
+
+impl From<&PortfolioItem> for PortfolioRecord {
+    fn from(item: &PortfolioItem) -> Self {
+      Self {
+        schema_version: SCHEMA_VERSION,
+        token: (&item.token).into(),
+        amount: item.amount,
+        cost_basis: item.cost_basis,
+      }
+    }
+}
@@ -0,0 +1,241 @@
+//! Execution quality of two-asset swaps.
+//!
+//! The effective rate of a swap (amount purchased per unit sold) is compared
+//! with the reference rate implied by the prices of both assets on the day of
+//! the swap. Reference prices are those of `data/ref/prices.csv`, or else the
+//! prices observed in every other transaction, so that a swap is never
+//! measured against its own legs. They are daily prices: intraday moves
+//! between the reference and the swap show up as slippage. Slippage is the
+//! shortfall of the effective rate in basis points, positive when the swap did
+//! worse than the reference. Swaps
+//! between tokens of the same underlying asset (Aave deposits and withdrawals,
+//! wrapping ETH) are not trades and are left out.
+
+use crate::category::{read_category_config, CategoryConfig};
+use crate::price::{parse_date, PriceStore};
+use crate::{SwapSubCategory, Token, Transaction, TransactionCategory, TransferDirection};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Slippage in basis points above which swaps are flagged by default.
+pub const DEFAULT_THRESHOLD_BPS: u32 = 50;
+
+#[derive(Debug, Serialize, Clone)]
+/// Execution quality of one swap.
+pub struct SwapExecution {
+  pub transfer_id: String,
+  pub datetime: String,
+  /// Address the sold token was sent to, usually a router.
+  pub router: String,
+  /// `description` of the router in `data/ref/categories.toml`, if any.
+  pub protocol: Option<String>,
+  pub asset_sold: String,
+  pub asset_purchased: String,
+  pub value_sold: Decimal,
+  pub value_purchased: Decimal,
+  /// Units purchased per unit sold.
+  pub effective_rate: Decimal,
+  /// Rate implied by the day's reference prices of both assets.
+  pub reference_rate: Decimal,
+  /// USD value of the amount sold at the reference price.
+  pub volume_usd: Decimal,
+  pub slippage_bps: Decimal,
+  /// Whether the slippage exceeds the threshold.
+  pub outlier: bool,
+}
+
+#[derive(Debug, Default, Serialize, Clone)]
+/// Execution quality of the swaps routed through one address.
+pub struct RouterExecution {
+  pub router: String,
+  pub protocol: Option<String>,
+  pub swaps: usize,
+  pub volume_usd: Decimal,
+  /// Slippage weighted by volume.
+  pub slippage_bps: Decimal,
+  pub max_slippage_bps: Decimal,
+  pub outliers: usize,
+  /// USD lost to slippage at reference prices.
+  pub cost_usd: Decimal,
+}
+
+/// Underlying asset of a swapped token, priced as ETH when wrapped.
+fn underlying(asset: &str) -> &str {
+  match asset.trim_start_matches("Debt ") {
+    "WETH" => "ETH",
+    asset => asset,
+  }
+}
+
+/// Execution of a two-asset swap, if both assets have a stable value or a price
+/// in `prices`.
+fn execution(
+    transaction: &Transaction,
+    prices: &PriceStore,
+    config: &CategoryConfig,
+    threshold_bps: Decimal,
+) -> Option<SwapExecution> {
+    let TransactionCategory::Swap(SwapSubCategory::TwoAsset(swap)) = &transaction.category else { return None };
+    let (sold, purchased) = (underlying(&swap.token_sold.asset), underlying(&swap.token_purchased.asset));
+    if sold == purchased || swap.value_sold.is_zero() || swap.value_purchased.is_zero() {
+      return None;
+    }
+
+    let date = parse_date(&transaction.datetime)?;
+    // Debt tokens carry a negative stable value, which is not a price
+    let price = |token: &Token, asset: &str| {
+      token.stable_usd_value.filter(|x| x.is_sign_positive()).or_else(|| prices.price(asset, date))
+    };
+    let (price_sold, price_purchased) = (price(&swap.token_sold, sold)?, price(&swap.token_purchased, purchased)?);
+    if price_sold.is_zero() || price_purchased.is_zero() {
+      return None;
+    }
+
+    let router = transaction
+      .net_transfers
+      .iter()
+      .find(|x| x.direction == TransferDirection::Outgoing && x.token == swap.token_sold)
+      .and_then(|x| x.counterparty.first())
+      .cloned()
+      .unwrap_or_default();
+    let effective_rate = swap.value_purchased / swap.value_sold;
+    let reference_rate = price_sold / price_purchased;
+    let slippage_bps = (Decimal::ONE - effective_rate / reference_rate) * Decimal::from(10_000);
+
+    Some(SwapExecution {
+      transfer_id: transaction.transfer_id.clone(),
+      datetime: transaction.datetime.clone(),
+      protocol: config.get(&router).and_then(|x| x.description.clone()),
+      router,
+      asset_sold: sold.to_string(),
+      asset_purchased: purchased.to_string(),
+      value_sold: swap.value_sold,
+      value_purchased: swap.value_purchased,
+      effective_rate,
+      reference_rate,
+      volume_usd: swap.value_sold * price_sold,
+      outlier: slippage_bps > threshold_bps,
+      slippage_bps,
+    })
+}
+
+/// Prices a swap is measured against: those observed in every other
+/// transaction, overridden by the `file_prices` of `data/ref/prices.csv`.
+fn reference_prices(transactions: &[Transaction], swap: &Transaction, file_prices: &PriceStore) -> PriceStore {
+    let mut prices = PriceStore::observed_except(transactions, Some(&swap.transfer_id));
+    prices.extend(file_prices);
+    prices
+}
+
+/// Execution of every two-asset swap with reference prices, in chronological order.
+pub fn swap_executions(transactions: &[Transaction], file_prices: &PriceStore, threshold_bps: Decimal) -> Vec<SwapExecution> {
+    let config = read_category_config();
+    let mut executions: Vec<SwapExecution> = transactions
+      .iter()
+      .filter(|x| matches!(x.category, TransactionCategory::Swap(SwapSubCategory::TwoAsset(_))))
+      .filter_map(|x| execution(x, &reference_prices(transactions, x, file_prices), &config, threshold_bps))
+      .collect();
+    executions.sort_by(|a, b| (&a.datetime, &a.transfer_id).cmp(&(&b.datetime, &b.transfer_id)));
+    executions
+}
+
+/// Swap executions totalled per router, largest volume first.
+pub fn router_executions(executions: &[SwapExecution]) -> Vec<RouterExecution> {
+    let mut routers: BTreeMap<&str, RouterExecution> = BTreeMap::new();
+    for execution in executions {
+      let router = routers.entry(&execution.router).or_insert_with(|| RouterExecution {
+        router: execution.router.clone(),
+        protocol: execution.protocol.clone(),
+        max_slippage_bps: execution.slippage_bps,
+        ..Default::default()
+      });
+      router.swaps += 1;
+      router.volume_usd += execution.volume_usd;
+      router.cost_usd += execution.volume_usd * execution.slippage_bps / Decimal::from(10_000);
+      router.max_slippage_bps = router.max_slippage_bps.max(execution.slippage_bps);
+      router.outliers += usize::from(execution.outlier);
+    }
+
+    let mut routers: Vec<RouterExecution> = routers.into_values().collect();
+    for router in routers.iter_mut().filter(|x| !x.volume_usd.is_zero()) {
+      router.slippage_bps = router.cost_usd / router.volume_usd * Decimal::from(10_000);
+    }
+    routers.sort_by_key(|x| Reverse(x.volume_usd));
+    routers
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Chain, Transfer};
+  use chrono::NaiveDate;
+
+  fn usdc() -> Token {
+    Token {
+      asset: String::from("USDC"),
+      symbol: String::from("USDC"),
+      address: String::from("0xusdc"),
+      stable_usd_value: Some(Decimal::ONE),
+      is_usd: true,
+      ..Default::default()
+    }
+  }
+
+  fn leg(transfer_id: &str, datetime: &str, token: Token, value: i64, usd_value: i64, direction: TransferDirection) -> Transfer {
+    Transfer {
+      transfer_id: transfer_id.to_string(),
+      datetime: datetime.to_string(),
+      token,
+      value: Decimal::from(value),
+      usd_value: Some(Decimal::from(usd_value)),
+      direction,
+      counterparty: vec![String::from("0xrouter")],
+      account: String::from("0xwallet"),
+      chain: Chain::Arbitrum,
+    }
+  }
+
+  fn transactions() -> Vec<Transaction> {
+    let eth = Token::eth(Chain::Arbitrum);
+    let bought = leg("a", "2024-01-01 10:00:00", eth.clone(), 1, 2000, TransferDirection::Incoming);
+    let swap = vec![
+      leg("b", "2024-01-01 12:00:00", eth, 1, 1900, TransferDirection::Outgoing),
+      leg("b", "2024-01-01 12:00:00", usdc(), 1900, 1900, TransferDirection::Incoming),
+    ];
+    vec![
+      Transaction { transfer_id: String::from("a"), datetime: bought.datetime.clone(), net_transfers: vec![bought], ..Default::default() },
+      Transaction {
+        transfer_id: String::from("b"),
+        datetime: String::from("2024-01-01 12:00:00"),
+        category: TransactionCategory::Swap((&swap).into()),
+        net_transfers: swap,
+        ..Default::default()
+      },
+    ]
+  }
+
+  #[test]
+  fn swaps_are_not_measured_against_their_own_legs() {
+    let transactions = transactions();
+    let prices = reference_prices(&transactions, &transactions[1], &PriceStore::default());
+
+    let execution = execution(&transactions[1], &prices, &CategoryConfig::new(), Decimal::from(50)).unwrap();
+    assert_eq!(execution.reference_rate, Decimal::from(2000));
+    assert_eq!(execution.slippage_bps, Decimal::from(500));
+    assert!(execution.outlier);
+  }
+
+  #[test]
+  fn price_file_overrides_observed_prices() {
+    let transactions = transactions();
+    let mut file_prices = PriceStore::default();
+    file_prices.insert("ETH", NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), Decimal::from(1900));
+    let prices = reference_prices(&transactions, &transactions[1], &file_prices);
+
+    let execution = execution(&transactions[1], &prices, &CategoryConfig::new(), Decimal::from(50)).unwrap();
+    assert_eq!(execution.slippage_bps, Decimal::ZERO);
+    assert!(!execution.outlier);
+  }
+}
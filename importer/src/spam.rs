@@ -0,0 +1,129 @@
+//! Detection of spam and dust tokens.
+//!
+//! Arbitrum wallets receive tokens nobody asked for, mostly adverts for
+//! phishing sites or look-alikes of real tokens used to poison the address
+//! history. They carry no value but would otherwise show up as `Unknown`
+//! transactions and holdings. [`filter_spam`] runs after classification and
+//! marks them [`crate::TransactionCategory::Ignore`].
+//!
+//! Findings can be confirmed or denied with `spam = true` or `spam = false` on
+//! the contract's entry in `data/ref/tokens.toml`. Confirmed tokens are ignored
+//! whatever the category; the heuristics only apply to transactions the
+//! classifier could not make sense of, so a configured category still wins.
+//! Transactions categorised in `data/ref/adjustments.toml` are left alone.
+
+use crate::token::{lookup, read_token_config, TokenConfig};
+use crate::{Chain, SwapSubCategory, Transaction, TransactionCategory, Transfer, TransferDirection};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Fragments of the symbols of tokens advertising a website.
+const SUSPICIOUS: [&str; 7] = ["http", "www.", "t.me/", "t.ly/", "visit", "claim", "airdrop"];
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+/// Why a transaction was taken for spam.
+pub enum SpamReason {
+    /// The token is marked `spam = true` in `data/ref/tokens.toml`.
+    Confirmed,
+    /// The token is not registered, has no USD value and its symbol looks like
+    /// an advert or a look-alike.
+    SuspiciousSymbol,
+    /// The token is not registered, has no USD value and was only ever received.
+    Unsolicited,
+}
+
+impl fmt::Display for SpamReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      let reason = match self {
+        SpamReason::Confirmed => "confirmed in tokens.toml",
+        SpamReason::SuspiciousSymbol => "suspicious symbol",
+        SpamReason::Unsolicited => "unsolicited unknown token",
+      };
+      write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+/// Transfer of a transaction ignored as spam.
+pub struct SpamTransfer {
+  pub transfer_id: String,
+  pub datetime: String,
+  pub chain: Chain,
+  /// Contract address of the token.
+  pub address: String,
+  /// Symbol the token claims.
+  pub symbol: String,
+  pub reason: SpamReason,
+}
+
+/// Whether a symbol advertises a website, hides characters or imitates another
+/// token with look-alike letters.
+pub fn is_suspicious(symbol: &str) -> bool {
+    let lower = symbol.to_lowercase();
+    let is_domain = |word: &str| {
+      word.rsplit_once('.').is_some_and(|(name, tld)| {
+        !name.is_empty() && (2..=8).contains(&tld.len()) && tld.chars().all(|x| x.is_ascii_alphabetic())
+      })
+    };
+    !symbol.is_ascii()
+      || symbol.chars().any(|x| x.is_whitespace() || x == '*')
+      || SUSPICIOUS.iter().any(|x| lower.contains(x))
+      || lower.split(|x: char| !(x.is_ascii_alphanumeric() || x == '.' || x == '-')).any(is_domain)
+}
+
+/// Reason the transfers of a transaction are spam, if every one of them is.
+pub fn spam_reason(transfers: &[Transfer], config: &TokenConfig) -> Option<SpamReason> {
+    let only_received = transfers.iter().all(|x| x.direction == TransferDirection::Incoming);
+    let reasons: Option<Vec<SpamReason>> = transfers
+      .iter()
+      .map(|transfer| {
+        let meta = lookup(config, transfer.chain, &transfer.token.address);
+        match meta.and_then(|x| x.spam) {
+          Some(true) => return Some(SpamReason::Confirmed),
+          Some(false) => return None,
+          None => {},
+        }
+        let worthless = transfer.usd_value.is_none_or(|x| x.is_zero());
+        match (meta, worthless) {
+          (Some(_), _) | (_, false) => None,
+          _ if transfer.token.is_nft() => None,
+          _ if is_suspicious(&transfer.token.symbol) => Some(SpamReason::SuspiciousSymbol),
+          _ if only_received => Some(SpamReason::Unsolicited),
+          _ => None,
+        }
+      })
+      .collect();
+    reasons?.into_iter().next()
+}
+
+/// Marks transactions whose every transfer is spam as `Ignore` and returns
+/// their transfers with the reason. Transactions in `exempt` are skipped.
+pub fn filter_spam(transactions: &mut [Transaction], exempt: &HashSet<String>) -> Vec<SpamTransfer> {
+    let config = read_token_config();
+    let mut spam = vec![];
+    let candidates = transactions
+      .iter_mut()
+      .filter(|x| x.category != TransactionCategory::Ignore && !exempt.contains(&x.transfer_id));
+    for transaction in candidates {
+      let unclassified = matches!(
+        transaction.category,
+        TransactionCategory::Unknown | TransactionCategory::Swap(SwapSubCategory::UnknownSwap)
+      );
+      let reason = match spam_reason(&transaction.net_transfers, &config) {
+        Some(SpamReason::Confirmed) => SpamReason::Confirmed,
+        Some(reason) if unclassified => reason,
+        _ => continue,
+      };
+      transaction.category = TransactionCategory::Ignore;
+      spam.extend(transaction.net_transfers.iter().map(|x| SpamTransfer {
+        transfer_id: x.transfer_id.clone(),
+        datetime: x.datetime.clone(),
+        chain: x.chain,
+        address: x.token.address.to_lowercase(),
+        symbol: x.token.symbol.clone(),
+        reason,
+      }));
+    }
+    spam
+}
@@ -0,0 +1,181 @@
+//! Export of the normalized ledger into a local SQLite database for ad-hoc SQL.
+//!
+//! The database is rebuilt from scratch on every export. Amounts are stored as
+//! exact decimal TEXT; cast them (`CAST(value AS REAL)`) to aggregate.
+
+use crate::portfolio::Portfolio;
+use crate::{Chain, Token, Transaction};
+use rusqlite::{params, Connection, Transaction as SqlTransaction};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Schema of the exported database.
+const SCHEMA: &str = "
+CREATE TABLE categories (
+  name TEXT PRIMARY KEY
+);
+CREATE TABLE tokens (
+  id INTEGER PRIMARY KEY,
+  chain TEXT NOT NULL,
+  address TEXT NOT NULL,
+  token_id TEXT,
+  symbol TEXT NOT NULL,
+  asset TEXT NOT NULL,
+  stable_usd_value TEXT,
+  is_usd INTEGER NOT NULL,
+  is_debt INTEGER NOT NULL
+);
+CREATE TABLE transactions (
+  transfer_id TEXT PRIMARY KEY,
+  datetime TEXT NOT NULL,
+  category TEXT NOT NULL REFERENCES categories (name),
+  assets TEXT NOT NULL
+);
+CREATE TABLE transfers (
+  id INTEGER PRIMARY KEY,
+  transfer_id TEXT NOT NULL REFERENCES transactions (transfer_id),
+  datetime TEXT NOT NULL,
+  chain TEXT NOT NULL,
+  account TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  token INTEGER NOT NULL REFERENCES tokens (id),
+  value TEXT NOT NULL,
+  usd_value TEXT,
+  counterparty TEXT NOT NULL
+);
+CREATE TABLE lots (
+  id INTEGER PRIMARY KEY,
+  transfer_id TEXT REFERENCES transactions (transfer_id),
+  datetime TEXT,
+  token INTEGER NOT NULL REFERENCES tokens (id),
+  amount TEXT NOT NULL,
+  cost_basis TEXT NOT NULL
+);
+CREATE TABLE disposals (
+  id INTEGER PRIMARY KEY,
+  transfer_id TEXT NOT NULL REFERENCES transactions (transfer_id),
+  datetime TEXT NOT NULL,
+  token INTEGER NOT NULL REFERENCES tokens (id),
+  value TEXT NOT NULL,
+  sale_price TEXT NOT NULL,
+  cost_basis TEXT NOT NULL,
+  pnl TEXT NOT NULL
+);
+CREATE INDEX transfers_transfer_id ON transfers (transfer_id);
+CREATE INDEX transfers_token ON transfers (token);
+";
+
+/// Assigns row ids to tokens, inserting each distinct chain, address and token ID once.
+struct TokenIds(HashMap<(Chain, String, Option<String>), i64>);
+
+impl TokenIds {
+  fn get(&mut self, tx: &SqlTransaction, token: &Token) -> Result<i64, Box<dyn Error>> {
+    let key = (token.chain, token.address.to_lowercase(), token.token_id.clone());
+    if let Some(id) = self.0.get(&key) {
+      return Ok(*id);
+    }
+    tx.execute(
+      "INSERT INTO tokens (chain, address, token_id, symbol, asset, stable_usd_value, is_usd, is_debt)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+      params![
+        token.chain.name(),
+        token.address,
+        token.token_id,
+        token.symbol,
+        token.asset,
+        token.stable_usd_value.map(|x| x.to_string()),
+        token.is_usd,
+        token.is_debt,
+      ],
+    )?;
+    let id = tx.last_insert_rowid();
+    self.0.insert(key, id);
+    Ok(id)
+  }
+}
+
+/// Writes the transactions with their net transfers, and the open lots and
+/// disposals of `portfolio`, into the SQLite database at `file_path`.
+///
+/// The database is built next to `file_path` and moved into place once
+/// complete, replacing any previous export.
+pub fn write_sqlite(transactions: &[Transaction], portfolio: &Portfolio, file_path: &str) -> Result<(), Box<dyn Error>> {
+    let staging = format!("{}.tmp", file_path);
+    if Path::new(&staging).exists() {
+      fs::remove_file(&staging)?;
+    }
+
+    let mut conn = Connection::open(&staging)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    let mut tokens = TokenIds(HashMap::new());
+
+    for transaction in transactions {
+      let category = transaction.category.name();
+      tx.execute("INSERT OR IGNORE INTO categories (name) VALUES (?1)", params![category])?;
+      tx.execute(
+        "INSERT INTO transactions (transfer_id, datetime, category, assets) VALUES (?1, ?2, ?3, ?4)",
+        params![transaction.transfer_id, transaction.datetime, category, transaction.assets],
+      )?;
+
+      for transfer in &transaction.net_transfers {
+        let token = tokens.get(&tx, &transfer.token)?;
+        tx.execute(
+          "INSERT INTO transfers (transfer_id, datetime, chain, account, direction, token, value, usd_value, counterparty)
+           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+          params![
+            transaction.transfer_id,
+            transfer.datetime,
+            transfer.chain.name(),
+            transfer.account,
+            format!("{:?}", transfer.direction),
+            token,
+            transfer.value.to_string(),
+            transfer.usd_value.map(|x| x.to_string()),
+            transfer.counterparty.join(";"),
+          ],
+        )?;
+      }
+    }
+
+    for lot in portfolio.lots.values().flatten() {
+      let token = tokens.get(&tx, &lot.token)?;
+      let acquired = !lot.transfer_id.is_empty();
+      tx.execute(
+        "INSERT INTO lots (transfer_id, datetime, token, amount, cost_basis) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+          acquired.then_some(&lot.transfer_id),
+          acquired.then_some(&lot.datetime),
+          token,
+          lot.amount.to_string(),
+          lot.cost_basis.to_string(),
+        ],
+      )?;
+    }
+
+    for disposal in &portfolio.disposals {
+      let token = tokens.get(&tx, &disposal.token)?;
+      tx.execute(
+        "INSERT INTO disposals (transfer_id, datetime, token, value, sale_price, cost_basis, pnl)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+          disposal.transfer_id,
+          disposal.datetime,
+          token,
+          disposal.value.to_string(),
+          disposal.sale_price.to_string(),
+          disposal.cost_basis.to_string(),
+          disposal.pnl.to_string(),
+        ],
+      )?;
+    }
+
+    tx.commit()?;
+    conn.close().map_err(|(_, err)| err)?;
+    fs::rename(&staging, file_path)?;
+    Ok(())
+}
@@ -0,0 +1,347 @@
+//! Persistent incremental ledger kept in an embedded SQLite file.
+//!
+//! Ingested transfers are remembered by `(source, hash, leg, position)`, where
+//! `leg` describes what moved and `position` counts identical legs within the
+//! transaction, so re-reading an export that grew only stores the new rows
+//! while genuinely repeated transfers are kept. Each source is stamped with the
+//! size and modification time of its files and is only parsed again once they
+//! change. The classified category of every transaction is cached together
+//! with a fingerprint of its legs and a snapshot of `data/ref/categories.toml`;
+//! a transaction is only classified again when its legs or one of its
+//! configuration keys changed. Editing `data/ref/tokens.toml` invalidates the
+//! stamps and the cached categories, so every source is parsed again and its
+//! stored rows take the new token metadata.
+
+use crate::category::{category_keys, classify, configured_category, CategoryConfig};
+use crate::dedup::Sources;
+use crate::token::TOKEN_FILE;
+use crate::{Chain, Token, Transaction, TransactionCategory, Transfer, TransferDirection};
+use itertools::Itertools;
+use rusqlite::{params, Connection, OptionalExtension};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// Schema of the store, created when missing.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transfers (
+  source TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  datetime TEXT NOT NULL,
+  chain TEXT NOT NULL,
+  account TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  token_address TEXT NOT NULL,
+  token_id TEXT,
+  token_symbol TEXT NOT NULL,
+  token_asset TEXT NOT NULL,
+  stable_usd_value TEXT,
+  is_usd INTEGER NOT NULL,
+  is_debt INTEGER NOT NULL,
+  value TEXT NOT NULL,
+  usd_value TEXT,
+  counterparty TEXT NOT NULL,
+  leg TEXT NOT NULL,
+  PRIMARY KEY (source, hash, leg, position)
+);
+CREATE TABLE IF NOT EXISTS sources (
+  source TEXT PRIMARY KEY,
+  stamp TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS classified (
+  transfer_id TEXT PRIMARY KEY,
+  fingerprint TEXT NOT NULL,
+  category TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS category_config (
+  key TEXT PRIMARY KEY,
+  category TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS token_config (
+  contents TEXT NOT NULL
+);
+";
+
+/// Identifies the legs of a transaction and the values they carry, so cached
+/// categories of transactions that gained, lost or revalued legs are discarded.
+fn fingerprint(transfers: &[Transfer]) -> String {
+  transfers
+    .iter()
+    .map(|x| format!(
+      "{}:{}:{}:{:?}:{}:{}",
+      x.account,
+      x.token.chain,
+      x.token.address.to_lowercase(),
+      x.direction,
+      x.value,
+      x.usd_value.map(|x| x.to_string()).unwrap_or_default(),
+    ))
+    .sorted()
+    .join("|")
+}
+
+/// Describes what a single transfer moved, independently of file order.
+fn leg(transfer: &Transfer) -> String {
+  format!(
+    "{}:{}:{}:{:?}:{}:{}:{}",
+    transfer.chain,
+    transfer.token.address.to_lowercase(),
+    transfer.token.token_id.clone().unwrap_or_default(),
+    transfer.direction,
+    transfer.value,
+    transfer.account,
+    transfer.counterparty.join(";"),
+  )
+}
+
+/// Size and modification time of a source: a file, or the files directly in a
+/// directory. Empty when the source does not exist.
+pub fn stamp(source: &str) -> Result<String, Box<dyn Error>> {
+  let file_stamp = |path: &Path| -> Result<String, Box<dyn Error>> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok(format!("{}:{}", metadata.len(), modified))
+  };
+
+  let path = Path::new(source);
+  if path.is_file() {
+    return file_stamp(path);
+  }
+  if !path.is_dir() {
+    return Ok(String::new());
+  }
+
+  let mut files = vec![];
+  for entry in fs::read_dir(path)? {
+    let file_path = entry?.path();
+    if file_path.is_file() {
+      files.push(format!("{}={}", file_path.to_string_lossy(), file_stamp(&file_path)?));
+    }
+  }
+  Ok(files.into_iter().sorted().join("|"))
+}
+
+/// Handle on the store file.
+pub struct Store {
+  conn: Connection,
+}
+
+impl Store {
+  /// Opens the store at `file_path`, creating it when missing. When
+  /// `data/ref/tokens.toml` changed since the last run, every source stamp and
+  /// cached category is dropped.
+  pub fn open(file_path: &str) -> Result<Self, Box<dyn Error>> {
+    let mut conn = Connection::open(file_path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tokens = fs::read_to_string(TOKEN_FILE)?;
+    let tx = conn.transaction()?;
+    let snapshot: Option<String> = tx.query_row("SELECT contents FROM token_config", [], |row| row.get(0)).optional()?;
+    if snapshot.as_ref() != Some(&tokens) {
+      tx.execute("DELETE FROM sources", [])?;
+      tx.execute("DELETE FROM classified", [])?;
+      tx.execute("DELETE FROM token_config", [])?;
+      tx.execute("INSERT INTO token_config (contents) VALUES (?1)", params![tokens])?;
+    }
+    tx.commit()?;
+    Ok(Self { conn })
+  }
+
+  /// Whether `source` was last ingested with the same [`stamp`], so reading it
+  /// again would yield nothing new.
+  pub fn is_current(&self, source: &str, stamp: &str) -> Result<bool, Box<dyn Error>> {
+    let stored: Option<String> = self
+      .conn
+      .query_row("SELECT stamp FROM sources WHERE source = ?1", params![source], |row| row.get(0))
+      .optional()?;
+    Ok(stored.as_deref() == Some(stamp))
+  }
+
+  /// Stores the transfers read from `source` (e.g. an export file) when its
+  /// files had the given `stamp`, and returns how many of them were not
+  /// already known. Known transfers take the token metadata and USD value
+  /// just read.
+  pub fn ingest(&mut self, source: &str, stamp: &str, transfers: Vec<Transfer>) -> Result<usize, Box<dyn Error>> {
+    let tx = self.conn.transaction()?;
+    let count = |tx: &rusqlite::Transaction| -> rusqlite::Result<usize> {
+      tx.query_row("SELECT COUNT(*) FROM transfers WHERE source = ?1", params![source], |row| row.get(0))
+    };
+    let known = count(&tx)?;
+
+    let mut positions: HashMap<(String, String), i64> = HashMap::new();
+    for transfer in transfers {
+      let leg = leg(&transfer);
+      let position = positions.entry((transfer.transfer_id.clone(), leg.clone())).or_default();
+      tx.execute(
+        "INSERT INTO transfers (
+           source, hash, leg, position, datetime, chain, account, direction, token_address, token_id,
+           token_symbol, token_asset, stable_usd_value, is_usd, is_debt, value, usd_value, counterparty
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)
+         ON CONFLICT (source, hash, leg, position) DO UPDATE SET
+           token_symbol = excluded.token_symbol,
+           token_asset = excluded.token_asset,
+           stable_usd_value = excluded.stable_usd_value,
+           is_usd = excluded.is_usd,
+           is_debt = excluded.is_debt,
+           usd_value = excluded.usd_value",
+        params![
+          source,
+          transfer.transfer_id,
+          leg,
+          *position,
+          transfer.datetime,
+          transfer.chain.name(),
+          transfer.account,
+          format!("{:?}", transfer.direction),
+          transfer.token.address,
+          transfer.token.token_id,
+          transfer.token.symbol,
+          transfer.token.asset,
+          transfer.token.stable_usd_value.map(|x| x.to_string()),
+          transfer.token.is_usd,
+          transfer.token.is_debt,
+          transfer.value.to_string(),
+          transfer.usd_value.map(|x| x.to_string()),
+          transfer.counterparty.join(";"),
+        ],
+      )?;
+      *position += 1;
+    }
+
+    let added = count(&tx)? - known;
+    tx.execute(
+      "INSERT OR REPLACE INTO sources (source, stamp) VALUES (?1, ?2)",
+      params![source, stamp],
+    )?;
+    tx.commit()?;
+    Ok(added)
+  }
+
+  /// Every transfer stored so far grouped by source, in ingestion order.
+  pub fn sources(&self) -> Result<Sources, Box<dyn Error>> {
+    let mut statement = self.conn.prepare(
+      "SELECT source, hash, datetime, chain, account, direction, token_address, token_id, token_symbol,
+              token_asset, stable_usd_value, is_usd, is_debt, value, usd_value, counterparty
+       FROM transfers ORDER BY rowid",
+    )?;
+
+    let rows = statement.query_map([], |row| {
+      Ok((
+        row.get::<_, String>(0)?,
+        (row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?, row.get::<_, String>(4)?),
+        (row.get::<_, String>(5)?, row.get::<_, String>(6)?, row.get::<_, Option<String>>(7)?, row.get::<_, String>(8)?),
+        (row.get::<_, String>(9)?, row.get::<_, Option<String>>(10)?, row.get::<_, bool>(11)?, row.get::<_, bool>(12)?),
+        (row.get::<_, String>(13)?, row.get::<_, Option<String>>(14)?, row.get::<_, String>(15)?),
+      ))
+    })?;
+
+    let mut sources: Sources = vec![];
+    for row in rows {
+      let (
+        source,
+        (hash, datetime, chain, account),
+        (direction, address, token_id, symbol),
+        (asset, stable_usd_value, is_usd, is_debt),
+        (value, usd_value, counterparty),
+      ) = row?;
+      let chain = Chain::from_str(&chain)?;
+
+      let transfer = Transfer {
+        transfer_id: hash,
+        datetime,
+        token: Token {
+          asset,
+          symbol,
+          stable_usd_value: stable_usd_value.map(|x| Decimal::from_str(&x)).transpose()?,
+          address,
+          chain,
+          is_usd,
+          is_debt,
+          token_id,
+        },
+        value: Decimal::from_str(&value)?,
+        usd_value: usd_value.map(|x| Decimal::from_str(&x)).transpose()?,
+        direction: match direction.as_str() {
+          "Incoming" => TransferDirection::Incoming,
+          _ => TransferDirection::Outgoing,
+        },
+        counterparty: counterparty.split(';').filter(|x| !x.is_empty()).map(String::from).collect(),
+        account,
+        chain,
+      };
+
+      match sources.iter_mut().find(|(name, _)| *name == source) {
+        Some((_, transfers)) => transfers.push(transfer),
+        None => sources.push((source, vec![transfer])),
+      }
+    }
+    Ok(sources)
+  }
+
+  /// Classifies grouped transactions with `config` (`data/ref/categories.toml`
+  /// and the category adjustments), reusing the cached category unless the
+  /// transaction's legs changed or one of its keys was added, removed or
+  /// recategorised since the last run. Returns the classified transactions and
+  /// how many were classified again.
+  pub fn classify(&mut self, transactions: Vec<Transaction>, config: &CategoryConfig) -> Result<(Vec<Transaction>, usize), Box<dyn Error>> {
+    let tx = self.conn.transaction()?;
+
+    let snapshot: HashMap<String, String> = tx
+      .prepare("SELECT key, category FROM category_config")?
+      .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+      .collect::<Result<_, _>>()?;
+    let changed: HashSet<&String> = config
+      .keys()
+      .chain(snapshot.keys())
+      .filter(|key| config.get(*key).map(|x| &x.category) != snapshot.get(*key))
+      .collect();
+    let cache: HashMap<String, (String, String)> = tx
+      .prepare("SELECT transfer_id, fingerprint, category FROM classified")?
+      .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+      .collect::<Result<_, _>>()?;
+
+    let mut reclassified = 0;
+    let mut classified = vec![];
+    for mut transaction in transactions {
+      let fingerprint = fingerprint(&transaction.net_transfers);
+      let cached = cache
+        .get(&transaction.transfer_id)
+        .filter(|(cached_fingerprint, _)| *cached_fingerprint == fingerprint)
+        .filter(|_| !category_keys(&transaction.net_transfers).iter().any(|x| changed.contains(x)))
+        .and_then(|(_, name)| TransactionCategory::from_name(name, &transaction.net_transfers));
+
+      transaction.category = match cached {
+        Some(category) => category,
+        None => {
+          let name = configured_category(&transaction.net_transfers, config);
+          let category = classify(name.as_deref(), &transaction.net_transfers);
+          tx.execute(
+            "INSERT OR REPLACE INTO classified (transfer_id, fingerprint, category) VALUES (?1, ?2, ?3)",
+            params![transaction.transfer_id, fingerprint, category.name()],
+          )?;
+          reclassified += 1;
+          category
+        },
+      };
+      classified.push(transaction);
+    }
+
+    if !changed.is_empty() {
+      tx.execute("DELETE FROM category_config", [])?;
+      for (key, mapping) in config {
+        tx.execute(
+          "INSERT INTO category_config (key, category) VALUES (?1, ?2)",
+          params![key, mapping.category],
+        )?;
+      }
+    }
+    tx.commit()?;
+
+    Ok((classified, reclassified))
+  }
+}
@@ -1,7 +1,7 @@
 //! Helpers for enriching raw token addresses with human readable metadata.
 
-use crate::Token;
-use std::collections::HashMap;
+use crate::{Chain, Token};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 use std::str::FromStr;
@@ -10,8 +10,9 @@ use rust_decimal::Decimal;
 use serde::Deserialize;
 
 /// Mapping of token contract addresses to associated metadata loaded from
-/// `data/ref/tokens.toml`.
-type TokenConfig = HashMap<String, TokenMeta>;
+/// `data/ref/tokens.toml`. Keys are `"<chain>:<address>"`; a bare address
+/// refers to Arbitrum.
+pub type TokenConfig = HashMap<String, TokenMeta>;
 
 #[derive(Debug, Deserialize, Clone)]
 /// Raw token metadata read from configuration.
@@ -23,6 +24,38 @@ pub struct TokenMeta {
   pub is_debt: bool,
   /// Optional USD value of a single token at import time.
   pub stable_usd_value: Option<String>,
+  /// Number of decimals used by the contract's raw integer amounts.
+  pub decimals: Option<u32>,
+  /// Open lots of airdrops and other income in this asset at zero cost
+  /// instead of their fair market value.
+  #[serde(default)]
+  pub zero_basis: bool,
+  /// Review of the spam filter: `true` ignores every transaction moving only
+  /// this token, `false` never takes it for spam.
+  pub spam: Option<bool>,
+}
+
+/// Path of the token registry.
+pub const TOKEN_FILE: &str = "data/ref/tokens.toml";
+
+/// Loads the token registry from `data/ref/tokens.toml`.
+pub fn read_token_config() -> TokenConfig {
+    let path = Path::new(TOKEN_FILE);
+    let toml_str = fs::read_to_string(path).unwrap();
+    toml::de::from_str(&toml_str).unwrap()
+}
+
+/// Underlying assets configured with `zero_basis`.
+pub fn zero_basis_assets(config: &TokenConfig) -> HashSet<String> {
+    config.values().filter(|x| x.zero_basis).map(|x| x.asset.clone()).collect()
+}
+
+/// Looks up the metadata of `address` on `chain`, accepting bare keys for Arbitrum.
+pub fn lookup<'a>(config: &'a TokenConfig, chain: Chain, address: &str) -> Option<&'a TokenMeta> {
+    let address = address.to_lowercase();
+    config
+      .get(&format!("{}:{}", chain, address))
+      .or_else(|| (chain == Chain::Arbitrum).then(|| config.get(&address)).flatten())
 }
 
 impl Default for Token {
@@ -32,37 +65,78 @@ impl Default for Token {
         asset: String::from("Unknown"),
         symbol: String::from("Unknown"),
         address: String::from("Unknown"),
+        chain: Chain::default(),
         stable_usd_value: None,
         is_usd: false,
         is_debt: false,
+        token_id: None,
+      }
+    }
+}
+
+impl Token {
+    /// Whether the token resolved to an asset rather than the `Unknown` placeholder.
+    pub fn is_known(&self) -> bool {
+      self.asset != Token::default().asset
+    }
+
+    /// Whether the token is a single NFT rather than a fungible amount.
+    pub fn is_nft(&self) -> bool {
+      self.token_id.is_some()
+    }
+
+    /// Key under which lots of this token are pooled: the underlying asset for
+    /// fungible tokens, the chain, contract and token ID for NFTs.
+    pub fn lot_key(&self) -> String {
+      match &self.token_id {
+        Some(token_id) => format!("{}:{}#{}", self.chain, self.address, token_id),
+        None => self.asset.clone(),
+      }
+    }
+
+    /// Native ETH on `chain`, which has no contract address.
+    pub fn eth(chain: Chain) -> Self {
+      Self {
+        asset: String::from("ETH"),
+        symbol: String::from("ETH"),
+        address: String::from("ETH"),
+        chain,
+        ..Default::default()
       }
     }
 }
 
 impl From<&String> for Token {
-    /// Attempts to construct a [`Token`] from a contract address by looking up
-    /// metadata in `data/ref/tokens.toml`.
+    /// Attempts to construct an Arbitrum [`Token`] from a contract address.
     fn from(address: &String) -> Self {
+      (Chain::Arbitrum, address).into()
+    }
+}
+
+impl From<(Chain, &String)> for Token {
+    /// Attempts to construct a [`Token`] from a contract address on `chain` by
+    /// looking up metadata in `data/ref/tokens.toml`.
+    fn from((chain, address): (Chain, &String)) -> Self {
       // Load token metadata from the TOML file
-      let path = Path::new("data/ref/tokens.toml");
-      let toml_str = fs::read_to_string(path).unwrap();
-      let config: TokenConfig = toml::de::from_str(&toml_str).unwrap();
+      let config = read_token_config();
 
-      // Iterate over the addresses and check for matches
-      match config.get(&address.to_lowercase()) {
+      match lookup(&config, chain, address) {
         Some(meta) => Token {
           asset: meta.asset.clone(),
           symbol: meta.symbol.clone(),
           address: address.to_string(),
+          chain,
           stable_usd_value: meta
             .stable_usd_value
             .as_ref()
             .map(|x| Decimal::from_str(x).unwrap()),
           is_usd: meta.stable_usd_value.is_some(),
           is_debt: meta.is_debt,
+          token_id: None,
         },
         None => Token {
           address: address.to_string(),
+          chain,
           ..Default::default()
         },
       }
@@ -1,9 +1,11 @@
 //! Conversion logic for grouping raw [`Transfer`]s into higher level [`Transaction`] records.
 
 use crate::{TransferDirection, Transaction, Transfer};
+use crate::category::{classify, configured_category, read_category_config, CategoryConfig};
 use std::collections::HashMap;
 use std::ops::Add;
 use rust_decimal::Decimal;
+use itertools::Itertools;
 
 /// Convert intermediate types into a collection of [`Transaction`]s.
 pub trait ToTransaction {
@@ -17,58 +19,46 @@ impl Add for Transfer {
     fn add(self, other: Transfer) -> Option<Transfer> {
       match (&self.direction, &other.direction) {
         (TransferDirection::Incoming, TransferDirection::Incoming) => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| x+y)),
           direction: TransferDirection::Incoming,
+          ..self
         }),
         (TransferDirection::Outgoing, TransferDirection::Outgoing) => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| x+y)),
           direction: TransferDirection::Outgoing,
+          ..self
         }),
         (TransferDirection::Incoming, TransferDirection::Outgoing) if self.value > other.value => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| x-y)),
           direction: TransferDirection::Incoming,
+          ..self
         }),
         (TransferDirection::Outgoing, TransferDirection::Incoming) if self.value > other.value => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| x-y)),
           direction: TransferDirection::Outgoing,
+          ..self
         }),
         (TransferDirection::Incoming, TransferDirection::Outgoing) if self.value < other.value => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| y-x)),
           direction: TransferDirection::Outgoing,
+          ..self
         }),
         (TransferDirection::Outgoing, TransferDirection::Incoming) if self.value < other.value => Some(Transfer {
-          transfer_id: self.transfer_id,
-          datetime: self.datetime,
-          token: self.token,
           counterparty: self.counterparty.into_iter().chain(other.counterparty).collect(),
           value: self.value + other.value,
           usd_value: self.usd_value.and_then(|x| other.usd_value.map(|y| y-x)),
           direction: TransferDirection::Incoming,
+          ..self
         }),
         _ => None,
       }
@@ -114,28 +104,50 @@ impl Add<Transfer> for Transaction {
     }
 }
 
-/// Groups a list of [`Transfer`]s by their identifier to build [`Transaction`]s.
-impl ToTransaction for Vec<Transfer> {
-    fn to_transaction(self) -> Vec<Transaction> {
-      let mut transaction_map: HashMap<String, Transaction> = HashMap::new();
+/// Groups a list of [`Transfer`]s by their identifier into unclassified
+/// [`Transaction`]s, ordered by datetime and identifier.
+pub fn group_transfers(transfers: Vec<Transfer>) -> Vec<Transaction> {
+    let mut transaction_map: HashMap<String, Transaction> = HashMap::new();
 
-      for transfer in self {
-          let current = transaction_map
-            .entry(transfer.transfer_id.clone())
-            .or_default();
-          *current = current.clone() + transfer;
-      }
+    for transfer in transfers {
+        let current = transaction_map
+          .entry(transfer.transfer_id.clone())
+          .or_default();
+        *current = current.clone() + transfer;
+    }
+
+    transaction_map
+        .into_values()
+        .map(|mut transaction| {
+            transaction.net_transfers.retain(|t| {
+                t.value != Decimal::ZERO
+            });
+            transaction.assets = transaction.net_transfers.iter().map(|x| x.token.symbol.clone()).collect::<Vec<String>>().join("|");
+            transaction
+        })
+        .sorted_by(|a, b| (&a.datetime, &a.transfer_id).cmp(&(&b.datetime, &b.transfer_id)))
+        .collect()
+}
+
+/// Classifies grouped transactions with the categories configured in `config`,
+/// falling back to the heuristics.
+pub fn classify_transactions(transactions: Vec<Transaction>, config: &CategoryConfig) -> Vec<Transaction> {
+    transactions
+      .into_iter()
+      .map(|mut transaction| {
+          transaction.category = classify(
+            configured_category(&transaction.net_transfers, config).as_deref(),
+            &transaction.net_transfers,
+          );
+          transaction
+      })
+      .collect()
+}
 
-      transaction_map
-          .into_values()
-          .map(|mut transaction| {
-              transaction.net_transfers.retain(|t| {
-                  t.value != Decimal::ZERO
-              });
-              transaction.assets = transaction.net_transfers.iter().map(|x| x.token.symbol.clone()).collect::<Vec<String>>().join("|");
-              transaction.category = (&transaction.net_transfers).into();
-              transaction
-          })
-          .collect()
+/// Groups a list of [`Transfer`]s by their identifier to build classified
+/// [`Transaction`]s, ordered by datetime and identifier.
+impl ToTransaction for Vec<Transfer> {
+    fn to_transaction(self) -> Vec<Transaction> {
+      classify_transactions(group_transfers(self), &read_category_config())
     }
 }
@@ -1,6 +1,6 @@
 //! Core data structures shared across the importer.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 
 #[derive(Default, Debug, Serialize, PartialEq, Clone)]
@@ -11,6 +11,23 @@ pub struct PortfolioItem {
   pub amount: Decimal,
 }
 
+#[derive(Default, Debug, Serialize, PartialEq, Clone)]
+/// Quantity of a token acquired in a single transaction together with its USD cost basis.
+pub struct Lot {
+  /// Identifier of the transaction that acquired the lot.
+  pub transfer_id: String,
+  /// ISO8601 timestamp of the acquisition.
+  pub datetime: String,
+  /// Token acquired.
+  pub token: Token,
+  /// Remaining amount of the token in the lot.
+  pub amount: Decimal,
+  /// USD cost basis of the remaining amount.
+  pub cost_basis: Decimal,
+}
+
+#[derive(Debug, Serialize, Clone)]
+/// Disposal of a token, matched against the lots it consumed.
 pub struct CostBasisTransfer {
   /// Identifier shared across the underlying transfers.
   pub transfer_id: String,
@@ -23,7 +40,30 @@ pub struct CostBasisTransfer {
 
   pub sale_price: Decimal,
   pub cost_basis: Decimal,
-  pub PnL: Decimal,
+  pub pnl: Decimal,
+  /// Lot slices consumed, oldest first.
+  #[serde(skip_serializing)]
+  pub lots: Vec<Lot>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+/// Tokens received as income, valued at their fair market value on receipt.
+pub struct Income {
+  /// Identifier shared across the underlying transfers.
+  pub transfer_id: String,
+  /// ISO8601 timestamp of the transfer.
+  pub datetime: String,
+  /// `Airdrop` or `Income`.
+  pub category: TransactionCategory,
+  /// Token received.
+  pub token: Token,
+  /// Amount received.
+  pub value: Decimal,
+  /// USD value on receipt, `None` when no price is known.
+  pub fair_value: Option<Decimal>,
+  /// Cost basis of the lot opened: the fair value, or zero for assets
+  /// configured with `zero_basis`.
+  pub cost_basis: Decimal,
 }
 
 #[derive(Default, Debug, Serialize, PartialEq, Clone)]
@@ -83,6 +123,7 @@ pub enum SwapSubCategory {
 }
 
 #[derive(Default, Debug, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)]
 /// High level category describing the nature of a [`Transaction`].
 pub enum TransactionCategory {
   /// Automated market-maker swap (e.g. AAVE).
@@ -91,8 +132,12 @@ pub enum TransactionCategory {
   Trade,
   /// Simple transfer of value between accounts.
   Transfer,
-  /// Airdropped tokens received without cost.
+  /// Movement of an asset from one chain to another through a bridge.
+  Bridge,
+  /// Airdropped tokens, income at their fair market value on receipt.
   Airdrop,
+  /// Rewards, interest and other income received.
+  Income,
   /// Activity that should be ignored in reports.
   Ignore,
   /// Unknown or unclassified activity.
@@ -115,8 +160,26 @@ pub struct Transaction {
   pub assets: String,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+/// Network a token lives on or a transfer was observed on.
+pub enum Chain {
+  /// Arbitrum One, the default for unqualified addresses.
+  #[default]
+  Arbitrum,
+  /// Ethereum mainnet.
+  Ethereum,
+  /// OP Mainnet.
+  Optimism,
+  /// Base mainnet.
+  Base,
+  /// Exchange accounts and other ledgers kept outside any chain.
+  Offchain,
+}
+
 #[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
-/// Normalized representation of an ERC-20 token.
+/// Normalized representation of an ERC-20 token, or of a single ERC-721/1155
+/// token when `token_id` is set.
 pub struct Token {
   /// Human readable name of the asset.
   pub asset: String,
@@ -127,21 +190,50 @@ pub struct Token {
   /// Contract address for the token.
   #[serde(skip_serializing)]
   pub address: String,
+  /// Chain the contract is deployed on; the same address on two chains is two tokens.
+  #[serde(skip_serializing)]
+  pub chain: Chain,
   #[serde(skip_serializing)]
   pub is_usd: bool,
   #[serde(skip_serializing)]
   pub is_debt: bool,
+  /// Token ID within an NFT contract; `None` for fungible tokens.
+  pub token_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Clone, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 /// Direction of value movement relative to the observed account.
 pub enum TransferDirection {
     /// Tokens moving into the account.
+    #[default]
     Incoming,
     /// Tokens moving out of the account.
     Outgoing,
 }
 
+#[derive(Debug, Serialize, Clone)]
+/// Network fee paid by one of our accounts for a transaction it sent.
+pub struct Fee {
+  /// Hash of the transaction.
+  pub transfer_id: String,
+  /// ISO8601 timestamp of the transaction.
+  pub datetime: String,
+  /// Chain the transaction was sent on.
+  pub chain: Chain,
+  /// Account that sent the transaction and paid the fee.
+  pub account: String,
+  /// Contract or address the transaction was sent to.
+  pub counterparty: String,
+  /// Native token the fee was paid in.
+  pub token: Token,
+  /// Amount of the native token paid as fee.
+  pub value: Decimal,
+  /// USD value of the fee at the time of the transaction.
+  pub usd_value: Option<Decimal>,
+  /// Native amount sent along with the call, e.g. a GMX execution fee deposit.
+  pub value_sent: Decimal,
+}
+
 #[derive(Debug, Serialize, Clone)]
 /// Individual movement of a token value between two addresses.
 pub struct Transfer {
@@ -160,5 +252,9 @@ pub struct Transfer {
   /// Address on the other side of the transfer relative to the observed account.
   #[serde(skip_serializing)]
   pub counterparty: Vec<String>,
+  /// Account the transfer was observed on: a wallet address or an exchange name.
+  pub account: String,
+  /// Chain the transfer was observed on.
+  pub chain: Chain,
 }
 